@@ -1,9 +1,47 @@
 fn main() {
     linker_be_nice();
+    git_hash();
+    check_ota_public_key();
     // make sure linkall.x is the last linker script (otherwise might cause problems with flip-link)
     println!("cargo:rustc-link-arg=-Tlinkall.x");
 }
 
+/// Expose the short commit hash as `GIT_HASH` for the app descriptor version.
+fn git_hash() {
+    let hash = std::process::Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=GIT_HASH={hash}");
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
+
+/// OTA only installs images signed with the key in `OTA_PUBLIC_KEY`; fail the
+/// build instead of shipping an OTA-enabled firmware that can't verify them.
+fn check_ota_public_key() {
+    println!("cargo:rerun-if-env-changed=OTA_MANIFEST_URL");
+    println!("cargo:rerun-if-env-changed=OTA_PUBLIC_KEY");
+    if std::env::var("OTA_MANIFEST_URL")
+        .unwrap_or_default()
+        .is_empty()
+    {
+        return;
+    }
+    let key = std::env::var("OTA_PUBLIC_KEY").unwrap_or_default();
+    if key.len() != 64 || !key.chars().all(|c| c.is_ascii_hexdigit()) {
+        panic!(
+            "OTA_MANIFEST_URL is set but OTA_PUBLIC_KEY is not a hex-encoded Ed25519 public key; \
+            create one with `cargo run -- keygen <key-file>` in tools/ota-sign"
+        );
+    }
+}
+
 fn linker_be_nice() {
     let args: Vec<String> = std::env::args().collect();
     if args.len() > 1 {
@@ -0,0 +1,140 @@
+//! Climate metrics derived from air temperature and relative humidity
+//!
+//! The saturation vapour pressure follows the Magnus formula with the
+//! coefficients of Sonntag (1990) over water, good to about 0.1 % between
+//! -45 °C and 60 °C. The vapour pressure deficit is the air's, not the
+//! leaf's: leaves under strong light run warmer than the air.
+
+use libm::{expf, logf, roundf};
+
+use crate::domain::{Hundredths, Tenths};
+
+const MAGNUS_HPA: f32 = 6.112;
+const MAGNUS_B: f32 = 17.62;
+const MAGNUS_C: f32 = 243.12;
+/// Water vapour density (g/m³) per hPa of vapour pressure, times the
+/// temperature in K: 100 Pa/hPa × 1000 g/kg / 461.5 J/(kg·K)
+const VAPOUR_DENSITY: f32 = 216.7;
+const ZERO_CELSIUS_K: f32 = 273.15;
+
+/// Air temperature and humidity as measured
+#[derive(Debug, Clone, Copy)]
+pub struct Climate {
+    /// °C
+    temperature: f32,
+    /// % relative humidity
+    humidity: f32,
+}
+
+impl Climate {
+    pub fn new(temperature: Tenths, humidity: Tenths) -> Self {
+        Self {
+            temperature: f32::from(temperature.0) / 10.0,
+            humidity: f32::from(humidity.0).clamp(0.0, 1000.0) / 10.0,
+        }
+    }
+
+    /// Dew point in °C, or `None` in completely dry air
+    pub fn dew_point(&self) -> Option<Tenths> {
+        if self.humidity <= 0.0 {
+            return None;
+        }
+        let gamma = logf(self.humidity / 100.0) + self.magnus_exponent();
+        Some(tenths(MAGNUS_C * gamma / (MAGNUS_B - gamma)))
+    }
+
+    /// Water vapour per volume of air in g/m³
+    pub fn absolute_humidity(&self) -> Tenths {
+        tenths(VAPOUR_DENSITY * self.vapour_pressure() / (self.temperature + ZERO_CELSIUS_K))
+    }
+
+    /// Vapour pressure deficit in kPa: how much more water the air could hold
+    pub fn vapour_pressure_deficit(&self) -> Hundredths {
+        let deficit_hpa = self.saturation_vapour_pressure() - self.vapour_pressure();
+        Hundredths(roundf(deficit_hpa * 10.0) as i16)
+    }
+
+    /// Saturation vapour pressure in hPa
+    fn saturation_vapour_pressure(&self) -> f32 {
+        MAGNUS_HPA * expf(self.magnus_exponent())
+    }
+
+    /// Vapour pressure in hPa
+    fn vapour_pressure(&self) -> f32 {
+        self.saturation_vapour_pressure() * self.humidity / 100.0
+    }
+
+    fn magnus_exponent(&self) -> f32 {
+        MAGNUS_B * self.temperature / (MAGNUS_C + self.temperature)
+    }
+}
+
+fn tenths(value: f32) -> Tenths {
+    Tenths(roundf(value * 10.0) as i16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics(temperature: i16, humidity: i16) -> (Option<Tenths>, Tenths, Hundredths) {
+        let climate = Climate::new(Tenths(temperature), Tenths(humidity));
+        (
+            climate.dew_point(),
+            climate.absolute_humidity(),
+            climate.vapour_pressure_deficit(),
+        )
+    }
+
+    #[test]
+    fn metrics_match_the_reference_values() {
+        // Dew point 13.85 °C, 11.48 g/m³, 1.58 kPa
+        assert_eq!(
+            metrics(250, 500),
+            (Some(Tenths(139)), Tenths(115), Hundredths(158))
+        );
+        assert_eq!(
+            metrics(250, 600),
+            (Some(Tenths(167)), Tenths(138), Hundredths(126))
+        );
+        assert_eq!(
+            metrics(200, 500),
+            (Some(Tenths(93)), Tenths(86), Hundredths(117))
+        );
+        assert_eq!(
+            metrics(300, 800),
+            (Some(Tenths(262)), Tenths(242), Hundredths(85))
+        );
+        assert_eq!(
+            metrics(215, 483),
+            (Some(Tenths(101)), Tenths(91), Hundredths(132))
+        );
+    }
+
+    #[test]
+    fn saturated_air_has_no_deficit() {
+        assert_eq!(
+            metrics(250, 1000),
+            (Some(Tenths(250)), Tenths(230), Hundredths(0))
+        );
+    }
+
+    #[test]
+    fn below_freezing() {
+        assert_eq!(
+            metrics(-50, 900),
+            (Some(Tenths(-64)), Tenths(31), Hundredths(4))
+        );
+    }
+
+    #[test]
+    fn dry_air_has_no_dew_point() {
+        assert_eq!(metrics(250, 0), (None, Tenths(0), Hundredths(316)));
+    }
+
+    #[test]
+    fn humidity_is_clamped_to_saturation() {
+        assert_eq!(metrics(250, 1015), metrics(250, 1000));
+        assert_eq!(metrics(250, -10), metrics(250, 0));
+    }
+}
@@ -0,0 +1,151 @@
+//! Wall clock kept in the RTC timer
+//!
+//! The RTC timer keeps counting through deep sleep, so once SNTP has set it
+//! the time stays known on later wakes; only a power-on loses it. Until then
+//! it counts from power-on. The clock is synced on the first wake without a
+//! time and then every `CLOCK_SYNC_INTERVAL_WAKES` wakes, which corrects the
+//! drift of the RTC oscillator.
+
+use embassy_net::{
+    IpAddress, Stack,
+    dns::{DnsQueryType, Error as DnsError},
+    udp::{BindError, PacketMetadata, RecvError, SendError, UdpSocket},
+};
+use log::info;
+
+use crate::{
+    config::{CLOCK_SYNC_INTERVAL_WAKES, NTP_SERVER, UTC_OFFSET_MINUTES},
+    watchdog,
+};
+
+const NTP_PORT: u16 = 123;
+const NTP_PACKET_LEN: usize = 48;
+/// Leap indicator 0, version 4, client mode
+const NTP_CLIENT_REQUEST: u8 = 0b0010_0011;
+const NTP_SERVER_MODE: u8 = 4;
+/// Seconds from the NTP epoch (1900) to the Unix epoch
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+/// Earlier times mean the clock was never set (2024-01-01)
+const MIN_UNIX_SECONDS: u64 = 1_704_067_200;
+
+/// Microseconds on the RTC timer: Unix time once set, else since power-on
+pub fn now_us() -> u64 {
+    watchdog::with_rtc(|rtc| rtc.current_time_us()).unwrap_or(0)
+}
+
+/// Unix time in seconds, once the clock has been set
+pub fn unix_seconds() -> Option<u64> {
+    Some(now_us() / 1_000_000).filter(|&seconds| seconds >= MIN_UNIX_SECONDS)
+}
+
+/// Days since 1970-01-01 in local time (`UTC_OFFSET_MINUTES`), once the
+/// clock has been set
+pub fn local_day() -> Option<u64> {
+    unix_seconds().map(|seconds| {
+        (seconds as i64 + i64::from(UTC_OFFSET_MINUTES) * 60).div_euclid(86_400) as u64
+    })
+}
+
+/// Whether this wake should ask the time server
+pub fn needs_sync(wake: u32) -> bool {
+    unix_seconds().is_none() || wake % CLOCK_SYNC_INTERVAL_WAKES == 0
+}
+
+/// Set the clock from `NTP_SERVER` with one SNTP request. Callers bound this
+/// with a timeout.
+pub async fn sync(stack: Stack<'_>) -> Result<(), Error> {
+    let address = resolve(stack, NTP_SERVER).await?;
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0u8; NTP_PACKET_LEN];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0u8; NTP_PACKET_LEN];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(0)?;
+
+    let mut packet = [0u8; NTP_PACKET_LEN];
+    packet[0] = NTP_CLIENT_REQUEST;
+    socket.send_to(&packet, (address, NTP_PORT)).await?;
+    let (len, _) = socket.recv_from(&mut packet).await?;
+    // Stratum 0 is a "kiss of death": the server refused to answer
+    if len < NTP_PACKET_LEN || packet[0] & 0b111 != NTP_SERVER_MODE || packet[1] == 0 {
+        return Err(Error::Response);
+    }
+
+    // Transmit timestamp: seconds and 2^-32 fractions since 1900
+    let seconds = u32::from_be_bytes([packet[40], packet[41], packet[42], packet[43]]);
+    let fraction = u32::from_be_bytes([packet[44], packet[45], packet[46], packet[47]]);
+    // Timestamps with the top bit clear are past the 2036 era rollover
+    let seconds = if seconds & 0x8000_0000 == 0 {
+        u64::from(seconds) + (1 << 32)
+    } else {
+        u64::from(seconds)
+    };
+    let unix_seconds = seconds - NTP_UNIX_OFFSET;
+    let unix_us = unix_seconds * 1_000_000 + ((u64::from(fraction) * 1_000_000) >> 32);
+    watchdog::with_rtc(|rtc| rtc.set_current_time_us(unix_us));
+    info!("Clock set from {}: {}", NTP_SERVER, unix_seconds);
+    Ok(())
+}
+
+async fn resolve(stack: Stack<'_>, host: &str) -> Result<IpAddress, Error> {
+    stack
+        .dns_query(host, DnsQueryType::A)
+        .await?
+        .first()
+        .copied()
+        .ok_or(Error::Dns(DnsError::Failed))
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Dns(DnsError),
+    Bind(BindError),
+    Send(SendError),
+    Recv(RecvError),
+    Response,
+    Timeout,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::Dns(e) => write!(f, "DNS error: {e:?}"),
+            Error::Bind(e) => write!(f, "Bind error: {e:?}"),
+            Error::Send(e) => write!(f, "Send error: {e:?}"),
+            Error::Recv(e) => write!(f, "Receive error: {e:?}"),
+            Error::Response => write!(f, "Invalid response from the time server"),
+            Error::Timeout => write!(f, "Time server did not answer"),
+        }
+    }
+}
+
+impl From<DnsError> for Error {
+    fn from(error: DnsError) -> Self {
+        Self::Dns(error)
+    }
+}
+
+impl From<BindError> for Error {
+    fn from(error: BindError) -> Self {
+        Self::Bind(error)
+    }
+}
+
+impl From<SendError> for Error {
+    fn from(error: SendError) -> Self {
+        Self::Send(error)
+    }
+}
+
+impl From<RecvError> for Error {
+    fn from(error: RecvError) -> Self {
+        Self::Recv(error)
+    }
+}
@@ -1,12 +1,37 @@
+use log::LevelFilter;
+
+use crate::{domain::Quantity, filter::Filter};
+
 pub const DEVICE_ID: &str = "esp32_breadboard";
 pub const AWAKE_DURATION_SECONDS: u64 = 30;
 pub const DISPLAY_WIDTH: u16 = 320;
 pub const DISPLAY_HEIGHT: u16 = 170;
 pub const HOMEASSISTANT_DISCOVERY_TOPIC_PREFIX: &str = "homeassistant";
 pub const HOMEASSISTANT_SENSOR_TOPIC: &str = "sensor";
+pub const HOMEASSISTANT_BINARY_SENSOR_TOPIC: &str = "binary_sensor";
 pub const HOMEASSISTANT_SWITCH_TOPIC: &str = "switch";
+pub const HOMEASSISTANT_EVENT_TOPIC: &str = "event";
+pub const HOMEASSISTANT_BUTTON_TOPIC: &str = "button";
+pub const HOMEASSISTANT_DEVICE_AUTOMATION_TOPIC: &str = "device_automation";
+pub const HOMEASSISTANT_UPDATE_TOPIC: &str = "update";
+pub const HOMEASSISTANT_SELECT_TOPIC: &str = "select";
 // ESP will go to deep sleep and not report any data for this duration
 pub const DEEP_SLEEP_DURATION_SECONDS: u64 = 3600 - AWAKE_DURATION_SECONDS;
+/// Whether to leave the command window early until the HA **Early sleep**
+/// switch sets otherwise. Off: the full window also catches commands sent
+/// while the device is awake. The switch is first published in this state.
+pub const EARLY_SLEEP_DEFAULT: bool = false;
+/// With early sleep, the command window closes once no command has arrived
+/// for this long. Retained commands arrive right after subscribing.
+pub const COMMAND_GRACE_MS: u64 = 2000;
+/// Maintenance mode keeps the device awake for at most this long.
+pub const MAINTENANCE_MAX_SECONDS: u64 = 600;
+/// Interval of the sensor reads, display updates and publishes in
+/// maintenance mode.
+pub const MAINTENANCE_REFRESH_SECONDS: u64 = 5;
+/// Holding the wake button this long into a button wake starts maintenance
+/// mode.
+pub const MAINTENANCE_HOLD_MS: u64 = 2000;
 /// Give up on WiFi after this long and go back to sleep instead of waiting forever
 pub const WIFI_CONNECT_TIMEOUT_SECONDS: u64 = 30;
 
@@ -17,14 +42,133 @@ pub const WIFI_CONNECT_TIMEOUT_SECONDS: u64 = 30;
 pub const WIFI_RECONNECT_BACKOFF_START_MS: u64 = 1000;
 pub const WIFI_RECONNECT_BACKOFF_MAX_MS: u64 = 30_000;
 
+/// MQTT reconnect backoff bounds. A failed connect or a dropped session is
+/// retried within the awake window, waiting this long before the first retry
+/// and doubling up to the cap. Retries stop once the next attempt would start
+/// after the awake deadline.
+pub const MQTT_RECONNECT_BACKOFF_START_MS: u64 = 500;
+pub const MQTT_RECONNECT_BACKOFF_MAX_MS: u64 = 8000;
+
+/// How long to wait for the broker's PUBACK on a QoS 1 publish (state data,
+/// pump switch reset) before treating it as lost.
+pub const MQTT_ACK_TIMEOUT_MS: u64 = 5000;
+/// Commands kept for the command window when they arrive while a publish
+/// awaits its PUBACK, e.g. the retained burst on subscribe. More than the
+/// number of command topics, so a burst is never cut short.
+pub const MQTT_PENDING_MESSAGES: usize = 16;
+
+/// Check the OTA manifest every this many wakes (and on every button wake)
+/// and report the latest version to the HA update entity. Fetching the
+/// manifest costs one extra HTTP request.
+pub const OTA_CHECK_INTERVAL_WAKES: u32 = 24;
+/// Install a newer manifest version as soon as a check finds it, instead
+/// of waiting for Install on the HA update entity.
+pub const OTA_AUTO_INSTALL: bool = false;
+/// A freshly installed image that hasn't published to MQTT after this many
+/// wakes is marked invalid and the previous image boots again.
+pub const OTA_MAX_VERIFY_WAKES: u32 = 3;
+/// Upper bound for a manifest check plus image download and flash write.
+pub const OTA_TIMEOUT_SECONDS: u64 = 120;
+/// Give up on a stalled update server after this long without data.
+pub const OTA_SOCKET_TIMEOUT_SECONDS: u64 = 10;
+
+/// Time server for the wall clock that timestamps pump runs and resets the
+/// daily light integral.
+pub const NTP_SERVER: &str = "pool.ntp.org";
+/// Upper bound for resolving the time server and its answer.
+pub const NTP_TIMEOUT_MS: u64 = 3000;
+/// Sync the clock every this many wakes once it is set; the RTC oscillator
+/// drifts by a few percent.
+pub const CLOCK_SYNC_INTERVAL_WAKES: u32 = 24;
+/// Local time zone as an offset from UTC (60 for CET), for the midnight
+/// reset of the daily light integral. Daylight saving time is not applied.
+pub const UTC_OFFSET_MINUTES: i32 = 0;
+
+/// Illuminance (lx) per µmol/m²/s of photosynthetic photon flux, for the
+/// daily light integral: about 54 for sunlight, 70-80 for white LED lights.
+pub const LUX_PER_PPFD: u32 = 54;
+/// Intervals between two light readings longer than this (s) are left out
+/// of the daily light integral rather than assumed to have had the same
+/// light.
+pub const LIGHT_MAX_INTERVAL_SECONDS: u64 = 3 * 3600;
+
+/// Records at or above this level are printed to the USB serial console.
+pub const CONSOLE_LOG_LEVEL: LevelFilter = LevelFilter::Info;
+/// Level for shipping logs to `SYSLOG_HOST` until the HA select sets another
+/// one; kept in RTC memory, so it falls back to this after a power cycle.
+pub const SYSLOG_DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+pub const SYSLOG_PORT: u16 = 514;
+/// Records kept for shipping per wake; the oldest are dropped beyond this.
+pub const SYSLOG_BUFFER_LINES: usize = 64;
+/// Longer syslog messages are truncated.
+pub const SYSLOG_LINE_LEN: usize = 256;
+/// Upper bound for resolving the syslog host and sending the buffer.
+pub const SYSLOG_TIMEOUT_MS: u64 = 2000;
+
+/// Estimated supply current (mA) with the CPU running and the radio off, for
+/// the energy telemetry. Measure the board and adjust these to compare
+/// changes with realistic figures.
+pub const CURRENT_ACTIVE_MA: u32 = 40;
+/// While WiFi associates and waits for DHCP (scans, TX bursts).
+pub const CURRENT_WIFI_CONNECT_MA: u32 = 120;
+/// While connected with the radio listening.
+pub const CURRENT_WIFI_CONNECTED_MA: u32 = 90;
+/// Added on top while the pump runs.
+pub const CURRENT_PUMP_MA: u32 = 250;
+/// In deep sleep (µA), including the regulator.
+pub const CURRENT_DEEP_SLEEP_UA: u32 = 150;
+
+/// Slack on top of the timeouts a wake-cycle phase enforces itself before the
+/// watchdog treats it as hung.
+pub const WATCHDOG_MARGIN_SECONDS: u64 = 15;
+/// Budget of each wake-cycle phase between two watchdog feeds. The MQTT
+/// command window is the longest phase and ends `AWAKE_DURATION_SECONDS` after
+/// the cycle started.
+pub const WATCHDOG_TIMEOUT_SECONDS: u64 = AWAKE_DURATION_SECONDS + WATCHDOG_MARGIN_SECONDS;
+/// If the watchdog interrupt can't run (interrupts blocked), the chip resets
+/// this long after the phase budget ran out.
+pub const WATCHDOG_RESET_GRACE_SECONDS: u64 = 5;
+
+/// Number of resets (other than wakes from deep sleep) kept in RTC memory
+/// and published to the HA reset history sensor.
+pub const RESET_HISTORY_LEN: usize = 8;
+/// This many brownout/watchdog resets in a row put the next boot into safe
+/// mode: no WiFi, no pump, and `SAFE_MODE_SLEEP_SECONDS` of deep sleep.
+pub const RESET_LOOP_THRESHOLD: u32 = 3;
+/// Deep sleep after a safe-mode boot, giving the battery time to recover.
+pub const SAFE_MODE_SLEEP_SECONDS: u64 = 6 * 3600;
+
 /// Battery voltage below this (mV) means the cell is too weak to safely power
 /// the WiFi radio and pump. The cycle skips WiFi/pump and sleeps to avoid a
 /// brownout/reset loop that would drain the battery further.
 pub const LOW_BATTERY_CUTOFF_MV: u16 = 3300;
 
-/// How many times to retry the (timing-sensitive, bit-banged) DHT11 read before
-/// giving up for this cycle.
-pub const DHT11_MAX_ATTEMPTS: usize = 3;
+/// Battery voltage below this (mV) still allows WiFi but blocks the pump: its
+/// motor current is the largest load on the cell. A blocked command is
+/// reported as `blocked_low_battery` on the pump last-run topic.
+pub const PUMP_LOW_BATTERY_CUTOFF_MV: u16 = 3500;
+
+/// How many times to retry the (timing-sensitive, bit-banged) DHT11/DHT22 read
+/// before giving up for this cycle.
+pub const DHT_MAX_ATTEMPTS: usize = 3;
+
+/// How many times to retry the DS18B20 soil temperature probes when none of
+/// them answered.
+pub const DS18B20_MAX_ATTEMPTS: usize = 2;
+/// ROM codes of the DS18B20 soil temperature probes, in slot order: the first
+/// is published as "Soil temperature 1". Copy them from the
+/// `Soil temperature probe N: 0x…` boot log. Empty uses every probe found on
+/// the bus in ROM order, whose slots shift when a probe is added or removed.
+pub const SOIL_PROBE_ROMS: &[u64] = &[];
+
+/// Also take air temperature and humidity from the SCD4x CO2 sensor, in place
+/// of the DHT11/DHT22. An I2C climate chip, when one answers, still measures
+/// the air and the SCD4x then reports CO2 only.
+pub const SCD4X_CLIMATE: bool = true;
+/// Measure the SCD4x in single-shot mode. The SCD40 lacks it: set false to
+/// run one periodic measurement per wake instead, which takes as long but
+/// draws more.
+pub const SCD4X_SINGLE_SHOT: bool = true;
 
 /// Set to false to suppress all MQTT publishing (useful during development on USB power).
 pub const MQTT_PUBLISH_ENABLED: bool = true;
@@ -32,9 +176,33 @@ pub const MQTT_PUBLISH_ENABLED: bool = true;
 // Sensor sampling configuration
 /// Battery voltage above this threshold (mV) indicates USB charging — skip reading
 pub const USB_CHARGING_VOLTAGE_MV: u16 = 4100;
+/// The battery is read through a divider that halves its voltage
+pub const BATTERY_DIVIDER_RATIO: u16 = 2;
 /// Warmup delay for DHT11 before each read (ms)
 pub const DHT11_WARMUP_DELAY_MS: u64 = 1000;
+/// Warmup delay for DHT22 before each read (ms); it samples at most every 2 s
+pub const DHT22_WARMUP_DELAY_MS: u64 = 2000;
 /// Warmup delay for powered ADC sensors (moisture, water level) before each read (ms)
 pub const SENSOR_WARMUP_DELAY_MS: u64 = 50;
-/// Number of samples to collect per sensor per cycle (min/max trimmed, rest averaged)
+/// Number of samples to collect per sensor per cycle, reduced to one value by
+/// the quantity's filter
 pub const SENSOR_SAMPLE_COUNT: usize = 5;
+/// Filter per quantity; quantities not listed use `DEFAULT_SENSOR_FILTER`.
+/// The capacitive moisture probe is smoothed across wakes so its level
+/// doesn't flap between Moist and Dry; after watering it takes about three
+/// wakes to settle.
+pub const SENSOR_FILTERS: &[(Quantity, Filter)] =
+    &[(Quantity::SoilMoisture, Filter::Ema { weight_percent: 40 })];
+/// Drop the lowest and highest sample and average the rest
+pub const DEFAULT_SENSOR_FILTER: Filter = Filter::TrimmedMean { trim: 1 };
+/// Hysteresis of the moisture level, as a fraction of the calibrated range
+/// (`MOISTURE_MIN`..`MOISTURE_MAX`): the level only changes once the reading
+/// is this far past the boundary, so the HA history doesn't fill with
+/// Moist/Dry toggles.
+pub const MOISTURE_HYSTERESIS: f32 = 0.05;
+/// Once water is detected in the overflow, it is reported until the reading
+/// falls this far (mV) below the threshold. Detection itself isn't delayed.
+pub const OVERFLOW_HYSTERESIS_MV: u16 = 300;
+/// A moisture calibration that isn't stopped from HA ends after this many
+/// wakes, so a forgotten one doesn't keep the EMA off for good.
+pub const MOISTURE_CALIBRATION_MAX_WAKES: u16 = 24;
@@ -0,0 +1,239 @@
+//! Panic capture
+//!
+//! Replaces the `esp-backtrace` panic handler. The panic is still printed to
+//! the serial console, but nobody watches that on a battery device: the
+//! message, location and a short backtrace are also kept in persistent RTC
+//! memory so the next wake that reaches MQTT can publish them to
+//! `{DEVICE_ID}/crash`, together with the number of crashes since power-on.
+//! Wake-cycle hangs caught by the watchdog are reported the same way.
+
+use core::{
+    fmt::{self, Display, Write},
+    panic::{Location, PanicInfo},
+    str,
+};
+
+use embassy_time::Duration;
+use esp_backtrace::Backtrace;
+use esp_hal::{
+    Persistable,
+    delay::Delay,
+    gpio::{Level, Output, OutputConfig},
+    peripherals::{GPIO13, GPIO14, LPWR},
+    rtc_cntl::Rtc,
+};
+use esp_println::println;
+
+use crate::{CRASH_COUNT, CRASH_REPORT, config::DEEP_SLEEP_DURATION_SECONDS, sleep::enter_deep};
+
+const MESSAGE_LEN: usize = 160;
+const LOCATION_LEN: usize = 96;
+const BACKTRACE_LEN: usize = 8;
+
+/// Marks a report that has not been published yet ("CRSH")
+const PENDING: u32 = 0x4352_5348;
+
+/// The last panic, as kept in RTC memory.
+///
+/// Only integers, so any bit pattern left by a reset halfway through writing
+/// it is a valid value; [`pending`] checks the lengths and the checksum
+/// before handing it out.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct CrashReport {
+    /// `PENDING` until the report has been published
+    state: u32,
+    checksum: u32,
+    message_len: u32,
+    location_len: u32,
+    backtrace_len: u32,
+    message: [u8; MESSAGE_LEN],
+    location: [u8; LOCATION_LEN],
+    backtrace: [u32; BACKTRACE_LEN],
+}
+
+// SAFETY: `CrashReport` contains only integers and integer arrays, which are
+// valid for any bit pattern.
+unsafe impl Persistable for CrashReport {}
+
+impl CrashReport {
+    pub const EMPTY: Self = Self {
+        state: 0,
+        checksum: 0,
+        message_len: 0,
+        location_len: 0,
+        backtrace_len: 0,
+        message: [0; MESSAGE_LEN],
+        location: [0; LOCATION_LEN],
+        backtrace: [0; BACKTRACE_LEN],
+    };
+
+    /// The panic message, truncated to `MESSAGE_LEN` bytes
+    pub fn message(&self) -> &str {
+        str::from_utf8(&self.message[..self.message_len as usize]).unwrap_or_default()
+    }
+
+    /// `file:line:column` of the panic
+    pub fn location(&self) -> &str {
+        str::from_utf8(&self.location[..self.location_len as usize]).unwrap_or_default()
+    }
+
+    /// Return addresses of the innermost frames
+    pub fn backtrace(&self) -> &[u32] {
+        &self.backtrace[..self.backtrace_len as usize]
+    }
+
+    fn is_valid(&self) -> bool {
+        self.state == PENDING
+            && self.message_len as usize <= MESSAGE_LEN
+            && self.location_len as usize <= LOCATION_LEN
+            && self.backtrace_len as usize <= BACKTRACE_LEN
+            && self.checksum == self.compute_checksum()
+    }
+
+    /// FNV-1a over the lengths and contents
+    fn compute_checksum(&self) -> u32 {
+        let words = [self.message_len, self.location_len, self.backtrace_len];
+        words
+            .iter()
+            .chain(&self.backtrace)
+            .flat_map(|word| word.to_le_bytes())
+            .chain(self.message)
+            .chain(self.location)
+            .fold(0x811c_9dc5, |hash, byte| {
+                (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+            })
+    }
+}
+
+/// The crash report waiting to be published, if any.
+pub fn pending() -> Option<CrashReport> {
+    Some(CRASH_REPORT.get()).filter(CrashReport::is_valid)
+}
+
+/// Drop the pending report once the broker has acknowledged it.
+pub fn mark_published() {
+    CRASH_REPORT.set(CrashReport::EMPTY);
+}
+
+/// Print the panic like `esp-backtrace` did, keep it for the next wake and
+/// go to sleep as after a failed wake cycle. A later panic or watchdog hang
+/// replaces a report that hasn't been published yet; the crash counter counts
+/// both.
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    // A panic in the middle of a pump run must not leave the pump on.
+    // SAFETY: the panicking code never runs again, so nothing else uses the pin.
+    let _pump_pin = Output::new(
+        unsafe { GPIO13::steal() },
+        Level::Low,
+        OutputConfig::default(),
+    );
+
+    println!("");
+    println!("====================== PANIC ======================");
+    println!("{info}");
+    println!("");
+    println!("Backtrace:");
+    println!("");
+    let backtrace = Backtrace::capture();
+    for frame in backtrace.frames() {
+        println!("0x{:x}", frame.program_counter());
+    }
+
+    record(info.message(), info.location(), &backtrace);
+
+    // Halting would leave the radio and display on until the battery is
+    // empty, and rebooting right away could loop just as fast; sleeping keeps
+    // the hourly schedule and the wake button still works.
+    println!("Enter deep sleep for {}s", DEEP_SLEEP_DURATION_SECONDS);
+    Delay::new().delay_millis(100);
+    // SAFETY: as above, the panicking code never touches these again.
+    let mut wake_up_btn_pin = unsafe { GPIO14::steal() };
+    enter_deep(
+        &mut wake_up_btn_pin,
+        &mut Rtc::new(unsafe { LPWR::steal() }),
+        Duration::from_secs(DEEP_SLEEP_DURATION_SECONDS),
+    )
+}
+
+/// Report a wake-cycle phase that overran its watchdog budget like a panic,
+/// with the backtrace of the watchdog interrupt.
+pub fn record_hang(phase: &str) {
+    record(
+        format_args!("watchdog expired during {phase}"),
+        None,
+        &Backtrace::capture(),
+    );
+}
+
+fn record(message: impl Display, location: Option<&Location>, backtrace: &Backtrace) {
+    let mut report = CrashReport::EMPTY;
+
+    let mut buffer = Truncating::new(&mut report.message);
+    let _ = write!(buffer, "{message}");
+    report.message_len = buffer.len as u32;
+
+    if let Some(location) = location {
+        // Paths into dependencies are long; their tail names the crate and file.
+        let file = location.file();
+        let mut start = file.len().saturating_sub(LOCATION_LEN - 16);
+        while !file.is_char_boundary(start) {
+            start += 1;
+        }
+        let mut buffer = Truncating::new(&mut report.location);
+        let _ = write!(
+            buffer,
+            "{}:{}:{}",
+            &file[start..],
+            location.line(),
+            location.column()
+        );
+        report.location_len = buffer.len as u32;
+    }
+
+    for (address, frame) in report.backtrace.iter_mut().zip(backtrace.frames()) {
+        *address = frame.program_counter() as u32;
+    }
+    report.backtrace_len = backtrace.frames().len().min(BACKTRACE_LEN) as u32;
+
+    report.checksum = report.compute_checksum();
+    report.state = PENDING;
+    CRASH_REPORT.set(report);
+    CRASH_COUNT.set(CRASH_COUNT.get().wrapping_add(1));
+}
+
+/// Formats into a fixed buffer, dropping whatever doesn't fit. Cuts at a
+/// character boundary so the contents stay valid UTF-8. Also used for the
+/// syslog buffer, which can't allocate either.
+pub(crate) struct Truncating<'a> {
+    buffer: &'a mut [u8],
+    pub(crate) len: usize,
+    full: bool,
+}
+
+impl<'a> Truncating<'a> {
+    pub(crate) fn new(buffer: &'a mut [u8]) -> Self {
+        Self {
+            buffer,
+            len: 0,
+            full: false,
+        }
+    }
+}
+
+impl Write for Truncating<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.full {
+            return Ok(());
+        }
+        let mut end = s.len().min(self.buffer.len() - self.len);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.buffer[self.len..self.len + end].copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end;
+        self.full = end < s.len();
+        Ok(())
+    }
+}
@@ -1,9 +1,18 @@
 use alloc::string::{String, ToString};
-use core::fmt::{Display, Formatter, Result};
+use core::{
+    fmt::{Display, Formatter, Result},
+    ops::RangeInclusive,
+};
 use heapless::Vec;
 use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
+use crate::config::{
+    BATTERY_DIVIDER_RATIO, MOISTURE_CALIBRATION_MAX_WAKES, MOISTURE_HYSTERESIS,
+    OVERFLOW_HYSTERESIS_MV,
+};
+
 const OVERFLOW_THRESHOLD: u16 = 2800;
 //soil is wet
 const MOISTURE_MIN: u16 = 800;
@@ -13,24 +22,53 @@ const MOISTURE_MAX: u16 = 2150;
 const MOISTURE_WET_THRESHOLD: f32 = 0.8;
 // less than 15% is dry
 const MOISTURE_DRY_THRESHOLD: f32 = 0.15;
+// moisture probe readings outside this are no soil: probe out of the pot or damaged
+const MOISTURE_VALID_MV: RangeInclusive<i32> = 300..=2800;
+// battery readings outside this are no LiPo cell
+const BATTERY_VALID_MV: RangeInclusive<i32> = 2500..=4500;
+// analog input at ground: probe unpowered or signal shorted
+const ADC_RAIL_LOW_MV: i32 = 20;
+// analog input at full scale (11 dB): signal wire floating or shorted to 3.3 V
+const ADC_RAIL_HIGH_MV: i32 = 3600;
 
 /// Struct to hold sensor data
 #[derive(Default, Debug)]
 pub struct SensorData {
-    pub data: Vec<Sensor, 6>,
+    pub data: Vec<Sensor, 24>,
+    /// Health of each quantity read this wake: `None` when its samples look
+    /// sound. Quantities without samples and without a failed read (the
+    /// battery on USB power) are left out.
+    pub health: Vec<(Quantity, Option<Fault>), { Quantity::COUNT }>,
+}
+
+impl SensorData {
+    /// The fault found in the samples of `quantity` this wake
+    pub fn fault(&self, quantity: Quantity) -> Option<Fault> {
+        self.health
+            .iter()
+            .find(|(q, _)| *q == quantity)
+            .and_then(|(_, fault)| *fault)
+    }
 }
 
 impl Display for SensorData {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         self.data.iter().try_for_each(|sensor| {
-            let unit = sensor.unit().unwrap_or_default();
-            writeln!(f, "{}: {} {}", sensor.name(), sensor.value(), unit)
-        })
+            let info = sensor.info();
+            let unit = info.unit.unwrap_or_default();
+            writeln!(f, "{}: {} {}", info.name, sensor.value(), unit)
+        })?;
+        self.health
+            .iter()
+            .try_for_each(|(quantity, fault)| match fault {
+                Some(fault) => writeln!(f, "{}: {}", quantity.label(), fault),
+                None => Ok(()),
+            })
     }
 }
 
 /// Represents the qualitative state of soil moisture as interpreted from sensor readings.
-#[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
 pub enum MoistureLevel {
     Wet,   // Soil is wet
     Moist, // Soil is moist (intermediate)
@@ -50,13 +88,39 @@ impl Display for MoistureLevel {
 
 impl From<u16> for MoistureLevel {
     fn from(value: u16) -> Self {
+        Self::classify(value, None)
+    }
+}
+
+impl MoistureLevel {
+    /// Level of the raw reading `value`, holding on to the `previous` level
+    /// until the reading is `MOISTURE_HYSTERESIS` past the boundary. Without
+    /// a previous level the plain thresholds apply.
+    pub fn classify(value: u16, previous: Option<Self>) -> Self {
         let clamped = clamp_soil_moisture(value);
 
         let value = (MOISTURE_MAX - clamped) as f32 / (MOISTURE_MAX - MOISTURE_MIN) as f32;
 
+        // Move the thresholds away from the previous level
+        let (wet, dry) = match previous {
+            None => (MOISTURE_WET_THRESHOLD, MOISTURE_DRY_THRESHOLD),
+            Some(Self::Wet) => (
+                MOISTURE_WET_THRESHOLD - MOISTURE_HYSTERESIS,
+                MOISTURE_DRY_THRESHOLD,
+            ),
+            Some(Self::Moist) => (
+                MOISTURE_WET_THRESHOLD + MOISTURE_HYSTERESIS,
+                MOISTURE_DRY_THRESHOLD - MOISTURE_HYSTERESIS,
+            ),
+            Some(Self::Dry) => (
+                MOISTURE_WET_THRESHOLD,
+                MOISTURE_DRY_THRESHOLD + MOISTURE_HYSTERESIS,
+            ),
+        };
+
         match value {
-            p if p > MOISTURE_WET_THRESHOLD => Self::Wet,
-            p if p < MOISTURE_DRY_THRESHOLD => Self::Dry,
+            p if p > wet => Self::Wet,
+            p if p < dry => Self::Dry,
             _ => Self::Moist,
         }
     }
@@ -66,11 +130,236 @@ impl From<u16> for MoistureLevel {
 #[derive(Debug, EnumIter)]
 pub enum Sensor {
     OverflowDetected(bool),      // true = water at pot base, pump blocked
-    AirTemperature(i8),          // Air temperature in °C
-    AirHumidity(u8),             // Air humidity in %
+    AirTemperature(Tenths),      // Air temperature in °C
+    AirHumidity(Tenths),         // Air humidity in %
+    AirPressure(u16),            // Air pressure in hPa
     SoilMoisture(MoistureLevel), // Soil moisture (qualitative)
     BatteryVoltage(u16),         // Battery voltage in mV
     SoilMoistureRaw(SoilMoistureRawLevel), // Raw soil moisture sensor value
+    SoilTemperature(Probe, Tenths), // Soil temperature in °C, per probe
+    Illuminance(u32),            // Ambient light in lx
+    DailyLightIntegral(Tenths),  // Light since local midnight in mol/m²
+    Co2(u16),                    // Carbon dioxide in ppm
+    DewPoint(Tenths),            // Dew point of the air in °C
+    AbsoluteHumidity(Tenths),    // Water vapour in g/m³
+    VapourPressureDeficit(Hundredths), // Vapour pressure deficit of the air in kPa
+}
+
+/// HA discovery metadata of a published sensor. Kept per `Sensor` rather
+/// than per driver: several drivers measure the same quantity, one quantity
+/// can publish several sensors, and the derived sensors have no driver.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SensorInfo {
+    /// Topic key under `{DEVICE_ID}/`, also the HA object id
+    pub topic: &'static str,
+    pub name: &'static str,
+    /// Unit of measurement; sensors with one are recorded as measurements
+    pub unit: Option<&'static str>,
+    pub device_class: Option<&'static str>,
+    /// Decimals HA should show
+    pub display_precision: Option<u8>,
+}
+
+impl SensorInfo {
+    const fn new(topic: &'static str, name: &'static str) -> Self {
+        Self {
+            topic,
+            name,
+            unit: None,
+            device_class: None,
+            display_precision: None,
+        }
+    }
+
+    const fn unit(self, unit: &'static str, device_class: Option<&'static str>) -> Self {
+        Self {
+            unit: Some(unit),
+            device_class,
+            ..self
+        }
+    }
+
+    const fn precision(self, decimals: u8) -> Self {
+        Self {
+            display_precision: Some(decimals),
+            ..self
+        }
+    }
+}
+
+/// Physical quantities the sensor drivers measure. Samples of one quantity
+/// are averaged together, whichever driver took them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Quantity {
+    AirHumidity,            // Tenths of %
+    AirTemperature,         // Tenths of °C
+    AirPressure,            // hPa
+    WaterLevel,             // Overflow probe, mV
+    SoilMoisture,           // Moisture probe, mV
+    BatteryVoltage,         // mV after the divider
+    Illuminance,            // lx
+    Co2,                    // ppm
+    SoilTemperature(Probe), // Tenths of °C
+}
+
+impl Quantity {
+    const SINGLE: [Quantity; 8] = [
+        Quantity::AirHumidity,
+        Quantity::AirTemperature,
+        Quantity::AirPressure,
+        Quantity::WaterLevel,
+        Quantity::SoilMoisture,
+        Quantity::BatteryVoltage,
+        Quantity::Illuminance,
+        Quantity::Co2,
+    ];
+    pub const COUNT: usize = Self::SINGLE.len() + Probe::COUNT;
+
+    /// All quantities, one soil temperature per probe slot
+    pub fn iter() -> impl Iterator<Item = Quantity> {
+        Self::SINGLE
+            .into_iter()
+            .chain(Probe::all().map(Quantity::SoilTemperature))
+    }
+
+    /// Position of the quantity in per-quantity arrays
+    pub fn index(&self) -> usize {
+        match self {
+            Quantity::AirHumidity => 0,
+            Quantity::AirTemperature => 1,
+            Quantity::AirPressure => 2,
+            Quantity::WaterLevel => 3,
+            Quantity::SoilMoisture => 4,
+            Quantity::BatteryVoltage => 5,
+            Quantity::Illuminance => 6,
+            Quantity::Co2 => 7,
+            Quantity::SoilTemperature(probe) => Self::SINGLE.len() + probe.0 as usize,
+        }
+    }
+
+    /// Topic key of the quantity, that of its main published sensor
+    pub fn key(&self) -> &'static str {
+        match self {
+            Quantity::AirHumidity => "humidity",
+            Quantity::AirTemperature => "temperature",
+            Quantity::AirPressure => "pressure",
+            Quantity::WaterLevel => "overflow",
+            Quantity::SoilMoisture => "moisture",
+            Quantity::BatteryVoltage => "batteryvoltage",
+            Quantity::Illuminance => "illuminance",
+            Quantity::Co2 => "co2",
+            Quantity::SoilTemperature(probe) => Probe::TOPICS[probe.0 as usize],
+        }
+    }
+
+    /// Name of what measures the quantity, for HA and the display
+    pub fn label(&self) -> &'static str {
+        match self {
+            Quantity::AirHumidity => "Room humidity",
+            Quantity::AirTemperature => "Room temperature",
+            Quantity::AirPressure => "Air pressure",
+            Quantity::WaterLevel => "Overflow probe",
+            Quantity::SoilMoisture => "Moisture probe",
+            Quantity::BatteryVoltage => "Battery voltage",
+            Quantity::Illuminance => "Light sensor",
+            Quantity::Co2 => "CO2 sensor",
+            Quantity::SoilTemperature(probe) => Probe::NAMES[probe.0 as usize],
+        }
+    }
+
+    /// Whether the quantity is sampled from an analog input, whose readings
+    /// always carry some noise
+    pub fn is_analog(&self) -> bool {
+        matches!(
+            self,
+            Quantity::WaterLevel | Quantity::SoilMoisture | Quantity::BatteryVoltage
+        )
+    }
+
+    /// Values the sensor can report at all; an average outside them is a fault
+    pub fn valid_range(&self) -> Option<RangeInclusive<i32>> {
+        match self {
+            Quantity::AirHumidity => Some(0..=1000),
+            Quantity::AirTemperature => Some(-400..=850),
+            Quantity::AirPressure => Some(300..=1100),
+            Quantity::WaterLevel => None,
+            Quantity::SoilMoisture => Some(MOISTURE_VALID_MV),
+            Quantity::BatteryVoltage => Some(BATTERY_VALID_MV),
+            Quantity::Illuminance => Some(0..=120_000),
+            Quantity::Co2 => Some(0..=40_000),
+            Quantity::SoilTemperature(_) => Some(-550..=1250),
+        }
+    }
+
+    /// Get the name of the quantity for logs
+    pub fn name(&self) -> &'static str {
+        match self {
+            Quantity::AirHumidity => "air humidity",
+            Quantity::AirTemperature => "air temperature",
+            Quantity::AirPressure => "air pressure",
+            Quantity::WaterLevel => "overflow level",
+            Quantity::SoilMoisture => "soil moisture",
+            Quantity::BatteryVoltage => "battery voltage",
+            Quantity::Illuminance => "illuminance",
+            Quantity::Co2 => "CO2",
+            Quantity::SoilTemperature(_) => "soil temperature",
+        }
+    }
+}
+
+/// Slot of a DS18B20 soil temperature probe, shown and published 1-based.
+/// Slots follow `config::SOIL_PROBE_ROMS`, or the ROM order on the bus.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Probe(pub u8);
+
+impl Probe {
+    pub const COUNT: usize = 4;
+    const TOPICS: [&'static str; Probe::COUNT] = [
+        "soiltemperature1",
+        "soiltemperature2",
+        "soiltemperature3",
+        "soiltemperature4",
+    ];
+    const NAMES: [&'static str; Probe::COUNT] = [
+        "Soil temperature 1",
+        "Soil temperature 2",
+        "Soil temperature 3",
+        "Soil temperature 4",
+    ];
+
+    pub fn all() -> impl Iterator<Item = Probe> {
+        (0..Self::COUNT as u8).map(Probe)
+    }
+}
+
+impl Display for Probe {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{}", self.0 + 1)
+    }
+}
+
+/// A value in tenths of its unit, shown with one decimal
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Tenths(pub i16);
+
+impl Display for Tenths {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        write!(f, "{}{}.{}", sign, abs / 10, abs % 10)
+    }
+}
+
+/// A value in hundredths of its unit, shown with two decimals
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Hundredths(pub i16);
+
+impl Display for Hundredths {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        write!(f, "{}{}.{:02}", sign, abs / 100, abs % 100)
+    }
 }
 
 #[derive(Debug, Default)]
@@ -89,50 +378,78 @@ impl Display for SoilMoistureRawLevel {
 }
 
 impl Sensor {
-    /// Get the unit of the sensor value
-    pub fn unit(&self) -> Option<&'static str> {
-        match self {
-            Sensor::AirTemperature(_) => Some("°C"),
-            Sensor::AirHumidity(_) => Some("%"),
-            Sensor::BatteryVoltage(_) => Some("mV"),
-            Sensor::SoilMoistureRaw(_) => Some("mV"),
-            _ => None,
-        }
-    }
-
-    /// Get the device class of the sensor
-    /// See https://www.home-assistant.io/integrations/sensor/#device-class
-    pub fn device_class(&self) -> Option<&'static str> {
-        match self {
-            Sensor::AirTemperature(_) => Some("temperature"),
-            Sensor::AirHumidity(_) => Some("humidity"),
-            Sensor::BatteryVoltage(_) => Some("voltage"),
-            Sensor::SoilMoistureRaw(_) => Some("voltage"),
-            _ => None,
-        }
+    /// One sensor of every kind for HA discovery, one soil temperature per
+    /// probe slot
+    pub fn all() -> impl Iterator<Item = Sensor> {
+        Sensor::iter()
+            .filter(|sensor| !matches!(sensor, Sensor::SoilTemperature(..)))
+            .chain(Probe::all().map(|probe| Sensor::SoilTemperature(probe, Tenths::default())))
     }
 
-    /// Get the MQTT topic for the sensor
-    pub fn topic(&self) -> &'static str {
+    /// Get the quantity the sensor value is derived from
+    pub fn quantity(&self) -> Quantity {
         match self {
-            Sensor::AirTemperature(_) => "temperature",
-            Sensor::AirHumidity(_) => "humidity",
-            Sensor::SoilMoisture(_) => "moisture",
-            Sensor::OverflowDetected(_) => "overflow",
-            Sensor::BatteryVoltage(_) => "batteryvoltage",
-            Sensor::SoilMoistureRaw(_) => "moistureraw",
+            Sensor::AirTemperature(_) => Quantity::AirTemperature,
+            // Derived from temperature and humidity, which every climate
+            // sensor measures together
+            Sensor::AirHumidity(_)
+            | Sensor::DewPoint(_)
+            | Sensor::AbsoluteHumidity(_)
+            | Sensor::VapourPressureDeficit(_) => Quantity::AirHumidity,
+            Sensor::AirPressure(_) => Quantity::AirPressure,
+            Sensor::SoilMoisture(_) | Sensor::SoilMoistureRaw(_) => Quantity::SoilMoisture,
+            Sensor::OverflowDetected(_) => Quantity::WaterLevel,
+            Sensor::BatteryVoltage(_) => Quantity::BatteryVoltage,
+            Sensor::SoilTemperature(probe, _) => Quantity::SoilTemperature(*probe),
+            Sensor::Illuminance(_) | Sensor::DailyLightIntegral(_) => Quantity::Illuminance,
+            Sensor::Co2(_) => Quantity::Co2,
         }
     }
 
-    /// Get the name of the sensor
-    pub fn name(&self) -> &'static str {
+    /// How the sensor is announced to HA and shown on the display.
+    /// Device classes: https://www.home-assistant.io/integrations/sensor/#device-class
+    pub fn info(&self) -> SensorInfo {
+        let info = SensorInfo::new;
         match self {
-            Sensor::AirTemperature(_) => "Room temperature",
-            Sensor::AirHumidity(_) => "Room humidity",
-            Sensor::SoilMoisture(_) => "Soil moisture",
-            Sensor::OverflowDetected(_) => "Overflow detected",
-            Sensor::BatteryVoltage(_) => "Battery voltage",
-            Sensor::SoilMoistureRaw(_) => "Soil moisture (mV)",
+            Sensor::AirTemperature(_) => info("temperature", "Room temperature")
+                .unit("°C", Some("temperature"))
+                .precision(1),
+            Sensor::AirHumidity(_) => info("humidity", "Room humidity")
+                .unit("%", Some("humidity"))
+                .precision(1),
+            Sensor::AirPressure(_) => {
+                info("pressure", "Air pressure").unit("hPa", Some("atmospheric_pressure"))
+            }
+            Sensor::SoilMoisture(_) => info("moisture", "Soil moisture"),
+            Sensor::OverflowDetected(_) => info("overflow", "Overflow detected"),
+            Sensor::BatteryVoltage(_) => {
+                info("batteryvoltage", "Battery voltage").unit("mV", Some("voltage"))
+            }
+            Sensor::SoilMoistureRaw(_) => {
+                info("moistureraw", "Soil moisture (mV)").unit("mV", Some("voltage"))
+            }
+            Sensor::SoilTemperature(probe, _) => {
+                let slot = probe.0 as usize;
+                info(Probe::TOPICS[slot], Probe::NAMES[slot])
+                    .unit("°C", Some("temperature"))
+                    .precision(1)
+            }
+            Sensor::Illuminance(_) => {
+                info("illuminance", "Illuminance").unit("lx", Some("illuminance"))
+            }
+            Sensor::DailyLightIntegral(_) => info("dli", "Daily light integral")
+                .unit("mol/m²", None)
+                .precision(1),
+            Sensor::Co2(_) => info("co2", "CO2").unit("ppm", Some("carbon_dioxide")),
+            Sensor::DewPoint(_) => info("dewpoint", "Dew point")
+                .unit("°C", Some("temperature"))
+                .precision(1),
+            Sensor::AbsoluteHumidity(_) => info("absolutehumidity", "Absolute humidity")
+                .unit("g/m³", Some("absolute_humidity"))
+                .precision(1),
+            Sensor::VapourPressureDeficit(_) => info("vpd", "Vapour pressure deficit")
+                .unit("kPa", Some("pressure"))
+                .precision(2),
         }
     }
 
@@ -141,25 +458,355 @@ impl Sensor {
         match self {
             Sensor::AirTemperature(v) => v.to_string(),
             Sensor::AirHumidity(v) => v.to_string(),
+            Sensor::AirPressure(v) => v.to_string(),
             Sensor::SoilMoisture(v) => v.to_string(),
             Sensor::OverflowDetected(v) => if *v { "YES" } else { "NO" }.to_string(),
             Sensor::BatteryVoltage(v) => v.to_string(),
             Sensor::SoilMoistureRaw(v) => v.to_string(),
+            Sensor::SoilTemperature(_, v) => v.to_string(),
+            Sensor::Illuminance(v) => v.to_string(),
+            Sensor::DailyLightIntegral(v) => v.to_string(),
+            Sensor::Co2(v) => v.to_string(),
+            Sensor::DewPoint(v) => v.to_string(),
+            Sensor::AbsoluteHumidity(v) => v.to_string(),
+            Sensor::VapourPressureDeficit(v) => v.to_string(),
         }
     }
 }
 
 impl Display for Sensor {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
-        let unit = self.unit().unwrap_or_default();
-        write!(f, "{}: {}{}", self.name(), self.value(), unit)
+        let info = self.info();
+        let unit = info.unit.unwrap_or_default();
+        write!(f, "{}: {}{}", info.name, self.value(), unit)
     }
 }
 
+/// On-device actions exposed to HA as button entities.
+#[derive(Debug, Clone, Copy, PartialEq, EnumIter)]
+pub enum Button {
+    ResendDiscovery,   // Publish all discovery messages again
+    Reboot,            // Software reset
+    CalibrateMoisture, // Start capturing the raw soil moisture range
+    StopCalibration,   // End the moisture calibration
+    ClearHistory,      // Reset the pump run statistics
+}
+
+impl Button {
+    /// Get the MQTT topic segment of the button
+    pub fn topic(&self) -> &'static str {
+        match self {
+            Button::ResendDiscovery => "resend_discovery",
+            Button::Reboot => "reboot",
+            Button::CalibrateMoisture => "calibrate_moisture",
+            Button::StopCalibration => "stop_calibration",
+            Button::ClearHistory => "clear_history",
+        }
+    }
+
+    /// Get the name of the button
+    pub fn name(&self) -> &'static str {
+        match self {
+            Button::ResendDiscovery => "Resend discovery",
+            Button::Reboot => "Reboot",
+            Button::CalibrateMoisture => "Start moisture calibration",
+            Button::StopCalibration => "Stop moisture calibration",
+            Button::ClearHistory => "Clear history",
+        }
+    }
+
+    /// Get the device class of the button
+    /// See https://www.home-assistant.io/integrations/button/#device-class
+    pub fn device_class(&self) -> Option<&'static str> {
+        match self {
+            Button::Reboot => Some("restart"),
+            _ => None,
+        }
+    }
+}
+
+/// Raw soil moisture range seen since calibration was started from HA. Put
+/// the probe in dry air and in water (the wake button gives an immediate
+/// reading) and use the captured bounds for `MOISTURE_MIN`/`MOISTURE_MAX`.
+/// Ends when stopped from HA or after `MOISTURE_CALIBRATION_MAX_WAKES`.
+#[derive(Debug, Clone, Copy)]
+pub struct MoistureCalibration {
+    pub min: u16,
+    pub max: u16,
+    pub samples: u16,
+    pub wakes: u16,
+}
+
+impl MoistureCalibration {
+    /// Calibration started, no reading captured yet
+    pub const EMPTY: Self = Self {
+        min: u16::MAX,
+        max: 0,
+        samples: 0,
+        wakes: 0,
+    };
+
+    /// Count a wake, `None` once the calibration has run for
+    /// `MOISTURE_CALIBRATION_MAX_WAKES` wakes
+    pub fn next_wake(self) -> Option<Self> {
+        (self.wakes < MOISTURE_CALIBRATION_MAX_WAKES).then_some(Self {
+            wakes: self.wakes + 1,
+            ..self
+        })
+    }
+
+    /// Widen the captured range with an unclamped raw reading
+    pub fn record(self, raw: u16) -> Self {
+        Self {
+            min: self.min.min(raw),
+            max: self.max.max(raw),
+            samples: self.samples.saturating_add(1),
+            wakes: self.wakes,
+        }
+    }
+}
+
+/// Pump state as reported on the pump state topic.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PumpState {
+    Pending, // ON received, interlocks not yet checked
+    Running, // Relay on
+    Blocked, // Last command blocked by an interlock
+    Idle,    // Nothing pending
+}
+
+impl PumpState {
+    pub const ALL: [Self; 4] = [Self::Pending, Self::Running, Self::Blocked, Self::Idle];
+
+    /// Get the state as published on MQTT
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Running => "running",
+            Self::Blocked => "blocked",
+            Self::Idle => "idle",
+        }
+    }
+}
+
+/// What happened to an accepted pump command.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PumpOutcome {
+    Ran,                // Pump ran for the full watering duration
+    BlockedOverflow,    // Water at the pot base
+    BlockedLowBattery,  // Battery too weak for the pump motor
+    BlockedSensorFault, // Moisture or overflow probe readings not trusted
+}
+
+impl PumpOutcome {
+    pub const ALL: [Self; 4] = [
+        Self::Ran,
+        Self::BlockedOverflow,
+        Self::BlockedLowBattery,
+        Self::BlockedSensorFault,
+    ];
+
+    /// Get the outcome as published on MQTT (also the HA event type)
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ran => "ran",
+            Self::BlockedOverflow => "blocked_overflow",
+            Self::BlockedLowBattery => "blocked_low_battery",
+            Self::BlockedSensorFault => "blocked_sensor_fault",
+        }
+    }
+
+    /// Get a human-readable reason for the outcome
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::Ran => "Watered on command",
+            Self::BlockedOverflow => "Overflow sensor detected water at the pot base",
+            Self::BlockedLowBattery => "Battery voltage too low to run the pump",
+            Self::BlockedSensorFault => "Moisture or overflow probe reported a fault",
+        }
+    }
+}
+
+/// Why the readings of a quantity can't be trusted this wake
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    FailedRead,  // Too few reads succeeded to average
+    StuckAtRail, // Analog input pinned at ground or full scale
+    OutOfRange,  // Average outside what the sensor can report
+    NoVariance,  // Identical analog samples, which always carry noise
+}
+
+impl Fault {
+    /// Get the fault as published on MQTT
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::FailedRead => "failed_read",
+            Self::StuckAtRail => "stuck_at_rail",
+            Self::OutOfRange => "out_of_range",
+            Self::NoVariance => "no_variance",
+        }
+    }
+}
+
+impl Display for Fault {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Self::FailedRead => write!(f, "Read failed"),
+            Self::StuckAtRail => write!(f, "Stuck at the ADC rail"),
+            Self::OutOfRange => write!(f, "Reading out of range"),
+            Self::NoVariance => write!(f, "Reading does not change"),
+        }
+    }
+}
+
+/// One accepted pump command, with the run statistics kept in RTC memory.
+#[derive(Debug)]
+pub struct PumpRun {
+    pub outcome: PumpOutcome,
+    pub duration_secs: u64,
+    /// Unix time the command was handled, once the clock has been set over
+    /// SNTP (see `clock`); HA's receipt time is wrong for a retained or
+    /// replayed message.
+    pub time: Option<u64>,
+    /// Boot count of the wake cycle that handled the command
+    pub wake: u32,
+    pub run_count: u32,
+    pub total_runtime_secs: u32,
+}
+
 pub fn overflow_detected(adc_mv: u16) -> bool {
     adc_mv > OVERFLOW_THRESHOLD // ~2217 mV dry, ~3475 mV submerged
 }
 
+/// `overflow_detected`, holding on to water detected on the `previous` wake
+/// until the reading falls `OVERFLOW_HYSTERESIS_MV` below the threshold.
+pub fn classify_overflow(adc_mv: u16, previous: bool) -> bool {
+    if previous {
+        adc_mv > OVERFLOW_THRESHOLD.saturating_sub(OVERFLOW_HYSTERESIS_MV)
+    } else {
+        overflow_detected(adc_mv)
+    }
+}
+
+/// Check the samples of one quantity for a fault: analog samples all at a
+/// rail or all identical, or an average outside the quantity's valid range.
+/// Too few samples to average is a `FailedRead`.
+pub fn diagnose(quantity: Quantity, samples: &[i32], average: Option<i32>) -> Option<Fault> {
+    let Some(average) = average else {
+        return Some(Fault::FailedRead);
+    };
+    if quantity.is_analog() {
+        // The rails are the ADC input's, ahead of the battery divider
+        let divider = match quantity {
+            Quantity::BatteryVoltage => i32::from(BATTERY_DIVIDER_RATIO),
+            _ => 1,
+        };
+        if samples.iter().all(|&mv| mv / divider <= ADC_RAIL_LOW_MV)
+            || samples.iter().all(|&mv| mv / divider >= ADC_RAIL_HIGH_MV)
+        {
+            return Some(Fault::StuckAtRail);
+        }
+        if samples.iter().all(|&mv| mv == samples[0]) {
+            return Some(Fault::NoVariance);
+        }
+    }
+    match quantity.valid_range() {
+        Some(range) if !range.contains(&average) => Some(Fault::OutOfRange),
+        _ => None,
+    }
+}
+
 fn clamp_soil_moisture(value: u16) -> u16 {
     value.clamp(MOISTURE_MIN, MOISTURE_MAX)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use MoistureLevel::{Dry, Moist, Wet};
+
+    // The ratio of a raw reading is (2150 - raw) / 1350; the hysteresis of
+    // 0.05 is 67.5 mV. Each pair sits 1 mV either side of a boundary.
+
+    #[test]
+    fn plain_thresholds_without_a_previous_level() {
+        assert_eq!(MoistureLevel::from(1069), Wet); // 0.8007
+        assert_eq!(MoistureLevel::from(1071), Moist); // 0.7993
+        assert_eq!(MoistureLevel::from(1947), Moist); // 0.1504
+        assert_eq!(MoistureLevel::from(1948), Dry); // 0.1496
+        // Clamped to MOISTURE_MIN/MOISTURE_MAX
+        assert_eq!(MoistureLevel::from(0), Wet);
+        assert_eq!(MoistureLevel::from(u16::MAX), Dry);
+    }
+
+    #[test]
+    fn moist_enters_wet_past_the_hysteresis() {
+        assert_eq!(MoistureLevel::classify(1069, Some(Moist)), Moist);
+        assert_eq!(MoistureLevel::classify(1003, Some(Moist)), Moist); // 0.8496
+        assert_eq!(MoistureLevel::classify(1002, Some(Moist)), Wet); // 0.8504
+    }
+
+    #[test]
+    fn wet_leaves_past_the_hysteresis() {
+        assert_eq!(MoistureLevel::classify(1071, Some(Wet)), Wet);
+        assert_eq!(MoistureLevel::classify(1137, Some(Wet)), Wet); // 0.7504
+        assert_eq!(MoistureLevel::classify(1138, Some(Wet)), Moist); // 0.7496
+    }
+
+    #[test]
+    fn moist_enters_dry_past_the_hysteresis() {
+        assert_eq!(MoistureLevel::classify(1948, Some(Moist)), Moist);
+        assert_eq!(MoistureLevel::classify(2014, Some(Moist)), Moist); // 0.1007
+        assert_eq!(MoistureLevel::classify(2016, Some(Moist)), Dry); // 0.0993
+    }
+
+    #[test]
+    fn dry_leaves_past_the_hysteresis() {
+        assert_eq!(MoistureLevel::classify(1947, Some(Dry)), Dry);
+        assert_eq!(MoistureLevel::classify(1881, Some(Dry)), Dry); // 0.1993
+        assert_eq!(MoistureLevel::classify(1879, Some(Dry)), Moist); // 0.2007
+    }
+
+    #[test]
+    fn wet_and_dry_jump_past_moist() {
+        assert_eq!(MoistureLevel::classify(1948, Some(Wet)), Dry);
+        assert_eq!(MoistureLevel::classify(1069, Some(Dry)), Wet);
+    }
+
+    #[test]
+    fn overflow_is_detected_without_delay() {
+        assert!(!classify_overflow(OVERFLOW_THRESHOLD, false));
+        assert!(classify_overflow(OVERFLOW_THRESHOLD + 1, false));
+        assert!(!classify_overflow(2217, false));
+        assert!(classify_overflow(u16::MAX, false));
+    }
+
+    #[test]
+    fn overflow_clears_past_the_hysteresis() {
+        let clear_mv = OVERFLOW_THRESHOLD - OVERFLOW_HYSTERESIS_MV;
+        assert!(classify_overflow(OVERFLOW_THRESHOLD, true));
+        assert!(classify_overflow(clear_mv + 1, true));
+        assert!(!classify_overflow(clear_mv, true));
+        assert!(!classify_overflow(0, true));
+    }
+
+    #[test]
+    fn sensors_announce_distinct_topics() {
+        let topics: alloc::vec::Vec<_> = Sensor::all().map(|s| s.info().topic).collect();
+        for (i, topic) in topics.iter().enumerate() {
+            assert!(!topics[..i].contains(topic), "{topic} announced twice");
+        }
+        // Only sensors with a unit are recorded as measurements
+        assert_eq!(Sensor::SoilMoisture(MoistureLevel::Moist).info().unit, None);
+        assert_eq!(
+            Sensor::SoilTemperature(Probe(2), Tenths(0)).info(),
+            SensorInfo {
+                topic: "soiltemperature3",
+                name: "Soil temperature 3",
+                unit: Some("°C"),
+                device_class: Some("temperature"),
+                display_precision: Some(1),
+            }
+        );
+    }
+}
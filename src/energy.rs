@@ -0,0 +1,231 @@
+//! Wake-cycle timing and energy estimate
+//!
+//! The wake cycle enters a [`Phase`] at each step; the time spent per phase
+//! and the charge it drew at the configured `CURRENT_*` figures add up over
+//! the wake. Before deep sleep the totals go to RTC memory, and the next wake
+//! that reaches MQTT publishes the breakdown with an estimate of the daily
+//! consumption, so changes to the awake window, sensor warmups or WiFi
+//! settings can be compared in HA. The figures are estimates: the phases are
+//! timed, the currents are not measured.
+
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+use esp_hal::time::Instant;
+use log::info;
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+use crate::{
+    LAST_WAKE_ENERGY,
+    config::{
+        CURRENT_ACTIVE_MA, CURRENT_DEEP_SLEEP_UA, CURRENT_PUMP_MA, CURRENT_WIFI_CONNECT_MA,
+        CURRENT_WIFI_CONNECTED_MA,
+    },
+};
+
+const PHASES: usize = Phase::Shutdown as usize + 1;
+
+/// Steps of the wake cycle, in the order they usually run.
+#[derive(Debug, Clone, Copy, PartialEq, EnumIter)]
+pub enum Phase {
+    Boot,            // From app start to the first sensor read
+    Sensors,         // DHT11 and the early battery sample, radio off
+    WifiLink,        // Association; the ADC reads finish alongside
+    Dhcp,            // Waiting for an address
+    Display,         // Display init and the status screen
+    ClockSync,       // Asking the time server
+    FirmwareCheck,   // Fetching the OTA manifest
+    MqttConnect,     // Connecting, subscribing and reconnect backoff
+    Publish,         // Readings and diagnostics
+    CommandWindow,   // Listening for commands until the awake deadline
+    Pump,            // A pump run, the radio stays on
+    FirmwareInstall, // Downloading and writing an update
+    Maintenance,     // Maintenance mode, readings refreshed live
+    Shutdown,        // Log shipping, WiFi teardown, entering deep sleep
+}
+
+impl Phase {
+    /// Key in the published breakdown
+    pub fn key(&self) -> &'static str {
+        match self {
+            Phase::Boot => "boot",
+            Phase::Sensors => "sensors",
+            Phase::WifiLink => "wifi_link",
+            Phase::Dhcp => "dhcp",
+            Phase::Display => "display",
+            Phase::ClockSync => "clock_sync",
+            Phase::FirmwareCheck => "firmware_check",
+            Phase::MqttConnect => "mqtt_connect",
+            Phase::Publish => "publish",
+            Phase::CommandWindow => "command_window",
+            Phase::Pump => "pump",
+            Phase::FirmwareInstall => "firmware_install",
+            Phase::Maintenance => "maintenance",
+            Phase::Shutdown => "shutdown",
+        }
+    }
+
+    /// Estimated supply current (mA). Phases that also run without WiFi
+    /// (low battery, safe mode) depend on whether the radio is on.
+    fn current_ma(&self, radio_on: bool) -> u32 {
+        match self {
+            Phase::Boot | Phase::Sensors => CURRENT_ACTIVE_MA,
+            Phase::WifiLink | Phase::Dhcp => CURRENT_WIFI_CONNECT_MA,
+            Phase::Pump => CURRENT_WIFI_CONNECTED_MA + CURRENT_PUMP_MA,
+            Phase::Display | Phase::Shutdown if !radio_on => CURRENT_ACTIVE_MA,
+            _ => CURRENT_WIFI_CONNECTED_MA,
+        }
+    }
+}
+
+/// Time and charge per phase of one wake, as kept in RTC memory until it is
+/// published.
+#[derive(Clone, Copy)]
+pub struct WakeEnergy {
+    phase_ms: [u32; PHASES],
+    /// Charge per phase in nAh
+    phase_nah: [u32; PHASES],
+    /// The ADC reads that overlap the WiFi link phase
+    sensors_finish_ms: u32,
+    sleep_seconds: u32,
+    unpublished: bool,
+}
+
+impl WakeEnergy {
+    pub const EMPTY: Self = Self {
+        phase_ms: [0; PHASES],
+        phase_nah: [0; PHASES],
+        sensors_finish_ms: 0,
+        sleep_seconds: 0,
+        unpublished: false,
+    };
+
+    /// Time (ms) and charge (µAh) of the phases the wake went through
+    pub fn phases(&self) -> impl Iterator<Item = (Phase, u32, u32)> + '_ {
+        Phase::iter()
+            .zip(self.phase_ms.iter().zip(&self.phase_nah))
+            .filter(|(_, (ms, _))| **ms > 0)
+            .map(|(phase, (&ms, &nah))| (phase, ms, nah.div_ceil(1000)))
+    }
+
+    pub fn sensors_finish_ms(&self) -> u32 {
+        self.sensors_finish_ms
+    }
+
+    pub fn awake_ms(&self) -> u32 {
+        self.phase_ms.iter().sum()
+    }
+
+    /// Charge of the awake part of the wake in µAh
+    pub fn wake_uah(&self) -> u32 {
+        self.wake_nah().div_ceil(1000) as u32
+    }
+
+    pub fn sleep_seconds(&self) -> u32 {
+        self.sleep_seconds
+    }
+
+    /// Daily consumption in µAh if every wake were like this one and followed
+    /// by the same deep sleep.
+    pub fn uah_per_day(&self) -> u32 {
+        let sleep_nah =
+            u64::from(CURRENT_DEEP_SLEEP_UA) * u64::from(self.sleep_seconds) * 1000 / 3600;
+        let cycle_ms = u64::from(self.awake_ms()) + u64::from(self.sleep_seconds) * 1000;
+        if cycle_ms == 0 {
+            return 0;
+        }
+        ((self.wake_nah() + sleep_nah) * 86_400_000 / cycle_ms / 1000) as u32
+    }
+
+    fn wake_nah(&self) -> u64 {
+        self.phase_nah.iter().map(|&nah| u64::from(nah)).sum()
+    }
+}
+
+/// The phase the wake cycle is in, and what the wake has used so far
+struct Timeline {
+    phase: Phase,
+    since: Instant,
+    radio_on: bool,
+    wifi_started: Option<Instant>,
+    wake: WakeEnergy,
+}
+
+impl Timeline {
+    /// Add the time since the last phase change to the current phase.
+    fn close(&mut self, now: Instant) {
+        let elapsed = now - self.since;
+        let index = self.phase as usize;
+        self.wake.phase_ms[index] += elapsed.as_millis() as u32;
+        self.wake.phase_nah[index] +=
+            (elapsed.as_micros() * u64::from(self.phase.current_ma(self.radio_on)) / 3600) as u32;
+        self.since = now;
+    }
+}
+
+static TIMELINE: Mutex<RefCell<Timeline>> = Mutex::new(RefCell::new(Timeline {
+    phase: Phase::Boot,
+    since: Instant::EPOCH,
+    radio_on: false,
+    wifi_started: None,
+    wake: WakeEnergy::EMPTY,
+}));
+
+/// Close the current phase and start `phase`. Entering the same phase again
+/// later adds to its total.
+pub fn enter(phase: Phase) {
+    let now = Instant::now();
+    critical_section::with(|cs| {
+        let mut timeline = TIMELINE.borrow_ref_mut(cs);
+        timeline.close(now);
+        timeline.phase = phase;
+        if phase == Phase::WifiLink {
+            timeline.radio_on = true;
+            timeline.wifi_started = Some(now);
+        }
+    });
+}
+
+/// The ADC reads started together with the WiFi link phase are done.
+pub fn sensors_finished() {
+    let now = Instant::now();
+    critical_section::with(|cs| {
+        let mut timeline = TIMELINE.borrow_ref_mut(cs);
+        if let Some(started) = timeline.wifi_started {
+            timeline.wake.sensors_finish_ms = (now - started).as_millis() as u32;
+        }
+    });
+}
+
+/// Close the wake right before deep sleep and keep its totals in RTC memory
+/// for the next wake to publish.
+pub fn finish(sleep_seconds: u64) {
+    let wake = critical_section::with(|cs| {
+        let mut timeline = TIMELINE.borrow_ref_mut(cs);
+        timeline.close(Instant::now());
+        timeline.wake
+    });
+    info!(
+        "Awake {}ms, ~{}µAh this wake",
+        wake.awake_ms(),
+        wake.wake_uah()
+    );
+    LAST_WAKE_ENERGY.set(WakeEnergy {
+        sleep_seconds: sleep_seconds as u32,
+        unpublished: true,
+        ..wake
+    });
+}
+
+/// The last wake's totals, if they haven't been published yet.
+pub fn unpublished() -> Option<WakeEnergy> {
+    Some(LAST_WAKE_ENERGY.get()).filter(|wake| wake.unpublished)
+}
+
+/// Call once the broker has acknowledged the breakdown.
+pub fn mark_published() {
+    let mut wake = LAST_WAKE_ENERGY.get();
+    wake.unpublished = false;
+    LAST_WAKE_ENERGY.set(wake);
+}
@@ -0,0 +1,279 @@
+//! Sample filters
+//!
+//! Each wake takes `SENSOR_SAMPLE_COUNT` samples per quantity and reduces
+//! them to one value with the quantity's [`Filter`] from `SENSOR_FILTERS`.
+//! The median and the trimmed mean work on the samples of one wake; the EMA
+//! also carries a smoothed value across wakes in RTC memory, which steadies
+//! a noisy probe at the cost of following real changes over a few wakes.
+
+use crate::{
+    SENSOR_FILTER_STATE,
+    config::{DEFAULT_SENSOR_FILTER, SENSOR_FILTERS},
+    domain::Quantity,
+};
+
+/// Fewer samples than this don't make a value
+const MIN_SAMPLES: usize = 3;
+/// Fixed-point scale of the EMA state, so small steps don't round away
+const EMA_SCALE: i64 = 1000;
+
+/// How the samples of one wake are reduced to the published value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    /// Middle sample, or the mean of the middle two
+    Median,
+    /// Mean after dropping the `trim` lowest and `trim` highest samples
+    TrimmedMean { trim: usize },
+    /// Trimmed mean (trim 1) of the wake, moved towards by `weight_percent`
+    /// from the average of the previous wakes
+    Ema { weight_percent: u8 },
+}
+
+impl Filter {
+    /// The filter configured for `quantity`
+    pub fn of(quantity: Quantity) -> Self {
+        SENSOR_FILTERS
+            .iter()
+            .find(|(q, _)| *q == quantity)
+            .map_or(DEFAULT_SENSOR_FILTER, |(_, filter)| *filter)
+    }
+
+    /// Value of one wake's samples, before any smoothing across wakes.
+    /// Sorts the samples. Returns `None` if too few samples are present.
+    pub fn apply<T>(&self, samples: &mut [T]) -> Option<T>
+    where
+        T: Copy + Ord + Into<i32>,
+        i32: TryInto<T>,
+    {
+        match self {
+            Filter::Median => median(samples),
+            Filter::TrimmedMean { trim } => trimmed_mean(samples, *trim),
+            Filter::Ema { .. } => trimmed_mean(samples, 1),
+        }
+    }
+}
+
+/// Middle sample, or the mean of the middle two rounded to the nearest
+/// integer (halves up).
+///
+/// Returns `None` if fewer than 3 samples are present.
+pub fn median<T>(samples: &mut [T]) -> Option<T>
+where
+    T: Copy + Ord + Into<i32>,
+    i32: TryInto<T>,
+{
+    if samples.len() < MIN_SAMPLES {
+        return None;
+    }
+    samples.sort_unstable();
+    let middle = samples.len() / 2;
+    if samples.len() % 2 == 1 {
+        Some(samples[middle])
+    } else {
+        mean(&samples[middle - 1..=middle])
+    }
+}
+
+/// Mean of the samples after removing the `trim` lowest and `trim` highest,
+/// rounded to the nearest integer (halves up).
+///
+/// Returns `None` if fewer than 3 samples are present, or no sample is left
+/// after trimming.
+pub fn trimmed_mean<T>(samples: &mut [T], trim: usize) -> Option<T>
+where
+    T: Copy + Ord + Into<i32>,
+    i32: TryInto<T>,
+{
+    if samples.len() < MIN_SAMPLES || samples.len() <= 2 * trim {
+        return None;
+    }
+    samples.sort_unstable();
+    mean(&samples[trim..samples.len() - trim])
+}
+
+fn mean<T>(samples: &[T]) -> Option<T>
+where
+    T: Copy + Into<i32>,
+    i32: TryInto<T>,
+{
+    // Summed in i64 so extreme samples can't overflow
+    let sum: i64 = samples.iter().map(|&x| i64::from(x.into())).sum();
+    let count = samples.len() as i64;
+    // floor(sum / count + 1/2); div_euclid rounds negative averages the same way
+    let mean = (2 * sum + count).div_euclid(2 * count);
+    i32::try_from(mean).ok()?.try_into().ok()
+}
+
+/// Exponential moving average across wakes, in fixed point
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ema(i64);
+
+impl Ema {
+    pub fn new(value: i32) -> Self {
+        Self(i64::from(value) * EMA_SCALE)
+    }
+
+    /// Move `weight_percent` of the way from the average to `value`.
+    pub fn update(self, value: i32, weight_percent: u8) -> Self {
+        let step = (i64::from(value) * EMA_SCALE - self.0) * i64::from(weight_percent.min(100));
+        Self(self.0 + step.div_euclid(100))
+    }
+
+    /// The average, rounded to the nearest integer
+    pub fn value(&self) -> i32 {
+        (self.0 + EMA_SCALE / 2).div_euclid(EMA_SCALE) as i32
+    }
+}
+
+/// Fold the value of this wake into the quantity's EMA in RTC memory and
+/// return the smoothed value. Other filters return `value` unchanged.
+pub fn smooth(quantity: Quantity, value: i32) -> i32 {
+    let Some(ema) = next_average(quantity, value) else {
+        return value;
+    };
+    let mut state = SENSOR_FILTER_STATE.get();
+    state[quantity.index()] = Some(ema);
+    SENSOR_FILTER_STATE.set(state);
+    ema.value()
+}
+
+/// The value [`smooth`] would return, without storing the average: a
+/// maintenance refresh every few seconds would otherwise move it many steps
+/// within one wake.
+pub fn preview(quantity: Quantity, value: i32) -> i32 {
+    next_average(quantity, value).map_or(value, |ema| ema.value())
+}
+
+/// The quantity's EMA with `value` folded in, `None` for other filters
+fn next_average(quantity: Quantity, value: i32) -> Option<Ema> {
+    let Filter::Ema { weight_percent } = Filter::of(quantity) else {
+        return None;
+    };
+    let previous = SENSOR_FILTER_STATE.get()[quantity.index()];
+    Some(previous.map_or(Ema::new(value), |ema| ema.update(value, weight_percent)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn median_of_odd_and_even_counts() {
+        assert_eq!(median(&mut [5, 1, 3]), Some(3));
+        // Mean of the middle two, halves rounded up
+        assert_eq!(median(&mut [1, 2, 3, 4]), Some(3));
+        assert_eq!(median(&mut [-4, -3, -2, -1]), Some(-2));
+        assert_eq!(median::<i32>(&mut [1, 2]), None);
+        assert_eq!(median::<i32>(&mut []), None);
+    }
+
+    #[test]
+    fn median_ignores_outliers() {
+        assert_eq!(median(&mut [1500, 1502, 2900, 1498, 1501]), Some(1501));
+        assert_eq!(
+            median(&mut [1500, i32::MAX, 1502, i32::MIN, 1501]),
+            Some(1501)
+        );
+        assert_eq!(
+            median(&mut [i32::MAX, i32::MAX, i32::MAX, i32::MAX]),
+            Some(i32::MAX)
+        );
+    }
+
+    #[test]
+    fn trimmed_mean_drops_the_extremes() {
+        assert_eq!(trimmed_mean(&mut [1, 1, 100, 1, 1], 1), Some(1));
+        assert_eq!(trimmed_mean(&mut [10, 20, 30, 40, 50], 1), Some(30));
+        assert_eq!(
+            trimmed_mean(&mut [1480, 1510, 2900, 1495, 1500], 1),
+            Some(1502)
+        );
+        assert_eq!(trimmed_mean(&mut [1, 2, 3, 4, 100], 2), Some(3));
+        assert_eq!(
+            trimmed_mean(&mut [1500, i32::MAX, 1500, i32::MIN, 1500], 1),
+            Some(1500)
+        );
+    }
+
+    #[test]
+    fn trimmed_mean_rounds_to_nearest() {
+        assert_eq!(trimmed_mean(&mut [1, 2, 4], 0), Some(2));
+        assert_eq!(trimmed_mean(&mut [0, 1, 2, 2, 9], 1), Some(2));
+        assert_eq!(trimmed_mean(&mut [-9, -2, -2, -1, 0], 1), Some(-2));
+        assert_eq!(trimmed_mean::<i8>(&mut [-5, -3, -1], 1), Some(-3));
+    }
+
+    #[test]
+    fn trimmed_mean_needs_samples_left() {
+        assert_eq!(trimmed_mean::<i32>(&mut [1, 2], 0), None);
+        assert_eq!(trimmed_mean(&mut [1, 2, 3, 4], 2), None);
+        assert_eq!(trimmed_mean::<i32>(&mut [], 1), None);
+    }
+
+    #[test]
+    fn mean_of_extremes_does_not_overflow() {
+        assert_eq!(
+            trimmed_mean(&mut [i32::MAX, i32::MAX, i32::MAX], 0),
+            Some(i32::MAX)
+        );
+        assert_eq!(
+            trimmed_mean(&mut [i32::MIN, i32::MIN, i32::MIN], 0),
+            Some(i32::MIN)
+        );
+    }
+
+    #[test]
+    fn ema_starts_at_the_first_sample() {
+        assert_eq!(Ema::new(1500).value(), 1500);
+        assert_eq!(Ema::new(-35).value(), -35);
+        assert_eq!(Ema::new(i32::MAX).value(), i32::MAX);
+    }
+
+    #[test]
+    fn ema_moves_by_the_weight() {
+        let ema = Ema::new(1500).update(1600, 40);
+        assert_eq!(ema.value(), 1540);
+        let ema = ema.update(1600, 40);
+        assert_eq!(ema.value(), 1564);
+        assert_eq!(ema.update(1400, 40).value(), 1498);
+        assert_eq!(Ema::new(1500).update(2000, 100).value(), 2000);
+        assert_eq!(Ema::new(1500).update(2000, 0).value(), 1500);
+        // Weights above 100 % don't overshoot
+        assert_eq!(Ema::new(1500).update(2000, 250).value(), 2000);
+    }
+
+    #[test]
+    fn ema_damps_an_outlier() {
+        let ema = Ema::new(1500).update(2900, 40);
+        assert_eq!(ema.value(), 2060);
+        assert_eq!(ema.update(1500, 40).value(), 1836);
+        // The fixed point state has room for the extremes of a sample
+        assert_eq!(Ema::new(1500).update(i32::MAX, 40).value(), 858_994_359);
+        assert_eq!(Ema::new(i32::MAX).update(i32::MIN, 100).value(), i32::MIN);
+    }
+
+    #[test]
+    fn smooth_stores_and_preview_does_not() {
+        SENSOR_FILTER_STATE.set([None; Quantity::COUNT]);
+        let moisture = Quantity::SoilMoisture;
+        assert_eq!(Filter::of(moisture), Filter::Ema { weight_percent: 40 });
+
+        assert_eq!(smooth(moisture, 1500), 1500);
+        assert_eq!(preview(moisture, 1600), 1540);
+        assert_eq!(preview(moisture, 1600), 1540);
+        assert_eq!(smooth(moisture, 1600), 1540);
+        assert_eq!(smooth(moisture, 1600), 1564);
+    }
+
+    #[test]
+    fn other_filters_pass_values_through() {
+        SENSOR_FILTER_STATE.set([None; Quantity::COUNT]);
+        let battery = Quantity::BatteryVoltage;
+        assert_eq!(Filter::of(battery), DEFAULT_SENSOR_FILTER);
+
+        assert_eq!(smooth(battery, 3900), 3900);
+        assert_eq!(smooth(battery, 3700), 3700);
+        assert_eq!(preview(battery, 3800), 3800);
+        assert_eq!(SENSOR_FILTER_STATE.get()[battery.index()], None);
+    }
+}
@@ -0,0 +1,170 @@
+//! Daily light integral
+//!
+//! Each illuminance reading is converted to an estimated photosynthetic
+//! photon flux density (PPFD, `LUX_PER_PPFD` lx per µmol/m²/s) and
+//! integrated over the time since the previous reading, averaging the two
+//! readings, into the day's light in mol/m². The sum lives in RTC memory
+//! across deep sleep and restarts at local midnight once the clock is known;
+//! before that it counts from power-on. The figure is an estimate: the hourly
+//! readings stand in for the light in between, and the lx to PPFD factor
+//! depends on the light source.
+
+use crate::{
+    LIGHT_INTEGRAL, clock,
+    config::{LIGHT_MAX_INTERVAL_SECONDS, LUX_PER_PPFD},
+    domain::Tenths,
+};
+
+/// Light accumulated today, as kept in RTC memory
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightIntegral {
+    /// Illuminance integrated over time, lx·ms
+    lux_ms: u64,
+    /// Illuminance and `clock::now_us` of the last reading
+    last: Option<(u32, u64)>,
+    /// Local day the sum belongs to, once the clock is known
+    day: Option<u64>,
+}
+
+impl LightIntegral {
+    pub const EMPTY: Self = Self {
+        lux_ms: 0,
+        last: None,
+        day: None,
+    };
+
+    /// Add the light since the previous reading. A new local day starts a new
+    /// sum; an interval longer than `LIGHT_MAX_INTERVAL_SECONDS` (the clock
+    /// was just set, or a reading was missed for long) is left out.
+    pub fn record(self, lux: u32, now_us: u64, day: Option<u64>) -> Self {
+        let Self { mut lux_ms, .. } = self.on_day(day);
+        if let Some((last_lux, last_us)) = self.last {
+            let elapsed_ms = now_us.saturating_sub(last_us) / 1000;
+            if elapsed_ms <= LIGHT_MAX_INTERVAL_SECONDS * 1000 {
+                lux_ms += (u64::from(last_lux) + u64::from(lux)) * elapsed_ms / 2;
+            }
+        }
+        Self {
+            lux_ms,
+            last: Some((lux, now_us)),
+            day: day.or(self.day),
+        }
+    }
+
+    /// The sum as of local `day`: empty once a new day has started
+    pub fn on_day(self, day: Option<u64>) -> Self {
+        let lux_ms = match (day, self.day) {
+            (Some(day), Some(last_day)) if day != last_day => 0,
+            _ => self.lux_ms,
+        };
+        Self {
+            lux_ms,
+            day: day.or(self.day),
+            ..self
+        }
+    }
+
+    /// Light so far today in mol/m²
+    pub fn mol_per_m2(&self) -> Tenths {
+        // lx·ms / (lx per µmol/m²/s) is in nmol/m²; 10^8 of them are a tenth
+        let divisor = u64::from(LUX_PER_PPFD) * 100_000_000;
+        Tenths(((self.lux_ms + divisor / 2) / divisor) as i16)
+    }
+}
+
+/// Fold an illuminance reading into the integral in RTC memory and return
+/// the light so far today.
+pub fn record(lux: u32) -> Tenths {
+    let integral = next_integral(lux);
+    LIGHT_INTEGRAL.set(integral);
+    integral.mol_per_m2()
+}
+
+/// The value [`record`] would return, without storing the reading: a
+/// maintenance refresh every few seconds would otherwise add each interval
+/// again at the next wake.
+pub fn preview(lux: u32) -> Tenths {
+    next_integral(lux).mol_per_m2()
+}
+
+/// The light so far today without a new reading, for a faulted one
+pub fn today() -> Tenths {
+    LIGHT_INTEGRAL.get().on_day(clock::local_day()).mol_per_m2()
+}
+
+fn next_integral(lux: u32) -> LightIntegral {
+    LIGHT_INTEGRAL
+        .get()
+        .record(lux, clock::now_us(), clock::local_day())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR_US: u64 = 3_600_000_000;
+    /// 2025-10-09 in Unix days, and the wall clock at its start
+    const DAY: u64 = 20_370;
+    const DAY_START_US: u64 = DAY * 24 * HOUR_US;
+
+    #[test]
+    fn integrates_the_average_of_two_readings() {
+        let integral = LightIntegral::EMPTY.record(12000, 0, None);
+        assert_eq!(integral.mol_per_m2(), Tenths(0));
+        // 12 klx is 222 µmol/m²/s, 0.8 mol/m² in an hour
+        let integral = integral.record(12000, HOUR_US, None);
+        assert_eq!(integral.mol_per_m2(), Tenths(8));
+        // Falling to dark counts as 6 klx for the second hour
+        let integral = integral.record(0, 2 * HOUR_US, None);
+        assert_eq!(integral.mol_per_m2(), Tenths(12));
+    }
+
+    #[test]
+    fn long_intervals_are_left_out() {
+        let max_us = LIGHT_MAX_INTERVAL_SECONDS * 1_000_000;
+        let integral = LightIntegral::EMPTY.record(12000, 0, None);
+        assert_eq!(
+            integral.record(12000, max_us + 1000, None).mol_per_m2(),
+            Tenths(0)
+        );
+        assert_eq!(
+            integral.record(12000, max_us, None).mol_per_m2(),
+            Tenths(24)
+        );
+        // A clock running backwards adds nothing
+        let integral = LightIntegral::EMPTY.record(12000, HOUR_US, None);
+        assert_eq!(integral.record(12000, 0, None).mol_per_m2(), Tenths(0));
+    }
+
+    #[test]
+    fn setting_the_clock_keeps_the_sum() {
+        // Counting from power-on without a clock
+        let integral = LightIntegral::EMPTY
+            .record(12000, 0, None)
+            .record(12000, HOUR_US, None);
+        // The jump to the wall clock is left out, the day adopted without a reset
+        let integral = integral.record(12000, DAY_START_US + 12 * HOUR_US, Some(DAY));
+        assert_eq!(integral.mol_per_m2(), Tenths(8));
+        let integral = integral.record(12000, DAY_START_US + 13 * HOUR_US, Some(DAY));
+        assert_eq!(integral.mol_per_m2(), Tenths(16));
+        // A wake that lost the day keeps the sum of the one it had
+        let integral = integral.record(12000, DAY_START_US + 14 * HOUR_US, None);
+        assert_eq!(integral.mol_per_m2(), Tenths(24));
+        assert_eq!(integral.on_day(None), integral);
+    }
+
+    #[test]
+    fn a_new_local_day_restarts_the_sum() {
+        let integral = LightIntegral::EMPTY
+            .record(12000, DAY_START_US + 22 * HOUR_US, Some(DAY))
+            .record(12000, DAY_START_US + 23 * HOUR_US, Some(DAY));
+        assert_eq!(integral.mol_per_m2(), Tenths(8));
+        assert_eq!(integral.on_day(Some(DAY)).mol_per_m2(), Tenths(8));
+        assert_eq!(integral.on_day(Some(DAY + 1)).mol_per_m2(), Tenths(0));
+        // The hour across midnight goes to the new day
+        let integral = integral.record(12000, DAY_START_US + 24 * HOUR_US, Some(DAY + 1));
+        assert_eq!(integral.mol_per_m2(), Tenths(8));
+        let integral = integral.record(12000, DAY_START_US + 25 * HOUR_US, Some(DAY + 1));
+        assert_eq!(integral.mol_per_m2(), Tenths(16));
+    }
+}
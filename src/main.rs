@@ -8,16 +8,25 @@
 
 use alloc::format;
 use config::{
-    AWAKE_DURATION_SECONDS, DEEP_SLEEP_DURATION_SECONDS, LOW_BATTERY_CUTOFF_MV,
+    AWAKE_DURATION_SECONDS, COMMAND_GRACE_MS, DEEP_SLEEP_DURATION_SECONDS, EARLY_SLEEP_DEFAULT,
+    LOW_BATTERY_CUTOFF_MV, MOISTURE_CALIBRATION_MAX_WAKES, MQTT_RECONNECT_BACKOFF_MAX_MS,
+    MQTT_RECONNECT_BACKOFF_START_MS, NTP_TIMEOUT_MS, OTA_AUTO_INSTALL, OTA_CHECK_INTERVAL_WAKES,
+    OTA_SOCKET_TIMEOUT_SECONDS, OTA_TIMEOUT_SECONDS, PUMP_LOW_BATTERY_CUTOFF_MV,
+    SAFE_MODE_SLEEP_SECONDS, SYSLOG_DEFAULT_LEVEL, SYSLOG_TIMEOUT_MS, WATCHDOG_MARGIN_SECONDS,
     WIFI_CONNECT_TIMEOUT_SECONDS,
 };
+use crash::CrashReport;
 use display::{Display, DisplayPeripherals, DisplayTrait};
-use domain::Sensor;
+use domain::{
+    Button, MoistureCalibration, MoistureLevel, PumpOutcome, PumpRun, PumpState, Quantity, Sensor,
+    SensorData,
+};
 use embassy_executor::Spawner;
 use embassy_futures::join::join;
-use embassy_time::{Delay, Duration, Instant, Timer, with_timeout};
+use embassy_net::Stack;
+use embassy_time::{Delay, Duration, Instant, Timer, with_deadline, with_timeout};
+use energy::{Phase, WakeEnergy};
 use esp_alloc::{heap_allocator, psram_allocator};
-use esp_backtrace as _;
 use esp_hal::{
     Config,
     clock::CpuClock,
@@ -26,14 +35,19 @@ use esp_hal::{
     ram,
     rng::Rng,
     rtc_cntl::{SocResetReason, wakeup_cause},
-    system::{SleepSource, reset_reason},
+    system::{SleepSource, reset_reason, software_reset},
     timer::timg::TimerGroup,
 };
-use esp_println::logger::init_logger;
 use esp_radio::wifi::WifiError;
 use esp_rtos::main;
-use log::{error, info, warn};
+use filter::Ema;
+use light::LightIntegral;
+use log::{LevelFilter, error, info, warn};
+use maintenance::Maintenance;
+use mqtt::{Command, MqttResources, MqttSession};
+use ota::Ota;
 use pump::run_pump;
+use reset_history::ResetHistory;
 use rtc_memory::RtcCell;
 use sensors::SensorPeripherals;
 use sleep::enter_deep;
@@ -41,14 +55,25 @@ use wifi::{WIFI_SIGNAL, connect_to_wifi};
 
 extern crate alloc;
 
+mod climate;
+mod clock;
 mod config;
+mod crash;
 mod display;
 mod domain;
+mod energy;
+mod filter;
+mod light;
+mod maintenance;
 mod mqtt;
+mod ota;
 mod pump;
+mod reset_history;
 mod rtc_memory;
 mod sensors;
 mod sleep;
+mod syslog;
+mod watchdog;
 mod wifi;
 
 /// Stored boot count between deep sleep cycles
@@ -65,11 +90,106 @@ pub(crate) static BOOT_COUNT: RtcCell<u32> = RtcCell::new(0);
 #[ram(unstable(rtc_fast))]
 pub(crate) static DISCOVERY_MESSAGES_SENT: RtcCell<bool> = RtcCell::new(false);
 
-esp_bootloader_esp_idf::esp_app_desc!();
+/// Number of completed pump runs since the RTC memory was last cleared
+///
+/// Placed in RTC Fast memory and published with every pump outcome.
+#[ram(unstable(rtc_fast))]
+pub(crate) static PUMP_RUN_COUNT: RtcCell<u32> = RtcCell::new(0);
+
+/// Total pump runtime in seconds since the RTC memory was last cleared
+///
+/// Placed in RTC Fast memory and published with every pump outcome.
+#[ram(unstable(rtc_fast))]
+pub(crate) static PUMP_RUNTIME_SECONDS: RtcCell<u32> = RtcCell::new(0);
+
+/// Raw soil moisture range captured since calibration was started from HA
+///
+/// Placed in RTC Fast memory so the capture spans wake cycles. `None` until
+/// the calibration button is pressed, and again once it is stopped or has
+/// run for `MOISTURE_CALIBRATION_MAX_WAKES`.
+#[ram(unstable(rtc_fast))]
+pub(crate) static MOISTURE_CALIBRATION: RtcCell<Option<MoistureCalibration>> = RtcCell::new(None);
+
+/// Boots of a freshly installed firmware image that haven't confirmed it by
+/// publishing to MQTT
+///
+/// Placed in persistent RTC Fast memory, as a bad image is most likely to end
+/// in brownout or watchdog resets, which reload the regular RTC statics. The
+/// image is rolled back once this reaches `OTA_MAX_VERIFY_WAKES`.
+#[ram(unstable(rtc_fast, persistent))]
+pub(crate) static OTA_UNVERIFIED_WAKES: RtcCell<u32> = RtcCell::new(0);
+
+/// Level for shipping logs to `SYSLOG_HOST`, set from the HA select. Placed
+/// in RTC Fast memory, so records logged before MQTT connects use the level
+/// of the previous wake.
+#[ram(unstable(rtc_fast))]
+pub(crate) static SYSLOG_LEVEL: RtcCell<LevelFilter> = RtcCell::new(SYSLOG_DEFAULT_LEVEL);
+
+/// Leave the command window once no command has arrived for
+/// `COMMAND_GRACE_MS`, set from the HA switch. Placed in RTC Fast memory.
+#[ram(unstable(rtc_fast))]
+pub(crate) static EARLY_SLEEP: RtcCell<bool> = RtcCell::new(EARLY_SLEEP_DEFAULT);
+
+/// Time and estimated charge per phase of the last wake, waiting to be
+/// published by the next one. Placed in RTC Fast memory.
+#[ram(unstable(rtc_fast))]
+pub(crate) static LAST_WAKE_ENERGY: RtcCell<WakeEnergy> = RtcCell::new(WakeEnergy::EMPTY);
+
+/// Light accumulated since local midnight and the last illuminance reading.
+/// Placed in RTC Fast memory so the sum spans the day's wakes.
+#[ram(unstable(rtc_fast))]
+pub(crate) static LIGHT_INTEGRAL: RtcCell<LightIntegral> = RtcCell::new(LightIntegral::EMPTY);
+
+/// Averages of the quantities filtered with an EMA, one slot per quantity.
+/// Placed in RTC Fast memory so the average spans wakes.
+#[ram(unstable(rtc_fast))]
+pub(crate) static SENSOR_FILTER_STATE: RtcCell<[Option<Ema>; Quantity::COUNT]> =
+    RtcCell::new([None; Quantity::COUNT]);
+
+/// Moisture level published on the last wake, the reference for the
+/// hysteresis of the next. Placed in RTC Fast memory; `None` after power-on.
+#[ram(unstable(rtc_fast))]
+pub(crate) static MOISTURE_LEVEL: RtcCell<Option<MoistureLevel>> = RtcCell::new(None);
+
+/// Whether the last wake found water in the overflow, the reference for the
+/// hysteresis of the next. Placed in RTC Fast memory.
+#[ram(unstable(rtc_fast))]
+pub(crate) static OVERFLOW_DETECTED: RtcCell<bool> = RtcCell::new(false);
+
+/// The last panic, waiting to be published to `{DEVICE_ID}/crash`. Placed in
+/// persistent RTC Fast memory, which is only cleared on power-on, so it also
+/// survives the software reset after a firmware update.
+#[ram(unstable(rtc_fast, persistent))]
+pub(crate) static CRASH_REPORT: RtcCell<CrashReport> = RtcCell::new(CrashReport::EMPTY);
+
+/// Panics since power-on, published with each crash report. Placed in
+/// persistent RTC Fast memory like `CRASH_REPORT`; cleared on power-on.
+#[ram(unstable(rtc_fast, persistent))]
+pub(crate) static CRASH_COUNT: RtcCell<u32> = RtcCell::new(0);
+
+/// Recent resets other than wakes from deep sleep, and the current run of
+/// brownout/watchdog resets. Placed in persistent RTC Fast memory, as those
+/// resets reload the regular RTC statics; cleared on power-on.
+#[ram(unstable(rtc_fast, persistent))]
+pub(crate) static RESET_HISTORY: RtcCell<ResetHistory> = RtcCell::new(ResetHistory::EMPTY);
+
+// The app descriptor version carries the commit hash and is reported to HA as
+// `sw_version`; OTA compares the plain crate version.
+esp_bootloader_esp_idf::esp_app_desc!(
+    concat!(env!("CARGO_PKG_VERSION"), "+", env!("GIT_HASH")),
+    env!("CARGO_PKG_NAME"),
+    esp_bootloader_esp_idf::BUILD_TIME,
+    esp_bootloader_esp_idf::BUILD_DATE,
+    esp_bootloader_esp_idf::ESP_IDF_COMPATIBLE_VERSION,
+    esp_bootloader_esp_idf::MMU_PAGE_SIZE,
+    0,
+    u16::MAX,
+    esp_bootloader_esp_idf::SECURE_VERSION
+);
 
 #[main]
 async fn main(spawner: Spawner) {
-    init_logger(log::LevelFilter::Info);
+    syslog::init();
 
     // Why the last reset happened. A SysBrownOut / watchdog / CoreSw reason
     // (rather than CoreDeepSleep) means a previous cycle crashed instead of
@@ -78,12 +198,48 @@ async fn main(spawner: Spawner) {
     let reset_reason = reset_reason();
     info!("Reset reason: {:?}", reset_reason);
 
+    // Persistent RTC memory holds garbage after power-on; the crash report
+    // has a checksum, its counter doesn't.
+    if reset_reason == Some(SocResetReason::ChipPowerOn) {
+        CRASH_COUNT.set(0);
+    }
+
     let boot_count = BOOT_COUNT.get();
     info!("Current boot count = {}", boot_count);
     BOOT_COUNT.set(boot_count + 1);
 
+    // Smoothing is off while calibrating, so a forgotten calibration must
+    // not run forever.
+    if let Some(calibration) = MOISTURE_CALIBRATION.get() {
+        let calibration = calibration.next_wake();
+        if calibration.is_none() {
+            info!("Moisture calibration ended after {MOISTURE_CALIBRATION_MAX_WAKES} wakes");
+        }
+        MOISTURE_CALIBRATION.set(calibration);
+    }
+
+    // A device that keeps browning out (or hanging) under radio load only
+    // digs its battery deeper by trying again every hour.
+    let reset_history = reset_history::record(reset_reason, boot_count);
+    let safe_mode = reset_history.in_reset_loop();
+    if safe_mode {
+        warn!(
+            "{} brownout/watchdog resets in a row — safe mode, no WiFi/pump",
+            reset_history.fault_streak()
+        );
+    }
+
     let peripherals = esp_hal::init(Config::default().with_cpu_clock(CpuClock::_80MHz));
 
+    // Every phase of the cycle from here on runs against the watchdog, so a
+    // hung driver can't keep the radio or the pump on.
+    watchdog::start(peripherals.LPWR);
+
+    // Settle the OTA state of the running image first: a new image that
+    // keeps failing is rolled back here, before it gets another chance.
+    let mut ota = Ota::new(peripherals.FLASH);
+    ota.check_running_image(reset_reason);
+
     heap_allocator!(#[unsafe(link_section = ".dram2_uninit")] size: 73744);
 
     psram_allocator!(peripherals.PSRAM, esp_hal::psram);
@@ -118,31 +274,77 @@ async fn main(spawner: Spawner) {
 
     // see https://github.com/Xinyuan-LilyGO/T-Display-S3/blob/main/image/T-DISPLAY-S3.jpg
     let sensor_peripherals = SensorPeripherals {
-        dht11_digital_pin: peripherals.GPIO1,
+        dht_digital_pin: peripherals.GPIO1,
         battery_pin: peripherals.GPIO4,
         moisture_analog_pin: peripherals.GPIO2,
         moisture_power_pin: peripherals.GPIO16,
         water_level_analog_pin: peripherals.GPIO3,
         water_level_power_pin: peripherals.GPIO21,
         adc1: peripherals.ADC1,
+        #[cfg(feature = "i2c")]
+        i2c0: peripherals.I2C0,
+        #[cfg(feature = "i2c")]
+        i2c_sda_pin: peripherals.GPIO17,
+        #[cfg(feature = "i2c")]
+        i2c_scl_pin: peripherals.GPIO18,
+        #[cfg(feature = "ds18b20")]
+        onewire_pin: peripherals.GPIO10,
     };
 
+    // Holding the wake button through the start of the wake asks for
+    // maintenance mode; a short press returns right away.
+    let mut wake_up_btn_pin = peripherals.GPIO14;
+    let button_held = !safe_mode
+        && matches!(wakeup_cause(), SleepSource::Ext0)
+        && maintenance::button_held(wake_up_btn_pin.reborrow()).await;
+
     // The wake cycle is fallible, but the device always goes back to sleep:
     // a failed cycle (router down, broker unreachable) retries in an hour
     // instead of boot-looping with the radio on.
-    if let Err(error) = run_cycle(
-        spawner,
-        peripherals.WIFI,
-        display_peripherals,
-        sensor_peripherals,
-        &mut pump_pin,
-        boot_count,
-        reset_reason,
-    )
-    .await
-    {
+    let result = if safe_mode {
+        watchdog::feed("safe mode");
+        energy::enter(Phase::Display);
+        show_safe_mode(
+            display_peripherals,
+            reset_reason,
+            reset_history.fault_streak(),
+        )
+    } else {
+        run_cycle(
+            spawner,
+            peripherals.WIFI,
+            display_peripherals,
+            sensor_peripherals,
+            &mut pump_pin,
+            &mut ota,
+            BootInfo {
+                count: boot_count,
+                reset_reason,
+                button_held,
+            },
+        )
+        .await
+    };
+    if let Err(error) = result {
         error!("Error while running wake cycle: {error:?}");
     }
+    energy::enter(Phase::Shutdown);
+
+    // Ship the log of this wake while the link is still up, including how
+    // the cycle ended.
+    if let Some(stack) = wifi::stack() {
+        watchdog::feed("syslog");
+        if let Err(error) = with_timeout(
+            Duration::from_millis(SYSLOG_TIMEOUT_MS),
+            syslog::flush(stack),
+        )
+        .await
+        .unwrap_or(Err(syslog::Error::Timeout))
+        {
+            warn!("Log shipping failed: {error}");
+        }
+    }
+    watchdog::feed("sleep");
 
     info!("Request to disconnect wifi");
     WIFI_SIGNAL.signal(());
@@ -150,25 +352,59 @@ async fn main(spawner: Spawner) {
     // set power pin to low to save power
     power_pin.set_low();
 
-    let deep_sleep_duration = Duration::from_secs(DEEP_SLEEP_DURATION_SECONDS);
-    info!("Enter deep sleep for {}s", DEEP_SLEEP_DURATION_SECONDS);
+    let deep_sleep_seconds = if safe_mode {
+        SAFE_MODE_SLEEP_SECONDS
+    } else {
+        DEEP_SLEEP_DURATION_SECONDS
+    };
+    let deep_sleep_duration = Duration::from_secs(deep_sleep_seconds);
+    info!("Enter deep sleep for {}s", deep_sleep_seconds);
     // Give the USB CDC logger time to flush pending output before powering down
     Timer::after(Duration::from_millis(100)).await;
-    let mut wake_up_btn_pin = peripherals.GPIO14;
-    enter_deep(&mut wake_up_btn_pin, peripherals.LPWR, deep_sleep_duration);
+    energy::finish(deep_sleep_seconds);
+    enter_deep(
+        &mut wake_up_btn_pin,
+        &mut watchdog::stop(),
+        deep_sleep_duration,
+    );
+}
+
+/// Safe-mode cycle after a reset loop: no WiFi and no pump, only a message
+/// on the display.
+fn show_safe_mode(
+    display_peripherals: DisplayPeripherals,
+    reset_reason: Option<SocResetReason>,
+    fault_streak: u32,
+) -> Result<(), Error> {
+    let mut display = Display::new(display_peripherals, Delay, false)?;
+    display.write_multiline(&format!(
+        "SAFE MODE\n{fault_streak} resets in a row\nLast: {reset_reason:?}\nSleeping {}h",
+        SAFE_MODE_SLEEP_SECONDS / 3600
+    ))?;
+    display.enable_powersave()?;
+    Ok(())
+}
+
+/// Why and how often the device has booted, shown on button wake.
+struct BootInfo {
+    count: u32,
+    reset_reason: Option<SocResetReason>,
+    /// The wake button was held through the start of the wake: start
+    /// maintenance mode
+    button_held: bool,
 }
 
 /// One linear wake cycle: connect WiFi while sampling sensors, show the
 /// readings, publish to MQTT, then listen for pump commands until the awake
-/// window closes.
+/// window closes. A requested firmware install closes a cycle that published.
 async fn run_cycle(
     spawner: Spawner,
     wifi: WIFI<'static>,
     display_peripherals: DisplayPeripherals,
     sensor_peripherals: SensorPeripherals,
     pump_pin: &mut Output<'static>,
-    boot_count: u32,
-    reset_reason: Option<SocResetReason>,
+    ota: &mut Ota,
+    boot: BootInfo,
 ) -> Result<(), Error> {
     // Everything in the cycle works against one deadline: whatever time WiFi,
     // sensors and publishing don't use remains as the MQTT command window.
@@ -176,29 +412,36 @@ async fn run_cycle(
 
     let button_wake = matches!(wakeup_cause(), SleepSource::Ext0);
 
+    watchdog::feed("sensors");
+    energy::enter(Phase::Sensors);
+
     // Pre-radio phase: read the timing-sensitive DHT11 and an early battery
     // sample *before* the WiFi radio is powered on, so radio interrupts can't
     // corrupt the bit-banged DHT11 read and we know the battery state before
     // committing to WiFi/pump current draw.
-    let readout = sensors::begin_read(sensor_peripherals).await;
+    let mut readout = sensors::begin_read(sensor_peripherals).await;
+    let battery_mv = readout.battery_mv;
 
     // Low-battery guard: a weak LiPo browns out under radio/pump current spikes,
     // causing a reset loop that drains it further. Skip WiFi and pump, show the
     // readings, and sleep.
-    if let Some(battery_mv) = readout.battery_mv
+    if let Some(battery_mv) = battery_mv
         && battery_mv < LOW_BATTERY_CUTOFF_MV
     {
         warn!(
             "Battery {}mV below cutoff {}mV — skipping WiFi/pump this cycle",
             battery_mv, LOW_BATTERY_CUTOFF_MV
         );
-        let sensor_data = sensors::finish_read(readout).await;
+        let sensor_data = sensors::finish_read(&mut readout).await;
+        energy::enter(Phase::Display);
         let mut display = Display::new(display_peripherals, Delay, button_wake)?;
         display.write_multiline(&format!("LOW BATTERY {battery_mv}mV\n{sensor_data}"))?;
         display.enable_powersave()?;
         return Ok(());
     }
 
+    watchdog::feed("wifi");
+    energy::enter(Phase::WifiLink);
     let rng = Rng::new();
     let seed = (rng.random() as u64) << 32 | rng.random() as u64;
 
@@ -209,18 +452,21 @@ async fn run_cycle(
             Duration::from_secs(WIFI_CONNECT_TIMEOUT_SECONDS),
             connect_to_wifi(wifi, seed, spawner),
         ),
-        sensors::finish_read(readout),
+        async {
+            let sensor_data = sensors::finish_read(&mut readout).await;
+            energy::sensors_finished();
+            sensor_data
+        },
     )
     .await;
     let stack = stack.map_err(|_| Error::WifiTimeout)??;
 
-    // Overflow state is established before MQTT ever connects, so a retained
-    // ON command can never race the interlock.
-    let pump_allowed = !sensor_data
-        .data
-        .iter()
-        .any(|e| matches!(e, Sensor::OverflowDetected(true)));
+    // Pump interlocks are established before MQTT ever connects, so a
+    // retained ON command can never race them.
+    let pump_blocked = pump_interlock(&sensor_data, battery_mv);
 
+    watchdog::feed("display");
+    energy::enter(Phase::Display);
     let mut display = Display::new(display_peripherals, Delay, button_wake)?;
 
     let mut status = format!("{sensor_data}");
@@ -228,7 +474,7 @@ async fn run_cycle(
         if let Some(stack_config) = stack.config_v4() {
             status = format!(
                 "Reset: {:?}\nClient IP: {}\nBoot count: {}\n{}",
-                reset_reason, stack_config.address, boot_count, status
+                boot.reset_reason, stack_config.address, boot.count, status
             );
         } else {
             error!("Failed to get stack config");
@@ -236,21 +482,136 @@ async fn run_cycle(
     }
     display.write_multiline(&status)?;
 
-    let mut session = mqtt::connect(stack).await?;
-    session.publish(&sensor_data).await?;
-    session.subscribe_to_pump_commands().await?;
+    // Pump runs are timestamped and the daily light integral restarts at
+    // local midnight, which needs the time; the RTC keeps it through deep
+    // sleep once it is set.
+    if clock::needs_sync(boot.count) {
+        watchdog::feed("clock sync");
+        energy::enter(Phase::ClockSync);
+        if let Err(error) = with_timeout(Duration::from_millis(NTP_TIMEOUT_MS), clock::sync(stack))
+            .await
+            .unwrap_or(Err(clock::Error::Timeout))
+        {
+            warn!("Clock sync failed: {error}");
+        }
+    }
 
-    // Keep listening until the deadline so a switch flipped while the device
-    // is awake still works; the retained ON from the sleep period arrives
-    // right after subscribing.
+    // Ask the update server for the latest firmware before connecting, so the
+    // HA update entity is refreshed together with the readings.
+    watchdog::feed("firmware check");
+    energy::enter(Phase::FirmwareCheck);
+    let manifest_url = ota::manifest_url();
+    let latest_firmware = match manifest_url {
+        Some(url) if button_wake || boot.count % OTA_CHECK_INTERVAL_WAKES == 0 => {
+            match with_timeout(
+                Duration::from_secs(OTA_SOCKET_TIMEOUT_SECONDS),
+                ota::latest_version(stack, url),
+            )
+            .await
+            .unwrap_or(Err(ota::Error::Timeout))
+            {
+                Ok(version) => Some(version),
+                Err(error) => {
+                    error!("Firmware check failed: {error}");
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+    let report = Report {
+        sensor_data: &sensor_data,
+        latest_firmware: latest_firmware.as_deref(),
+        button_wake,
+    };
+
+    // A broker restart or dropped TCP connection is retried with backoff for
+    // as long as the awake window allows, instead of costing an hour of data
+    // and a pending watering. Readings are replayed until one session has
+    // published them.
+    watchdog::feed("mqtt");
+    let resources = mqtt::resources();
+    let mut published = false;
+    let mut install_requested = false;
+    let mut pump = PumpControl {
+        pin: pump_pin,
+        blocked: pump_blocked,
+        battery_mv,
+        wake: boot.count,
+    };
+    let mut window = CommandWindow {
+        deadline,
+        maintenance: Maintenance::new(&mut readout, &mut display),
+    };
+    if boot.button_held {
+        window.maintenance.start();
+    }
+    let mut backoff_ms = MQTT_RECONNECT_BACKOFF_START_MS;
     loop {
-        match session.wait_for_pump_command(pump_allowed, deadline).await {
-            Ok(true) => run_pump(pump_pin).await,
-            Ok(false) => break, // awake window over
+        match run_mqtt_session(
+            stack,
+            resources,
+            &report,
+            &mut published,
+            &mut pump,
+            &mut install_requested,
+            &mut window,
+        )
+        .await
+        {
+            Ok(()) => break, // awake window over
             Err(error) => {
-                // No reconnect: the next wake is in an hour anyway.
-                error!("MQTT error during command window: {error}");
-                break;
+                error!("MQTT session failed: {error}");
+                let retry_at = Instant::now() + Duration::from_millis(backoff_ms);
+                if retry_at >= window.deadline() {
+                    if published {
+                        break;
+                    }
+                    return Err(error.into());
+                }
+                info!("Reconnecting to MQTT in {}ms", backoff_ms);
+                // Maintenance extends the deadline well past the watchdog
+                // budget of one phase
+                watchdog::feed("mqtt reconnect");
+                Timer::at(retry_at).await;
+                backoff_ms = (backoff_ms * 2).min(MQTT_RECONNECT_BACKOFF_MAX_MS);
+            }
+        }
+    }
+
+    if published {
+        ota.confirm();
+
+        let install = install_requested
+            || (OTA_AUTO_INSTALL && report.latest_firmware.is_some_and(ota::is_newer));
+        // Only a confirmed image on a healthy battery installs an update: the
+        // download keeps the radio on for up to OTA_TIMEOUT_SECONDS.
+        if install && ota.is_pending_verify() {
+            warn!("Firmware install skipped: running image not verified yet");
+        } else if install && battery_mv.is_some_and(|mv| mv < PUMP_LOW_BATTERY_CUTOFF_MV) {
+            warn!("Firmware install skipped: battery low");
+        } else if let Some(manifest_url) = manifest_url
+            && install
+        {
+            watchdog::feed_for(
+                "firmware install",
+                Duration::from_secs(OTA_TIMEOUT_SECONDS + WATCHDOG_MARGIN_SECONDS),
+            );
+            energy::enter(Phase::FirmwareInstall);
+            let result = with_timeout(
+                Duration::from_secs(OTA_TIMEOUT_SECONDS),
+                ota.update(stack, manifest_url),
+            )
+            .await
+            .unwrap_or(Err(ota::Error::Timeout));
+            match result {
+                Ok(true) => {
+                    display.write_multiline("Firmware updated\nRebooting")?;
+                    Timer::after(Duration::from_millis(100)).await;
+                    software_reset();
+                }
+                Ok(false) => {}
+                Err(error) => error!("Firmware update failed: {error}"),
             }
         }
     }
@@ -259,6 +620,238 @@ async fn run_cycle(
     Ok(())
 }
 
+/// What the first MQTT session of the cycle publishes.
+struct Report<'a> {
+    sensor_data: &'a SensorData,
+    /// Latest version from the OTA manifest, if it was checked this wake
+    latest_firmware: Option<&'a str>,
+    /// Woken by the button: fire the HA device trigger
+    button_wake: bool,
+}
+
+/// What the command window needs to act on an accepted pump command.
+struct PumpControl<'a> {
+    pin: &'a mut Output<'static>,
+    /// Interlock established before MQTT connected, updated by maintenance
+    /// refreshes; `None` lets the pump run.
+    blocked: Option<PumpOutcome>,
+    /// Early battery sample the interlock was established with
+    battery_mv: Option<u16>,
+    /// Boot count of this wake, reported with the outcome.
+    wake: u32,
+}
+
+/// When the command window closes. Maintenance mode keeps it open past the
+/// awake deadline.
+struct CommandWindow<'a> {
+    /// End of the normal awake window
+    deadline: Instant,
+    maintenance: Maintenance<'a>,
+}
+
+impl CommandWindow<'_> {
+    /// The awake deadline, extended while maintenance mode runs
+    fn deadline(&self) -> Instant {
+        self.maintenance
+            .until()
+            .map_or(self.deadline, |until| until.max(self.deadline))
+    }
+
+    /// When to stop waiting for commands. In maintenance mode that is the
+    /// next refresh. With early sleep, the window closes `COMMAND_GRACE_MS`
+    /// after subscribing or the last command: retained commands arrive right
+    /// away, and a typical wake has none. A button wake keeps the full window,
+    /// as someone is at the device and may still use HA.
+    fn end(&self, button_wake: bool) -> Instant {
+        if let Some(next_refresh) = self.maintenance.next_refresh() {
+            next_refresh
+        } else if EARLY_SLEEP.get() && !button_wake {
+            self.deadline
+                .min(Instant::now() + Duration::from_millis(COMMAND_GRACE_MS))
+        } else {
+            self.deadline
+        }
+    }
+
+    /// The energy phase while waiting for commands
+    fn phase(&self) -> Phase {
+        if self.maintenance.is_active() {
+            Phase::Maintenance
+        } else {
+            Phase::CommandWindow
+        }
+    }
+}
+
+/// Why the pump must not run with these readings: water in the overflow, a
+/// faulted moisture or overflow probe, or a battery too weak for the motor
+/// current.
+fn pump_interlock(sensor_data: &SensorData, battery_mv: Option<u16>) -> Option<PumpOutcome> {
+    if sensor_data
+        .data
+        .iter()
+        .any(|e| matches!(e, Sensor::OverflowDetected(true)))
+    {
+        Some(PumpOutcome::BlockedOverflow)
+    } else if [Quantity::SoilMoisture, Quantity::WaterLevel]
+        .into_iter()
+        .any(|quantity| sensor_data.fault(quantity).is_some())
+    {
+        Some(PumpOutcome::BlockedSensorFault)
+    } else if battery_mv.is_some_and(|mv| mv < PUMP_LOW_BATTERY_CUTOFF_MV) {
+        Some(PumpOutcome::BlockedLowBattery)
+    } else {
+        None
+    }
+}
+
+/// One MQTT connection: connect, publish the report (and the wake button
+/// trigger) unless an earlier session of this cycle already did, then act on
+/// commands until the window closes: run the pump unless an interlock blocks
+/// it and report the outcome, handle an HA button press or a setting, or note
+/// a firmware install for the end of the cycle. In maintenance mode the
+/// readings are refreshed in between. Connecting, publishing and subscribing
+/// are bounded by the deadline; a pump run never is, so it can't be cut short.
+async fn run_mqtt_session(
+    stack: Stack<'static>,
+    resources: &mut MqttResources,
+    report: &Report<'_>,
+    published: &mut bool,
+    pump: &mut PumpControl<'_>,
+    install_requested: &mut bool,
+    window: &mut CommandWindow<'_>,
+) -> Result<(), mqtt::Error> {
+    energy::enter(Phase::MqttConnect);
+    let mut session = with_deadline(window.deadline(), async {
+        let mut session = mqtt::connect(stack, resources).await?;
+        if !*published {
+            energy::enter(Phase::Publish);
+            session
+                .publish(report.sensor_data, report.latest_firmware)
+                .await?;
+            if report.button_wake {
+                session.publish_wake_button_trigger().await?;
+            }
+            *published = true;
+        }
+        session.subscribe_to_commands().await?;
+        Ok::<_, mqtt::Error>(session)
+    })
+    .await
+    .map_err(|_| mqtt::Error::Timeout)??;
+    energy::enter(window.phase());
+    if window.maintenance.is_active() {
+        session.publish_maintenance(true).await?;
+    }
+
+    // Keep listening until the deadline so a switch flipped while the device
+    // is awake still works; the retained ON from the sleep period arrives
+    // right after subscribing. Early sleep closes the window sooner,
+    // maintenance mode keeps it open longer.
+    loop {
+        match session
+            .wait_for_command(window.end(report.button_wake))
+            .await?
+        {
+            Some(command) => {
+                handle_command(&mut session, pump, install_requested, window, command).await?
+            }
+            None if window.maintenance.is_active() => {
+                watchdog::feed("maintenance");
+                // Testing the pump can fill the saucer, so the interlock
+                // follows the new readings.
+                if let Some(sensor_data) = window.maintenance.refresh(&mut session).await? {
+                    pump.blocked = pump_interlock(&sensor_data, pump.battery_mv);
+                }
+            }
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Act on one command from the command window.
+async fn handle_command(
+    session: &mut MqttSession<'_>,
+    pump: &mut PumpControl<'_>,
+    install_requested: &mut bool,
+    window: &mut CommandWindow<'_>,
+    command: Command,
+) -> Result<(), mqtt::Error> {
+    match command {
+        Command::RunPump => {
+            let time = clock::unix_seconds();
+            let (outcome, duration) = match pump.blocked {
+                Some(outcome) => {
+                    warn!("Pump command blocked: {}", outcome.reason());
+                    session.publish_pump_state(PumpState::Blocked).await?;
+                    (outcome, Duration::from_secs(0))
+                }
+                None => {
+                    session.publish_pump_state(PumpState::Running).await?;
+                    watchdog::feed("pump");
+                    energy::enter(Phase::Pump);
+                    let duration = run_pump(pump.pin).await;
+                    watchdog::feed("mqtt");
+                    energy::enter(window.phase());
+                    session.publish_pump_state(PumpState::Idle).await?;
+                    (PumpOutcome::Ran, duration)
+                }
+            };
+            let run = PumpRun {
+                outcome,
+                duration_secs: duration.as_secs(),
+                time,
+                wake: pump.wake,
+                run_count: PUMP_RUN_COUNT.get(),
+                total_runtime_secs: PUMP_RUNTIME_SECONDS.get(),
+            };
+            session.publish_pump_run(&run).await?;
+        }
+        Command::Press(button) => {
+            info!("Button pressed: {}", button.name());
+            match button {
+                Button::ResendDiscovery => session.resend_discovery().await?,
+                Button::Reboot => {
+                    // The retained press is already cleared, so this can't
+                    // turn into a reboot loop.
+                    Timer::after(Duration::from_millis(100)).await;
+                    software_reset();
+                }
+                Button::CalibrateMoisture => {
+                    MOISTURE_CALIBRATION.set(Some(MoistureCalibration::EMPTY))
+                }
+                Button::StopCalibration => MOISTURE_CALIBRATION.set(None),
+                Button::ClearHistory => {
+                    PUMP_RUN_COUNT.set(0);
+                    PUMP_RUNTIME_SECONDS.set(0);
+                }
+            }
+        }
+        // The download needs the radio for longer than the command window
+        // allows; it runs once the window has closed.
+        Command::InstallFirmware => *install_requested = true,
+        Command::SetMaintenance(enabled) => {
+            if enabled {
+                window.maintenance.start();
+            } else {
+                window.maintenance.stop();
+            }
+            session.publish_maintenance(enabled).await?;
+        }
+        Command::SetEarlySleep(enabled) => {
+            info!("Early sleep: {}", enabled);
+            EARLY_SLEEP.set(enabled);
+            session.publish_early_sleep(enabled).await?;
+        }
+        Command::SetLogLevel(level) => {
+            info!("Remote log level: {}", syslog::level_name(level));
+            syslog::set_level(level);
+            session.publish_log_level(level).await?;
+        }
+    }
+    Ok(())
+}
+
 #[derive(Debug)]
 enum Error {
     Wifi(WifiError),
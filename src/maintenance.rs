@@ -0,0 +1,132 @@
+//! Maintenance mode
+//!
+//! Calibrating the moisture probe, testing the pump or watching the sensors
+//! needs the device online for minutes, not for one command window. In
+//! maintenance mode the command window stays open for up to
+//! `MAINTENANCE_MAX_SECONDS`, and every `MAINTENANCE_REFRESH_SECONDS` the
+//! sensors are read again, shown on the display and published. It is started
+//! from the HA **Maintenance mode** switch or by holding the wake button
+//! through the wake, and ends when the switch is turned off or the time is up.
+
+use alloc::format;
+
+use embassy_time::{Duration, Instant, Timer};
+use esp_hal::{
+    gpio::{Input, InputConfig, Pull},
+    peripherals::GPIO14,
+};
+use log::{info, warn};
+
+use crate::{
+    config::{MAINTENANCE_HOLD_MS, MAINTENANCE_MAX_SECONDS, MAINTENANCE_REFRESH_SECONDS},
+    display::DisplayTrait,
+    domain::SensorData,
+    energy::{self, Phase},
+    mqtt::{self, MqttSession},
+    sensors::{self, SensorReadout},
+};
+
+/// The sensors and display maintenance mode refreshes, and its schedule.
+pub struct Maintenance<'a> {
+    sensors: &'a mut SensorReadout,
+    display: &'a mut dyn DisplayTrait,
+    active: Option<Schedule>,
+}
+
+#[derive(Clone, Copy)]
+struct Schedule {
+    until: Instant,
+    next_refresh: Instant,
+}
+
+impl<'a> Maintenance<'a> {
+    pub fn new(sensors: &'a mut SensorReadout, display: &'a mut dyn DisplayTrait) -> Self {
+        Self {
+            sensors,
+            display,
+            active: None,
+        }
+    }
+
+    /// Start maintenance, or keep it going if it already runs. The first
+    /// refresh is due right away.
+    pub fn start(&mut self) {
+        if self.active.is_some() {
+            return;
+        }
+        info!("Maintenance mode for up to {}s", MAINTENANCE_MAX_SECONDS);
+        let now = Instant::now();
+        self.active = Some(Schedule {
+            until: now + Duration::from_secs(MAINTENANCE_MAX_SECONDS),
+            next_refresh: now,
+        });
+        energy::enter(Phase::Maintenance);
+    }
+
+    pub fn stop(&mut self) {
+        if self.active.take().is_some() {
+            info!("Maintenance mode ended");
+            energy::enter(Phase::CommandWindow);
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.is_some()
+    }
+
+    /// When maintenance ends at the latest
+    pub fn until(&self) -> Option<Instant> {
+        self.active.map(|schedule| schedule.until)
+    }
+
+    /// When [`refresh`](Self::refresh) is due next
+    pub fn next_refresh(&self) -> Option<Instant> {
+        self.active
+            .map(|schedule| schedule.next_refresh.min(schedule.until))
+    }
+
+    /// Read, show and publish the sensors once the refresh is due. Returns
+    /// the new readings, or `None` when the time is up and maintenance ended.
+    pub async fn refresh(
+        &mut self,
+        session: &mut MqttSession<'_>,
+    ) -> Result<Option<SensorData>, mqtt::Error> {
+        let Some(schedule) = self.active.as_mut() else {
+            return Ok(None);
+        };
+        let now = Instant::now();
+        if now >= schedule.until {
+            self.stop();
+            session.publish_maintenance(false).await?;
+            return Ok(None);
+        }
+        schedule.next_refresh = now + Duration::from_secs(MAINTENANCE_REFRESH_SECONDS);
+        let remaining = (schedule.until - now).as_secs();
+
+        let sensor_data = sensors::read_again(self.sensors).await;
+        // The readings still go out if the display fails.
+        if let Err(error) = self
+            .display
+            .write_multiline(&format!("MAINTENANCE {remaining}s\n{sensor_data}"))
+        {
+            warn!("Display update failed: {error}");
+        }
+        session.publish_readings(&sensor_data).await?;
+        Ok(Some(sensor_data))
+    }
+}
+
+/// Whether the wake button is still held `MAINTENANCE_HOLD_MS` into a button
+/// wake. A short press returns as soon as the button is released.
+pub async fn button_held(pin: GPIO14<'_>) -> bool {
+    let button = Input::new(pin, InputConfig::default().with_pull(Pull::Up));
+    let held_until = Instant::now() + Duration::from_millis(MAINTENANCE_HOLD_MS);
+    while Instant::now() < held_until {
+        if button.is_high() {
+            return false;
+        }
+        Timer::after(Duration::from_millis(50)).await;
+    }
+    info!("Wake button held");
+    true
+}
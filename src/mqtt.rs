@@ -1,6 +1,7 @@
 use alloc::{
     format,
     string::{String, ToString},
+    vec::Vec,
 };
 use core::{num::NonZero, num::ParseIntError, str};
 use embassy_net::{
@@ -8,8 +9,10 @@ use embassy_net::{
     dns::{DnsQueryType, Error as DnsError},
     tcp::{ConnectError, TcpSocket},
 };
-use embassy_time::{Instant, with_deadline};
-use log::{error, info, warn};
+use embassy_time::{Duration, Instant, with_deadline, with_timeout};
+use esp_hal::rtc_cntl::SocResetReason;
+use heapless::Deque;
+use log::{LevelFilter, error, info, warn};
 use rust_mqtt::{
     Bytes,
     buffer::AllocBuffer,
@@ -21,24 +24,40 @@ use rust_mqtt::{
         },
     },
     config::{KeepAlive, SessionExpiryInterval},
-    types::{MqttBinary, MqttString, QoS, ReasonCode, TopicName},
+    types::{MqttBinary, MqttString, PacketIdentifier, QoS, ReasonCode, TopicFilter, TopicName},
 };
 use serde_json::{Value, json};
 use static_cell::StaticCell;
 use strum::IntoEnumIterator;
 
 use crate::{
-    DISCOVERY_MESSAGES_SENT,
+    CRASH_COUNT, DISCOVERY_MESSAGES_SENT, EARLY_SLEEP, ESP_APP_DESC, MOISTURE_CALIBRATION,
     config::{
-        DEVICE_ID, HOMEASSISTANT_DISCOVERY_TOPIC_PREFIX, HOMEASSISTANT_SENSOR_TOPIC,
-        HOMEASSISTANT_SWITCH_TOPIC, MQTT_PUBLISH_ENABLED,
+        DEVICE_ID, HOMEASSISTANT_BINARY_SENSOR_TOPIC, HOMEASSISTANT_BUTTON_TOPIC,
+        HOMEASSISTANT_DEVICE_AUTOMATION_TOPIC, HOMEASSISTANT_DISCOVERY_TOPIC_PREFIX,
+        HOMEASSISTANT_EVENT_TOPIC, HOMEASSISTANT_SELECT_TOPIC, HOMEASSISTANT_SENSOR_TOPIC,
+        HOMEASSISTANT_SWITCH_TOPIC, HOMEASSISTANT_UPDATE_TOPIC, MQTT_ACK_TIMEOUT_MS,
+        MQTT_PENDING_MESSAGES, MQTT_PUBLISH_ENABLED,
     },
-    domain::{Sensor, SensorData},
+    crash::{self, CrashReport},
+    domain::{Button, PumpOutcome, PumpRun, PumpState, Quantity, Sensor, SensorData},
+    energy::{self, WakeEnergy},
+    ota::{self, FIRMWARE_VERSION},
+    reset_history::{self, ResetHistory},
+    sensors, syslog,
 };
 
 const BUFFER_SIZE: usize = 4096;
 
-struct MqttResources {
+/// Payload of an HA button press, also used for the wake button trigger
+const BUTTON_PRESS_PAYLOAD: &str = "PRESS";
+
+/// Payload of Install on the HA update entity
+const FIRMWARE_INSTALL_PAYLOAD: &str = "install";
+
+/// Socket and client buffers, allocated once per boot and reused by every
+/// connection attempt of the wake cycle.
+pub struct MqttResources {
     rx_buffer: [u8; BUFFER_SIZE],
     tx_buffer: [u8; BUFFER_SIZE],
     alloc_buffer: AllocBuffer,
@@ -48,18 +67,54 @@ static RESOURCES: StaticCell<MqttResources> = StaticCell::new();
 
 type MqttClientImpl<'a> = Client<'a, TcpSocket<'a>, AllocBuffer, 1, 1, 1, 1>;
 
-pub struct MqttSession<'a>(MqttClientImpl<'a>);
+pub struct MqttSession<'a> {
+    client: MqttClientImpl<'a>,
+    /// Commands that arrived while a publish waited for its PUBACK. The
+    /// broker counts them as delivered, so they are handled by the next
+    /// [`wait_for_command`](Self::wait_for_command) instead of dropped.
+    pending: Deque<PendingMessage, MQTT_PENDING_MESSAGES>,
+}
+
+/// A message received on a command topic, copied out of the client buffer
+struct PendingMessage {
+    topic: String,
+    message: Vec<u8>,
+}
 
-/// Resolve the broker, open the TCP socket and connect the MQTT session.
-/// Called once per wake cycle — there is no reconnect loop; on failure the
-/// device simply sleeps and retries on the next wake.
-pub async fn connect(stack: Stack<'static>) -> Result<MqttSession<'static>, Error> {
-    let resources = RESOURCES.init(MqttResources {
+/// A command accepted during the command window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    /// The pump switch was turned ON
+    RunPump,
+    /// An HA button was pressed
+    Press(Button),
+    /// Install was pressed on the HA update entity
+    InstallFirmware,
+    /// The HA remote log level select changed
+    SetLogLevel(LevelFilter),
+    /// The HA early sleep switch changed
+    SetEarlySleep(bool),
+    /// The HA maintenance mode switch was turned on or off
+    SetMaintenance(bool),
+}
+
+/// Take the static MQTT buffers. Must be called only once per boot; each
+/// [`connect`] borrows them for the lifetime of its session.
+pub fn resources() -> &'static mut MqttResources {
+    RESOURCES.init(MqttResources {
         rx_buffer: [0u8; BUFFER_SIZE],
         tx_buffer: [0u8; BUFFER_SIZE],
         alloc_buffer: AllocBuffer,
-    });
+    })
+}
 
+/// Resolve the broker, open the TCP socket and connect the MQTT session.
+/// The session borrows `resources`; drop it before connecting again. Retrying
+/// is up to the caller (see `run_cycle`), which bounds it by the awake window.
+pub async fn connect<'a>(
+    stack: Stack<'a>,
+    resources: &'a mut MqttResources,
+) -> Result<MqttSession<'a>, Error> {
     let mut socket = TcpSocket::new(stack, &mut resources.rx_buffer, &mut resources.tx_buffer);
 
     let host_addr = stack
@@ -109,26 +164,57 @@ pub async fn connect(stack: Stack<'static>) -> Result<MqttSession<'static>, Erro
 
     info!("MQTT Broker connected");
 
-    Ok(MqttSession(client))
+    Ok(MqttSession {
+        client,
+        pending: Deque::new(),
+    })
 }
 
 impl MqttSession<'_> {
-    /// Publish discovery messages (first boot only) and the sensor state topics,
+    /// Publish discovery messages (first boot only), the sensor state topics,
+    /// the installed firmware version, the latest one if the OTA manifest was
+    /// checked this wake, a pending crash report and a changed reset history,
     /// honoring the MQTT_PUBLISH_ENABLED development gate.
-    pub async fn publish(&mut self, sensor_data: &SensorData) -> Result<(), Error> {
+    pub async fn publish(
+        &mut self,
+        sensor_data: &SensorData,
+        latest_firmware: Option<&str>,
+    ) -> Result<(), Error> {
         if !MQTT_PUBLISH_ENABLED {
             info!("MQTT publishing disabled, skipping");
             return Ok(());
         }
         self.publish_discovery_topics().await?;
-        self.publish_sensor_data(sensor_data).await
+        self.publish_sensor_data(sensor_data).await?;
+        self.publish_moisture_calibration().await?;
+        if ota::manifest_url().is_some() {
+            self.publish_installed_firmware().await?;
+        }
+        if let Some(latest_firmware) = latest_firmware {
+            self.publish_latest_firmware(latest_firmware).await?;
+        }
+        if let Some(report) = crash::pending() {
+            self.publish_crash_report(&report).await?;
+            crash::mark_published();
+        }
+        if let Some(history) = reset_history::unpublished() {
+            self.publish_reset_history(&history).await?;
+            reset_history::mark_published();
+        }
+        if let Some(wake) = energy::unpublished() {
+            self.publish_wake_energy(&wake).await?;
+            energy::mark_published();
+        }
+        Ok(())
     }
 
-    /// Subscribe to the pump command topic. The retained message is always
-    /// delivered on subscribe, so an ON set while the device was asleep is never
-    /// missed. Callers must establish the overflow state *before* subscribing.
-    pub async fn subscribe_to_pump_commands(&mut self) -> Result<(), Error> {
-        let pump_set_topic = pump_set_topic();
+    /// Subscribe to the command topics (pump switch and HA buttons) with one
+    /// `{DEVICE_ID}/+/set` wildcard. Retained messages are always delivered on
+    /// subscribe, so an ON or a button press made while the device was asleep
+    /// is never missed. Callers must establish the pump interlocks *before*
+    /// subscribing.
+    pub async fn subscribe_to_commands(&mut self) -> Result<(), Error> {
+        let command_topic_filter = command_topic("+");
 
         let sub_options = SubscriptionOptions {
             // Always deliver retained message on subscribe so a pending ON
@@ -136,43 +222,48 @@ impl MqttSession<'_> {
             retain_handling: RetainHandling::AlwaysSend,
             retain_as_published: false,
             no_local: false,
-            qos: QoS::AtMostOnce,
+            // QoS 1: the broker resends a command until the device has
+            // acknowledged it instead of fire-and-forget.
+            qos: QoS::AtLeastOnce,
             ..Default::default()
         };
 
-        let topic =
-            TopicName::new_unchecked(MqttString::try_from(pump_set_topic.as_str()).unwrap());
-        self.0.subscribe(topic.into(), sub_options).await?;
+        let topic = TopicFilter::new_unchecked(
+            MqttString::try_from(command_topic_filter.as_str()).unwrap(),
+        );
+        self.client.subscribe(topic, sub_options).await?;
 
-        info!("Subscribed to pump command topic: {}", pump_set_topic);
+        info!("Subscribed to command topics: {}", command_topic_filter);
         Ok(())
     }
 
-    /// Poll the broker for pump commands until `deadline`. Returns `Ok(true)` as
-    /// soon as an ON command is accepted (the switch is reset to OFF first), or
-    /// `Ok(false)` when the deadline passes without one.
-    pub async fn wait_for_pump_command(
-        &mut self,
-        pump_allowed: bool,
-        deadline: Instant,
-    ) -> Result<bool, Error> {
-        let pump_set_topic = pump_set_topic();
+    /// Poll the broker for commands until `deadline`. Returns `Ok(Some(_))` as
+    /// soon as a command is accepted (its retained message is cleared first),
+    /// or `Ok(None)` when the deadline passes without one. Commands queued
+    /// while awaiting a PUBACK are handled first. Whether the pump may
+    /// actually run is the caller's interlock decision.
+    pub async fn wait_for_command(&mut self, deadline: Instant) -> Result<Option<Command>, Error> {
         loop {
-            let Ok(event) = with_deadline(deadline, self.0.poll()).await else {
-                return Ok(false); // awake window over
+            // Clearing a command may queue the next one of a retained burst
+            if let Some(pending) = self.pending.pop_front() {
+                if let Some(command) = self
+                    .process_command(&pending.topic, &pending.message)
+                    .await?
+                {
+                    return Ok(Some(command));
+                }
+                continue;
+            }
+            let Ok(event) = with_deadline(deadline, self.client.poll()).await else {
+                return Ok(None); // awake window over
             };
             match event {
                 Ok(Event::Publish(e)) => {
-                    if self
-                        .process_pump_command(
-                            e.topic.as_ref().as_str(),
-                            e.message.as_ref(),
-                            &pump_set_topic,
-                            pump_allowed,
-                        )
+                    if let Some(command) = self
+                        .process_command(e.topic.as_ref().as_str(), e.message.as_ref())
                         .await?
                     {
-                        return Ok(true);
+                        return Ok(Some(command));
                     }
                 }
                 Ok(e) => info!("Received event {:?}", e),
@@ -184,11 +275,35 @@ impl MqttSession<'_> {
         }
     }
 
+    /// Clear the discovery flag and publish every discovery message again,
+    /// e.g. after the broker lost its retained messages.
+    pub async fn resend_discovery(&mut self) -> Result<(), Error> {
+        DISCOVERY_MESSAGES_SENT.set(false);
+        if !MQTT_PUBLISH_ENABLED {
+            info!("MQTT publishing disabled, skipping");
+            return Ok(());
+        }
+        self.publish_discovery_topics().await
+    }
+
+    /// Tell HA the physical wake button was pressed, firing the device
+    /// trigger automations can attach to.
+    pub async fn publish_wake_button_trigger(&mut self) -> Result<(), Error> {
+        let topic_name = wake_button_topic();
+        info!("Publishing wake button press to {}", topic_name);
+        let topic_ref = TopicReference::Name(TopicName::new_unchecked(
+            MqttString::try_from(topic_name.as_str()).unwrap(),
+        ));
+        let options = PublicationOptions::new(topic_ref).at_least_once();
+        self.publish_confirmed(&options, BUTTON_PRESS_PAYLOAD.as_bytes())
+            .await
+    }
+
     async fn publish_discovery_topics(&mut self) -> Result<(), Error> {
         if !DISCOVERY_MESSAGES_SENT.get() {
             info!("First run, sending discovery messages");
 
-            for s in Sensor::iter() {
+            for s in Sensor::all().filter(|s| sensors::is_fitted(s.quantity())) {
                 let (discovery_topic, message) = get_sensor_discovery(&s);
 
                 let topic_ref = TopicReference::Name(TopicName::new_unchecked(
@@ -196,20 +311,114 @@ impl MqttSession<'_> {
                 ));
                 let options = PublicationOptions::new(topic_ref).retain();
 
-                self.0
+                self.client
+                    .publish(&options, Bytes::Borrowed(message.as_bytes()))
+                    .await?;
+                info!("Discovery message sent for sensor: {}", s.info().name);
+            }
+
+            for quantity in Quantity::iter().filter(|&q| sensors::is_fitted(q)) {
+                let (discovery_topic, message) = get_problem_discovery(quantity);
+
+                let topic_ref = TopicReference::Name(TopicName::new_unchecked(
+                    MqttString::try_from(discovery_topic.as_str()).unwrap(),
+                ));
+                let options = PublicationOptions::new(topic_ref).retain();
+
+                self.client
+                    .publish(&options, Bytes::Borrowed(message.as_bytes()))
+                    .await?;
+            }
+
+            for b in Button::iter() {
+                let (discovery_topic, message) = get_button_discovery(&b);
+
+                let topic_ref = TopicReference::Name(TopicName::new_unchecked(
+                    MqttString::try_from(discovery_topic.as_str()).unwrap(),
+                ));
+                let options = PublicationOptions::new(topic_ref).retain();
+
+                self.client
+                    .publish(&options, Bytes::Borrowed(message.as_bytes()))
+                    .await?;
+                info!("Discovery message sent for button: {}", b.name());
+            }
+
+            for (discovery_topic, message) in [
+                get_pump_switch_discovery(),
+                get_pump_state_discovery(),
+                get_pump_last_run_discovery(),
+                get_pump_last_run_time_discovery(),
+                get_pump_event_discovery(),
+                get_pump_stat_discovery(
+                    "pump_run_count",
+                    "Pump runs",
+                    "{{ value_json.run_count }}",
+                    None,
+                ),
+                get_pump_stat_discovery(
+                    "pump_total_runtime",
+                    "Pump total runtime",
+                    "{{ value_json.total_runtime }}",
+                    Some(("duration", "s")),
+                ),
+                get_wake_button_trigger_discovery(),
+                get_moisture_calibration_discovery("min", "Moisture calibration min"),
+                get_moisture_calibration_discovery("max", "Moisture calibration max"),
+                get_early_sleep_discovery(),
+                get_maintenance_discovery(),
+                get_crash_discovery(),
+                get_reset_history_discovery(),
+                get_energy_discovery(
+                    "energy_per_day",
+                    "Estimated consumption",
+                    "{{ (value_json.uah_per_day / 1000) | round(1) }}",
+                    None,
+                    "mAh/d",
+                ),
+                get_energy_discovery(
+                    "awake_time",
+                    "Awake time",
+                    "{{ (value_json.awake_ms / 1000) | round(1) }}",
+                    Some("duration"),
+                    "s",
+                ),
+            ] {
+                let topic_ref = TopicReference::Name(TopicName::new_unchecked(
+                    MqttString::try_from(discovery_topic.as_str()).unwrap(),
+                ));
+                let options = PublicationOptions::new(topic_ref).retain();
+                self.client
+                    .publish(&options, Bytes::Borrowed(message.as_bytes()))
+                    .await?;
+            }
+
+            if ota::manifest_url().is_some() {
+                let (discovery_topic, message) = get_firmware_update_discovery();
+                let topic_ref = TopicReference::Name(TopicName::new_unchecked(
+                    MqttString::try_from(discovery_topic.as_str()).unwrap(),
+                ));
+                let options = PublicationOptions::new(topic_ref).retain();
+                self.client
                     .publish(&options, Bytes::Borrowed(message.as_bytes()))
                     .await?;
-                info!("Discovery message sent for sensor: {}", s.name());
             }
 
-            for (discovery_topic, message) in [get_pump_switch_discovery()] {
+            // The switch shows the mode in use (`EARLY_SLEEP_DEFAULT` after
+            // power-on) until it is first changed.
+            self.publish_early_sleep(EARLY_SLEEP.get()).await?;
+
+            if syslog::host().is_some() {
+                let (discovery_topic, message) = get_log_level_discovery();
                 let topic_ref = TopicReference::Name(TopicName::new_unchecked(
                     MqttString::try_from(discovery_topic.as_str()).unwrap(),
                 ));
                 let options = PublicationOptions::new(topic_ref).retain();
-                self.0
+                self.client
                     .publish(&options, Bytes::Borrowed(message.as_bytes()))
                     .await?;
+                // The select shows the level in use until it is first changed.
+                self.publish_log_level(syslog::level()).await?;
             }
 
             DISCOVERY_MESSAGES_SENT.set(true);
@@ -219,56 +428,458 @@ impl MqttSession<'_> {
         Ok(())
     }
 
-    /// Returns true when an ON command was accepted and the pump should run.
-    async fn process_pump_command(
+    /// Publish the outcome of an accepted pump command to the last-run topic,
+    /// feeding both the last-run sensor and the HA event entity. `time` is
+    /// left out while the clock isn't set.
+    pub async fn publish_pump_run(&mut self, run: &PumpRun) -> Result<(), Error> {
+        let mut message = json!({
+            "outcome": run.outcome.as_str(),
+            "reason": run.outcome.reason(),
+            "duration": run.duration_secs,
+            "wake": run.wake,
+            "run_count": run.run_count,
+            "total_runtime": run.total_runtime_secs,
+        });
+        if let Some(time) = run.time {
+            message["time"] = json!(time);
+        }
+        let message = message.to_string();
+        let topic_name = pump_last_run_topic();
+
+        info!(
+            "Publishing to topic {}, message: {}",
+            topic_name.as_str(),
+            message.as_str()
+        );
+
+        let topic_ref = TopicReference::Name(TopicName::new_unchecked(
+            MqttString::try_from(topic_name.as_str()).unwrap(),
+        ));
+        let options = PublicationOptions::new(topic_ref).at_least_once();
+        self.publish_confirmed(&options, message.as_bytes()).await
+    }
+
+    /// Report the running firmware to the HA update entity on every wake, so
+    /// it follows an install or a rollback right away. Retained, as HA may
+    /// restart while the device sleeps.
+    async fn publish_installed_firmware(&mut self) -> Result<(), Error> {
+        let message = json!({
+            "installed_version": FIRMWARE_VERSION,
+            "title": "ESP32 firmware",
+        })
+        .to_string();
+        self.publish_firmware_topic(firmware_topic(), message).await
+    }
+
+    /// Report the latest version offered by the OTA manifest to the HA update
+    /// entity, on the wakes that checked it. An older manifest is never
+    /// installed, so it offers no update.
+    async fn publish_latest_firmware(&mut self, latest_firmware: &str) -> Result<(), Error> {
+        let latest_firmware = if ota::is_newer(latest_firmware) {
+            latest_firmware
+        } else {
+            FIRMWARE_VERSION
+        };
+        let message = json!({ "latest_version": latest_firmware }).to_string();
+        self.publish_firmware_topic(firmware_latest_topic(), message)
+            .await
+    }
+
+    async fn publish_firmware_topic(
+        &mut self,
+        topic_name: String,
+        message: String,
+    ) -> Result<(), Error> {
+        info!(
+            "Publishing to topic {}, message: {}",
+            topic_name.as_str(),
+            message.as_str()
+        );
+
+        let topic_ref = TopicReference::Name(TopicName::new_unchecked(
+            MqttString::try_from(topic_name.as_str()).unwrap(),
+        ));
+        let options = PublicationOptions::new(topic_ref).retain().at_least_once();
+        self.publish_confirmed(&options, message.as_bytes()).await
+    }
+
+    /// Publish the last panic once, with the crash counter. Retained, so HA
+    /// still shows the last crash after a restart.
+    async fn publish_crash_report(&mut self, report: &CrashReport) -> Result<(), Error> {
+        let backtrace: Vec<String> = report
+            .backtrace()
+            .iter()
+            .map(|address| format!("0x{address:08x}"))
+            .collect();
+        let message = json!({
+            "message": report.message(),
+            "location": report.location(),
+            "backtrace": backtrace,
+            "count": CRASH_COUNT.get(),
+        })
+        .to_string();
+        let topic_name = crash_topic();
+
+        warn!(
+            "Publishing to topic {}, message: {}",
+            topic_name.as_str(),
+            message.as_str()
+        );
+
+        let topic_ref = TopicReference::Name(TopicName::new_unchecked(
+            MqttString::try_from(topic_name.as_str()).unwrap(),
+        ));
+        let options = PublicationOptions::new(topic_ref).retain().at_least_once();
+        self.publish_confirmed(&options, message.as_bytes()).await
+    }
+
+    /// Publish the recent resets other than wakes from deep sleep, newest
+    /// first. Retained, and only sent after the history changed.
+    async fn publish_reset_history(&mut self, history: &ResetHistory) -> Result<(), Error> {
+        let entries: Vec<Value> = history
+            .entries()
+            .map(|entry| {
+                json!({
+                    "reason": reset_reason_name(entry.reason),
+                    "boot": entry.boot_count,
+                })
+            })
+            .collect();
+        let message = json!({
+            "last": reset_reason_name(history.entries().next().and_then(|entry| entry.reason)),
+            "fault_streak": history.fault_streak(),
+            "history": entries,
+        })
+        .to_string();
+        let topic_name = reset_history_topic();
+
+        info!(
+            "Publishing to topic {}, message: {}",
+            topic_name.as_str(),
+            message.as_str()
+        );
+
+        let topic_ref = TopicReference::Name(TopicName::new_unchecked(
+            MqttString::try_from(topic_name.as_str()).unwrap(),
+        ));
+        let options = PublicationOptions::new(topic_ref).retain().at_least_once();
+        self.publish_confirmed(&options, message.as_bytes()).await
+    }
+
+    /// Publish the time and estimated charge per phase of the last wake, with
+    /// the daily consumption it extrapolates to.
+    async fn publish_wake_energy(&mut self, wake: &WakeEnergy) -> Result<(), Error> {
+        let phases: serde_json::Map<String, Value> = wake
+            .phases()
+            .map(|(phase, ms, uah)| (phase.key().to_string(), json!({"ms": ms, "uah": uah})))
+            .collect();
+        let message = json!({
+            "awake_ms": wake.awake_ms(),
+            "wake_uah": wake.wake_uah(),
+            "sleep_s": wake.sleep_seconds(),
+            "uah_per_day": wake.uah_per_day(),
+            "sensors_finish_ms": wake.sensors_finish_ms(),
+            "phases": phases,
+        })
+        .to_string();
+        let topic_name = energy_topic();
+
+        info!(
+            "Publishing to topic {}, message: {}",
+            topic_name.as_str(),
+            message.as_str()
+        );
+
+        let topic_ref = TopicReference::Name(TopicName::new_unchecked(
+            MqttString::try_from(topic_name.as_str()).unwrap(),
+        ));
+        let options = PublicationOptions::new(topic_ref).retain().at_least_once();
+        self.publish_confirmed(&options, message.as_bytes()).await
+    }
+
+    /// Returns the command when an ON, a button press or a firmware install
+    /// was accepted.
+    async fn process_command(
         &mut self,
         topic: &str,
         data: &[u8],
-        pump_set_topic: &str,
-        pump_allowed: bool,
-    ) -> Result<bool, Error> {
-        if topic != pump_set_topic {
-            warn!("Message on unhandled topic: {}", topic);
-            return Ok(false);
-        }
+    ) -> Result<Option<Command>, Error> {
         let Ok(message) = str::from_utf8(data) else {
             warn!("Invalid UTF-8 message on topic {}", topic);
-            return Ok(false);
+            return Ok(None);
         };
-        match message {
-            "ON" => {
-                // Reset the switch immediately so HA reflects the outcome,
-                // and a second wake doesn't re-trigger the pump.
-                self.reset_pump_switch().await?;
-                if pump_allowed {
-                    info!("Pump command received, starting pump");
-                    Ok(true)
-                } else {
-                    warn!("Pump command blocked: overflow detected");
-                    Ok(false)
+        if topic == pump_set_topic() {
+            return match message {
+                "ON" => {
+                    // Clear the command immediately so a second wake doesn't
+                    // re-trigger the pump. If the clear is not acknowledged
+                    // the pump must not run: the retained ON would fire it
+                    // again on the next wake.
+                    self.clear_retained(topic).await?;
+                    self.publish_pump_state(PumpState::Pending).await?;
+                    info!("Pump command received");
+                    Ok(Some(Command::RunPump))
+                }
+                "OFF" => {
+                    // Either the user cancelled a pending ON from HA, or a
+                    // retained OFF left on the command topic by firmware that
+                    // used it as the state topic. Both are cleared the same way.
+                    info!("Pump command cancelled, clearing retained OFF");
+                    self.clear_retained(topic).await?;
+                    self.publish_pump_state(PumpState::Idle).await?;
+                    Ok(None)
+                }
+                "" => Ok(None), // broker echo after our own clear — ignore
+                _ => {
+                    warn!("Unexpected payload on '{}': {}", topic, message);
+                    Ok(None)
                 }
+            };
+        }
+        if topic == firmware_set_topic() {
+            return match message {
+                FIRMWARE_INSTALL_PAYLOAD => {
+                    // Retained like a button press; cleared before the
+                    // install so a failed update doesn't retry every wake.
+                    self.clear_retained(topic).await?;
+                    info!("Firmware install requested");
+                    Ok(Some(Command::InstallFirmware))
+                }
+                "" => Ok(None), // broker echo after our own clear — ignore
+                _ => {
+                    warn!("Unexpected payload on '{}': {}", topic, message);
+                    Ok(None)
+                }
+            };
+        }
+        if topic == log_level_set_topic() {
+            // The level stays retained: it is the setting, not a one-off
+            // command, and RTC memory loses it on power-on.
+            if message.is_empty() {
+                return Ok(None);
             }
-            "OFF" => Ok(false), // broker echo after our own reset — ignore
+            let Some(&level) = syslog::LEVELS
+                .iter()
+                .find(|&&level| syslog::level_name(level) == message)
+            else {
+                warn!("Unexpected payload on '{}': {}", topic, message);
+                return Ok(None);
+            };
+            return Ok((level != syslog::level()).then_some(Command::SetLogLevel(level)));
+        }
+        if topic == maintenance_set_topic() {
+            return match message {
+                // A one-off like the pump command: cleared so maintenance
+                // doesn't start again on every wake.
+                "ON" | "OFF" => {
+                    self.clear_retained(topic).await?;
+                    Ok(Some(Command::SetMaintenance(message == "ON")))
+                }
+                "" => Ok(None), // broker echo after our own clear — ignore
+                _ => {
+                    warn!("Unexpected payload on '{}': {}", topic, message);
+                    Ok(None)
+                }
+            };
+        }
+        if topic == early_sleep_set_topic() {
+            // Retained and kept, like the log level.
+            let enabled = match message {
+                "ON" => true,
+                "OFF" => false,
+                "" => return Ok(None),
+                _ => {
+                    warn!("Unexpected payload on '{}': {}", topic, message);
+                    return Ok(None);
+                }
+            };
+            return Ok((enabled != EARLY_SLEEP.get()).then_some(Command::SetEarlySleep(enabled)));
+        }
+        let Some(button) = Button::iter().find(|b| topic == command_topic(b.topic())) else {
+            warn!("Message on unhandled topic: {}", topic);
+            return Ok(None);
+        };
+        match message {
+            BUTTON_PRESS_PAYLOAD => {
+                // Button presses are retained so they survive deep sleep;
+                // clear the press before acting so it runs only once.
+                self.clear_retained(topic).await?;
+                Ok(Some(Command::Press(button)))
+            }
+            "" => Ok(None), // broker echo after our own clear — ignore
             _ => {
                 warn!("Unexpected payload on '{}': {}", topic, message);
-                Ok(false)
+                Ok(None)
             }
         }
     }
 
-    async fn reset_pump_switch(&mut self) -> Result<(), Error> {
-        let topic_name = pump_set_topic();
+    /// Delete the retained message on `topic` by publishing an empty retained
+    /// payload, and wait for the broker to confirm it.
+    async fn clear_retained(&mut self, topic: &str) -> Result<(), Error> {
+        let topic_ref = TopicReference::Name(TopicName::new_unchecked(
+            MqttString::try_from(topic).unwrap(),
+        ));
+        let options = PublicationOptions::new(topic_ref).retain().at_least_once();
+        self.publish_confirmed(&options, b"").await
+    }
+
+    /// Report the pump state to HA. Retained so the switch shows the right
+    /// state after an HA restart while the device sleeps.
+    pub async fn publish_pump_state(&mut self, state: PumpState) -> Result<(), Error> {
+        let topic_name = pump_state_topic();
+        info!("Pump state: {}", state.as_str());
         let topic_ref = TopicReference::Name(TopicName::new_unchecked(
             MqttString::try_from(topic_name.as_str()).unwrap(),
         ));
-        let options = PublicationOptions::new(topic_ref).retain();
-        self.0.publish(&options, Bytes::Borrowed(b"OFF")).await?;
-        Ok(())
+        let options = PublicationOptions::new(topic_ref).retain().at_least_once();
+        self.publish_confirmed(&options, state.as_str().as_bytes())
+            .await
+    }
+
+    /// Report the remote log level to the HA select. Retained like the pump
+    /// state.
+    pub async fn publish_log_level(&mut self, level: LevelFilter) -> Result<(), Error> {
+        let topic_name = log_level_topic();
+        let topic_ref = TopicReference::Name(TopicName::new_unchecked(
+            MqttString::try_from(topic_name.as_str()).unwrap(),
+        ));
+        let options = PublicationOptions::new(topic_ref).retain().at_least_once();
+        self.publish_confirmed(&options, syslog::level_name(level).as_bytes())
+            .await
+    }
+
+    /// Report whether maintenance mode runs to the HA switch. Retained, so the
+    /// switch is off again after the device went to sleep.
+    pub async fn publish_maintenance(&mut self, active: bool) -> Result<(), Error> {
+        let topic_name = maintenance_topic();
+        let topic_ref = TopicReference::Name(TopicName::new_unchecked(
+            MqttString::try_from(topic_name.as_str()).unwrap(),
+        ));
+        let options = PublicationOptions::new(topic_ref).retain().at_least_once();
+        let payload = if active { "ON" } else { "OFF" };
+        self.publish_confirmed(&options, payload.as_bytes()).await
+    }
+
+    /// Publish fresh readings in maintenance mode, with the moisture
+    /// calibration they may have widened. Honors MQTT_PUBLISH_ENABLED like
+    /// [`publish`](Self::publish).
+    pub async fn publish_readings(&mut self, sensor_data: &SensorData) -> Result<(), Error> {
+        if !MQTT_PUBLISH_ENABLED {
+            return Ok(());
+        }
+        self.publish_sensor_data(sensor_data).await?;
+        self.publish_moisture_calibration().await
+    }
+
+    /// Report the early sleep mode to the HA switch. Retained like the pump
+    /// state.
+    pub async fn publish_early_sleep(&mut self, enabled: bool) -> Result<(), Error> {
+        let topic_name = early_sleep_topic();
+        let topic_ref = TopicReference::Name(TopicName::new_unchecked(
+            MqttString::try_from(topic_name.as_str()).unwrap(),
+        ));
+        let options = PublicationOptions::new(topic_ref).retain().at_least_once();
+        let payload = if enabled { "ON" } else { "OFF" };
+        self.publish_confirmed(&options, payload.as_bytes()).await
+    }
+
+    /// Publish and, for QoS 1, wait up to `MQTT_ACK_TIMEOUT_MS` for the PUBACK.
+    /// Every confirmed publish is settled before this returns, so nothing is
+    /// left in flight when the device goes to sleep.
+    async fn publish_confirmed(
+        &mut self,
+        options: &PublicationOptions<'_>,
+        message: &[u8],
+    ) -> Result<(), Error> {
+        let Some(packet_identifier) = self
+            .client
+            .publish(options, Bytes::Borrowed(message))
+            .await?
+        else {
+            return Ok(()); // QoS 0, nothing to wait for
+        };
+        with_timeout(
+            Duration::from_millis(MQTT_ACK_TIMEOUT_MS),
+            self.wait_for_puback(packet_identifier),
+        )
+        .await
+        .map_err(|_| Error::Unacknowledged(packet_identifier))?
+    }
+
+    async fn wait_for_puback(&mut self, packet_identifier: PacketIdentifier) -> Result<(), Error> {
+        loop {
+            match self.client.poll().await? {
+                Event::PublishAcknowledged(ack) if ack.packet_identifier == packet_identifier => {
+                    return Ok(());
+                }
+                Event::PublishRejected(rejection)
+                    if rejection.packet_identifier == packet_identifier =>
+                {
+                    error!(
+                        "Publish {} rejected by broker: {:?}",
+                        packet_identifier, rejection.reason_code
+                    );
+                    return Err(Error::Broker(rejection.reason_code));
+                }
+                Event::Publish(e) => {
+                    self.defer(e.topic.as_ref().as_str(), e.message.as_ref());
+                }
+                e => info!("Received event {:?}", e),
+            }
+        }
+    }
+
+    /// Queue a command that arrived while awaiting a PUBACK. The empty
+    /// payloads of our own clears echo back on the command topics and are
+    /// not worth keeping.
+    fn defer(&mut self, topic: &str, message: &[u8]) {
+        if message.is_empty() {
+            return;
+        }
+        info!("Deferring message on {} while awaiting PUBACK", topic);
+        let pending = PendingMessage {
+            topic: topic.to_string(),
+            message: message.to_vec(),
+        };
+        if self.pending.push_back(pending).is_err() {
+            error!("Command queue full, dropping message on {}", topic);
+        }
+    }
+
+    /// Publish the captured raw moisture range while a calibration started
+    /// from HA is running.
+    async fn publish_moisture_calibration(&mut self) -> Result<(), Error> {
+        let Some(calibration) = MOISTURE_CALIBRATION.get() else {
+            return Ok(());
+        };
+        if calibration.samples == 0 {
+            return Ok(());
+        }
+        let message = json!({
+            "min": calibration.min,
+            "max": calibration.max,
+            "samples": calibration.samples,
+        })
+        .to_string();
+        let topic_name = moisture_calibration_topic();
+
+        info!(
+            "Publishing to topic {}, message: {}",
+            topic_name.as_str(),
+            message.as_str()
+        );
+
+        let topic_ref = TopicReference::Name(TopicName::new_unchecked(
+            MqttString::try_from(topic_name.as_str()).unwrap(),
+        ));
+        let options = PublicationOptions::new(topic_ref).at_least_once();
+        self.publish_confirmed(&options, message.as_bytes()).await
     }
 
     async fn publish_sensor_data(&mut self, sensor_data: &SensorData) -> Result<(), Error> {
         for s in &sensor_data.data {
-            let key = s.topic();
+            let key = s.info().topic;
             let value = s.value();
             let message = json!({ "value": value }).to_string();
             let topic_name = format!("{DEVICE_ID}/{key}");
@@ -282,36 +893,131 @@ impl MqttSession<'_> {
             let topic_ref = TopicReference::Name(TopicName::new_unchecked(
                 MqttString::try_from(topic_name.as_str()).unwrap(),
             ));
-            let options = PublicationOptions::new(topic_ref);
+            let options = PublicationOptions::new(topic_ref).at_least_once();
+
+            self.publish_confirmed(&options, message.as_bytes()).await?;
+        }
+
+        for &(quantity, fault) in &sensor_data.health {
+            let message = json!({
+                "problem": if fault.is_some() { "ON" } else { "OFF" },
+                "fault": fault.map(|fault| fault.as_str()),
+            })
+            .to_string();
+            let topic_name = problem_topic(quantity);
+
+            info!(
+                "Publishing to topic {}, message: {}",
+                topic_name.as_str(),
+                message.as_str()
+            );
+
+            let topic_ref = TopicReference::Name(TopicName::new_unchecked(
+                MqttString::try_from(topic_name.as_str()).unwrap(),
+            ));
+            let options = PublicationOptions::new(topic_ref).at_least_once();
 
-            self.0
-                .publish(&options, Bytes::Borrowed(message.as_bytes()))
-                .await?;
+            self.publish_confirmed(&options, message.as_bytes()).await?;
         }
 
         Ok(())
     }
 }
 
+fn command_topic(name: &str) -> String {
+    format!("{DEVICE_ID}/{name}/set")
+}
+
+fn problem_topic(quantity: Quantity) -> String {
+    format!("{DEVICE_ID}/{}/problem", quantity.key())
+}
+
+fn moisture_calibration_topic() -> String {
+    format!("{DEVICE_ID}/moisture_calibration")
+}
+
 fn pump_set_topic() -> String {
-    format!("{DEVICE_ID}/pump/set")
+    command_topic("pump")
+}
+
+fn wake_button_topic() -> String {
+    format!("{DEVICE_ID}/wake_button")
+}
+
+fn firmware_topic() -> String {
+    format!("{DEVICE_ID}/firmware")
+}
+
+fn firmware_latest_topic() -> String {
+    format!("{DEVICE_ID}/firmware/latest")
+}
+
+fn firmware_set_topic() -> String {
+    command_topic("firmware")
+}
+
+fn crash_topic() -> String {
+    format!("{DEVICE_ID}/crash")
+}
+
+fn reset_history_topic() -> String {
+    format!("{DEVICE_ID}/reset_history")
+}
+
+fn energy_topic() -> String {
+    format!("{DEVICE_ID}/energy")
+}
+
+fn log_level_topic() -> String {
+    format!("{DEVICE_ID}/log_level")
+}
+
+fn log_level_set_topic() -> String {
+    command_topic("log_level")
+}
+
+fn maintenance_topic() -> String {
+    format!("{DEVICE_ID}/maintenance")
+}
+
+fn maintenance_set_topic() -> String {
+    command_topic("maintenance")
+}
+
+fn early_sleep_topic() -> String {
+    format!("{DEVICE_ID}/early_sleep")
+}
+
+fn early_sleep_set_topic() -> String {
+    command_topic("early_sleep")
+}
+
+fn pump_state_topic() -> String {
+    format!("{DEVICE_ID}/pump/state")
+}
+
+fn pump_last_run_topic() -> String {
+    format!("{DEVICE_ID}/pump/last_run")
 }
 
 fn get_sensor_discovery(s: &Sensor) -> (String, String) {
-    let topic = s.topic();
-    let mut payload = get_common_device_info(topic, s.name());
+    let info = s.info();
+    let topic = info.topic;
+    let mut payload = get_common_device_info(topic, info.name);
     payload["state_topic"] = json!(format!("{}/{}", DEVICE_ID, topic));
     payload["value_template"] = json!("{{ value_json.value }}");
     payload["platform"] = json!("sensor");
     payload["unique_id"] = json!(format!("{}_{}", DEVICE_ID, topic));
 
-    let device_class = s.device_class();
-    if let Some(device_class) = device_class {
+    if let Some(device_class) = info.device_class {
         payload["device_class"] = json!(device_class);
     }
 
-    let unit = s.unit();
-    if let Some(unit) = unit {
+    if let Some(precision) = info.display_precision {
+        payload["suggested_display_precision"] = json!(precision);
+    }
+
+    if let Some(unit) = info.unit {
         payload["unit_of_measurement"] = json!(unit);
         // only set state_class if unit is present - enables Home Assistant to display the unit correctly and keep track of state changes
         payload["state_class"] = json!("measurement");
@@ -326,13 +1032,40 @@ fn get_sensor_discovery(s: &Sensor) -> (String, String) {
     (discovery_topic, payload.to_string())
 }
 
+/// Problem binary sensor of one fitted quantity, with the fault found as an
+/// attribute.
+fn get_problem_discovery(quantity: Quantity) -> (String, String) {
+    let key = quantity.key();
+    let mut payload = get_common_device_info(
+        &format!("{key}_problem"),
+        &format!("{} problem", quantity.label()),
+    );
+    payload["state_topic"] = json!(problem_topic(quantity));
+    payload["value_template"] = json!("{{ value_json.problem }}");
+    payload["json_attributes_topic"] = json!(problem_topic(quantity));
+    payload["payload_on"] = json!("ON");
+    payload["payload_off"] = json!("OFF");
+    payload["device_class"] = json!("problem");
+    payload["entity_category"] = json!("diagnostic");
+
+    let discovery_topic = format!(
+        "{HOMEASSISTANT_DISCOVERY_TOPIC_PREFIX}/{HOMEASSISTANT_BINARY_SENSOR_TOPIC}/{DEVICE_ID}_{key}_problem/config"
+    );
+    (discovery_topic, payload.to_string())
+}
+
 fn get_pump_switch_discovery() -> (String, String) {
     let mut payload = get_common_device_info("pump", "Water pump");
-    payload["command_topic"] = json!(format!("{}/pump/set", DEVICE_ID));
-    payload["state_topic"] = json!(format!("{}/pump/set", DEVICE_ID));
+    payload["command_topic"] = json!(pump_set_topic());
+    payload["state_topic"] = json!(pump_state_topic());
+    // The switch is ON while a command is pending or the pump is running
+    payload["value_template"] = json!("{{ 'ON' if value in ['pending', 'running'] else 'OFF' }}");
     payload["payload_on"] = json!("ON");
     payload["payload_off"] = json!("OFF");
+    // The command is retained until the device wakes and clears it; HA shows
+    // it as ON right away instead of waiting up to an hour for the state.
     payload["retain"] = json!(true);
+    payload["optimistic"] = json!(true);
 
     let discovery_topic = format!(
         "{HOMEASSISTANT_DISCOVERY_TOPIC_PREFIX}/{HOMEASSISTANT_SWITCH_TOPIC}/{DEVICE_ID}_pump/config"
@@ -340,16 +1073,295 @@ fn get_pump_switch_discovery() -> (String, String) {
     (discovery_topic, payload.to_string())
 }
 
+/// Switch for leaving the command window once the retained commands are
+/// handled. Retained on the command topic like the pump switch, but kept as
+/// the setting.
+fn get_early_sleep_discovery() -> (String, String) {
+    let mut payload = get_common_device_info("early_sleep", "Early sleep");
+    payload["command_topic"] = json!(early_sleep_set_topic());
+    payload["state_topic"] = json!(early_sleep_topic());
+    payload["payload_on"] = json!("ON");
+    payload["payload_off"] = json!("OFF");
+    payload["retain"] = json!(true);
+    payload["optimistic"] = json!(true);
+    payload["entity_category"] = json!("config");
+
+    let discovery_topic = format!(
+        "{HOMEASSISTANT_DISCOVERY_TOPIC_PREFIX}/{HOMEASSISTANT_SWITCH_TOPIC}/{DEVICE_ID}_early_sleep/config"
+    );
+    (discovery_topic, payload.to_string())
+}
+
+/// Switch for maintenance mode. Turned on while the device sleeps, the
+/// retained ON starts it on the next wake.
+fn get_maintenance_discovery() -> (String, String) {
+    let mut payload = get_common_device_info("maintenance", "Maintenance mode");
+    payload["command_topic"] = json!(maintenance_set_topic());
+    payload["state_topic"] = json!(maintenance_topic());
+    payload["payload_on"] = json!("ON");
+    payload["payload_off"] = json!("OFF");
+    payload["retain"] = json!(true);
+    payload["optimistic"] = json!(true);
+    payload["entity_category"] = json!("config");
+
+    let discovery_topic = format!(
+        "{HOMEASSISTANT_DISCOVERY_TOPIC_PREFIX}/{HOMEASSISTANT_SWITCH_TOPIC}/{DEVICE_ID}_maintenance/config"
+    );
+    (discovery_topic, payload.to_string())
+}
+
+fn get_pump_state_discovery() -> (String, String) {
+    let mut payload = get_common_device_info("pump_state", "Pump state");
+    payload["state_topic"] = json!(pump_state_topic());
+    payload["device_class"] = json!("enum");
+    payload["options"] = json!(PumpState::ALL.map(|s| s.as_str()));
+
+    let discovery_topic = format!(
+        "{HOMEASSISTANT_DISCOVERY_TOPIC_PREFIX}/{HOMEASSISTANT_SENSOR_TOPIC}/{DEVICE_ID}_pump_state/config"
+    );
+    (discovery_topic, payload.to_string())
+}
+
+fn get_pump_last_run_discovery() -> (String, String) {
+    let mut payload = get_common_device_info("pump_last_run", "Pump last run");
+    payload["state_topic"] = json!(pump_last_run_topic());
+    payload["value_template"] = json!("{{ value_json.outcome }}");
+    payload["json_attributes_topic"] = json!(pump_last_run_topic());
+    payload["device_class"] = json!("enum");
+    payload["options"] = json!(PumpOutcome::ALL.map(|o| o.as_str()));
+
+    let discovery_topic = format!(
+        "{HOMEASSISTANT_DISCOVERY_TOPIC_PREFIX}/{HOMEASSISTANT_SENSOR_TOPIC}/{DEVICE_ID}_pump_last_run/config"
+    );
+    (discovery_topic, payload.to_string())
+}
+
+/// When the last pump command was handled, from the device's clock. A run
+/// without a time (clock not set yet) keeps the previous value.
+fn get_pump_last_run_time_discovery() -> (String, String) {
+    let mut payload = get_common_device_info("pump_last_run_time", "Pump last run time");
+    payload["state_topic"] = json!(pump_last_run_topic());
+    payload["value_template"] =
+        json!("{{ as_datetime(value_json.time) if value_json.time is defined else this.state }}");
+    payload["device_class"] = json!("timestamp");
+
+    let discovery_topic = format!(
+        "{HOMEASSISTANT_DISCOVERY_TOPIC_PREFIX}/{HOMEASSISTANT_SENSOR_TOPIC}/{DEVICE_ID}_pump_last_run_time/config"
+    );
+    (discovery_topic, payload.to_string())
+}
+
+fn get_pump_event_discovery() -> (String, String) {
+    let mut payload = get_common_device_info("pump_event", "Pump");
+    payload["state_topic"] = json!(pump_last_run_topic());
+    // HA event entities expect an `event_type` key; map our `outcome` onto it.
+    payload["value_template"] = json!("{{ {'event_type': value_json.outcome} | to_json }}");
+    payload["event_types"] = json!(PumpOutcome::ALL.map(|o| o.as_str()));
+    payload["json_attributes_topic"] = json!(pump_last_run_topic());
+
+    let discovery_topic = format!(
+        "{HOMEASSISTANT_DISCOVERY_TOPIC_PREFIX}/{HOMEASSISTANT_EVENT_TOPIC}/{DEVICE_ID}_pump_event/config"
+    );
+    (discovery_topic, payload.to_string())
+}
+
+/// Discovery for a counter carried in the last-run payload. Both counters are
+/// kept in RTC memory and restart at zero after a power cycle, which
+/// `total_increasing` treats as a meter reset.
+fn get_pump_stat_discovery(
+    topic: &str,
+    name: &str,
+    value_template: &str,
+    device_class_and_unit: Option<(&str, &str)>,
+) -> (String, String) {
+    let mut payload = get_common_device_info(topic, name);
+    payload["state_topic"] = json!(pump_last_run_topic());
+    payload["value_template"] = json!(value_template);
+    payload["state_class"] = json!("total_increasing");
+    if let Some((device_class, unit)) = device_class_and_unit {
+        payload["device_class"] = json!(device_class);
+        payload["unit_of_measurement"] = json!(unit);
+    }
+
+    let discovery_topic = format!(
+        "{HOMEASSISTANT_DISCOVERY_TOPIC_PREFIX}/{HOMEASSISTANT_SENSOR_TOPIC}/{DEVICE_ID}_{topic}/config"
+    );
+    (discovery_topic, payload.to_string())
+}
+
+fn get_button_discovery(b: &Button) -> (String, String) {
+    let topic = b.topic();
+    let mut payload = get_common_device_info(topic, b.name());
+    payload["command_topic"] = json!(command_topic(topic));
+    payload["payload_press"] = json!(BUTTON_PRESS_PAYLOAD);
+    // Retained so a press while the device sleeps runs on the next wake
+    payload["retain"] = json!(true);
+    payload["entity_category"] = json!("config");
+    if let Some(device_class) = b.device_class() {
+        payload["device_class"] = json!(device_class);
+    }
+
+    let discovery_topic = format!(
+        "{HOMEASSISTANT_DISCOVERY_TOPIC_PREFIX}/{HOMEASSISTANT_BUTTON_TOPIC}/{DEVICE_ID}_{topic}/config"
+    );
+    (discovery_topic, payload.to_string())
+}
+
+fn get_moisture_calibration_discovery(bound: &str, name: &str) -> (String, String) {
+    let topic = format!("moisture_calibration_{bound}");
+    let mut payload = get_common_device_info(&topic, name);
+    payload["state_topic"] = json!(moisture_calibration_topic());
+    payload["value_template"] = json!(format!("{{{{ value_json.{bound} }}}}"));
+    payload["json_attributes_topic"] = json!(moisture_calibration_topic());
+    payload["unit_of_measurement"] = json!("mV");
+    payload["entity_category"] = json!("diagnostic");
+
+    let discovery_topic = format!(
+        "{HOMEASSISTANT_DISCOVERY_TOPIC_PREFIX}/{HOMEASSISTANT_SENSOR_TOPIC}/{DEVICE_ID}_{topic}/config"
+    );
+    (discovery_topic, payload.to_string())
+}
+
+/// HA update entity: installed vs. latest firmware, with Install retained on
+/// the command topic until the device wakes and picks it up.
+fn get_firmware_update_discovery() -> (String, String) {
+    let mut payload = get_common_device_info("firmware", "Firmware");
+    payload["state_topic"] = json!(firmware_topic());
+    payload["latest_version_topic"] = json!(firmware_latest_topic());
+    payload["latest_version_template"] = json!("{{ value_json.latest_version }}");
+    payload["command_topic"] = json!(firmware_set_topic());
+    payload["payload_install"] = json!(FIRMWARE_INSTALL_PAYLOAD);
+    payload["retain"] = json!(true);
+    payload["device_class"] = json!("firmware");
+    payload["entity_category"] = json!("config");
+
+    let discovery_topic = format!(
+        "{HOMEASSISTANT_DISCOVERY_TOPIC_PREFIX}/{HOMEASSISTANT_UPDATE_TOPIC}/{DEVICE_ID}_firmware/config"
+    );
+    (discovery_topic, payload.to_string())
+}
+
+/// Crash counter, with the message, location and backtrace of the last panic
+/// as attributes.
+fn get_crash_discovery() -> (String, String) {
+    let mut payload = get_common_device_info("crashes", "Crashes");
+    payload["state_topic"] = json!(crash_topic());
+    payload["value_template"] = json!("{{ value_json.count }}");
+    payload["json_attributes_topic"] = json!(crash_topic());
+    payload["state_class"] = json!("total_increasing");
+    payload["entity_category"] = json!("diagnostic");
+
+    let discovery_topic = format!(
+        "{HOMEASSISTANT_DISCOVERY_TOPIC_PREFIX}/{HOMEASSISTANT_SENSOR_TOPIC}/{DEVICE_ID}_crashes/config"
+    );
+    (discovery_topic, payload.to_string())
+}
+
+/// Last reset other than a wake from deep sleep, with the history and the
+/// current brownout/watchdog streak as attributes.
+fn get_reset_history_discovery() -> (String, String) {
+    let mut payload = get_common_device_info("last_reset", "Last reset");
+    payload["state_topic"] = json!(reset_history_topic());
+    payload["value_template"] = json!("{{ value_json.last }}");
+    payload["json_attributes_topic"] = json!(reset_history_topic());
+    payload["entity_category"] = json!("diagnostic");
+
+    let discovery_topic = format!(
+        "{HOMEASSISTANT_DISCOVERY_TOPIC_PREFIX}/{HOMEASSISTANT_SENSOR_TOPIC}/{DEVICE_ID}_last_reset/config"
+    );
+    (discovery_topic, payload.to_string())
+}
+
+/// Select for the level of the records shipped to `SYSLOG_HOST`. Retained on
+/// the command topic, so a change made while the device sleeps applies on
+/// its next wake.
+fn get_log_level_discovery() -> (String, String) {
+    let mut payload = get_common_device_info("log_level", "Remote log level");
+    payload["state_topic"] = json!(log_level_topic());
+    payload["command_topic"] = json!(log_level_set_topic());
+    payload["options"] = json!(
+        syslog::LEVELS
+            .iter()
+            .map(|&level| syslog::level_name(level))
+            .collect::<Vec<_>>()
+    );
+    payload["retain"] = json!(true);
+    payload["entity_category"] = json!("config");
+
+    let discovery_topic = format!(
+        "{HOMEASSISTANT_DISCOVERY_TOPIC_PREFIX}/{HOMEASSISTANT_SELECT_TOPIC}/{DEVICE_ID}_log_level/config"
+    );
+    (discovery_topic, payload.to_string())
+}
+
+/// Diagnostic sensor on the last wake's energy breakdown, with the per-phase
+/// figures as attributes.
+fn get_energy_discovery(
+    topic: &str,
+    name: &str,
+    value_template: &str,
+    device_class: Option<&str>,
+    unit: &str,
+) -> (String, String) {
+    let mut payload = get_common_device_info(topic, name);
+    payload["state_topic"] = json!(energy_topic());
+    payload["value_template"] = json!(value_template);
+    payload["json_attributes_topic"] = json!(energy_topic());
+    payload["unit_of_measurement"] = json!(unit);
+    payload["state_class"] = json!("measurement");
+    payload["entity_category"] = json!("diagnostic");
+    if let Some(device_class) = device_class {
+        payload["device_class"] = json!(device_class);
+    }
+
+    let discovery_topic = format!(
+        "{HOMEASSISTANT_DISCOVERY_TOPIC_PREFIX}/{HOMEASSISTANT_SENSOR_TOPIC}/{DEVICE_ID}_{topic}/config"
+    );
+    (discovery_topic, payload.to_string())
+}
+
+/// Device trigger for the physical wake button on GPIO14. Pressing it wakes
+/// the device, which then publishes a press once MQTT is connected.
+fn get_wake_button_trigger_discovery() -> (String, String) {
+    let payload = json!({
+        "automation_type": "trigger",
+        "topic": wake_button_topic(),
+        "type": "button_short_press",
+        "subtype": "button_1",
+        "payload": BUTTON_PRESS_PAYLOAD,
+        "device": get_device_info(),
+    });
+
+    let discovery_topic = format!(
+        "{HOMEASSISTANT_DISCOVERY_TOPIC_PREFIX}/{HOMEASSISTANT_DEVICE_AUTOMATION_TOPIC}/{DEVICE_ID}_wake_button/config"
+    );
+    (discovery_topic, payload.to_string())
+}
+
+/// `SocResetReason` variant name, `Unknown` if the ROM reported none
+fn reset_reason_name(reason: Option<SocResetReason>) -> String {
+    match reason {
+        Some(reason) => format!("{reason:?}"),
+        None => "Unknown".to_string(),
+    }
+}
+
 fn get_common_device_info(topic: &str, name: &str) -> Value {
     json!({
         "name": name,
         "unique_id": format!("{}_{}", DEVICE_ID, topic),
-        "device": {
-            "identifiers": [DEVICE_ID],
-            "name": "ESP32 Device",
-            "model": "ESP32S3",
-            "manufacturer": "Espressif"
-        }
+        "device": get_device_info()
+    })
+}
+
+fn get_device_info() -> Value {
+    json!({
+        "identifiers": [DEVICE_ID],
+        "name": "ESP32 Device",
+        "model": "ESP32S3",
+        "manufacturer": "Espressif",
+        // Crate version and commit hash from the app descriptor
+        "sw_version": ESP_APP_DESC.version()
     })
 }
 
@@ -360,6 +1372,8 @@ pub enum Error {
     Connection(ConnectError),
     Broker(ReasonCode),
     Mqtt,
+    Timeout,
+    Unacknowledged(PacketIdentifier),
 }
 
 impl core::fmt::Display for Error {
@@ -370,6 +1384,8 @@ impl core::fmt::Display for Error {
             Error::Connection(e) => write!(f, "Connection error: {e:?}"),
             Error::Broker(e) => write!(f, "Broker error: {e:?}"),
             Error::Mqtt => write!(f, "MQTT error"),
+            Error::Timeout => write!(f, "MQTT operation timed out"),
+            Error::Unacknowledged(id) => write!(f, "Publish {id} not acknowledged by broker"),
         }
     }
 }
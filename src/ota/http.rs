@@ -0,0 +1,171 @@
+//! Parsing for the minimal HTTP/1.0 client that fetches the manifest and
+//! image: the update URL and the response head. No sockets involved, so the
+//! host tests cover it.
+
+use core::str;
+
+/// Split `http://host[:port]/path` into its parts.
+pub fn parse_url(url: &str) -> Option<(&str, u16, &str)> {
+    let rest = url.strip_prefix("http://")?;
+    let (authority, path) = match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.split_once(':') {
+        Some((host, port)) => (host, port.parse().ok()?),
+        None => (authority, 80),
+    };
+    (!host.is_empty()).then_some((host, port, path))
+}
+
+/// Status and length of a response, from its head
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResponseHead {
+    pub status: u16,
+    pub content_length: Option<usize>,
+}
+
+impl ResponseHead {
+    /// Start of the body: the end of the status line and headers including
+    /// the blank line after them, once `buf` holds all of them
+    pub fn end(buf: &[u8]) -> Option<usize> {
+        buf.windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .map(|end| end + 4)
+    }
+
+    /// Parse the status line and headers; `None` if they are malformed.
+    pub fn parse(head: &[u8]) -> Option<Self> {
+        let head = str::from_utf8(head).ok()?;
+        let mut lines = head.lines();
+        let mut status_line = lines.next()?.split(' ');
+        if !status_line.next()?.starts_with("HTTP/") {
+            return None;
+        }
+        let status = status_line.next()?.parse().ok()?;
+        let mut content_length = None;
+        for line in lines {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = Some(value.trim().parse().ok()?);
+            }
+        }
+        Some(Self {
+            status,
+            content_length,
+        })
+    }
+
+    /// Whether the body is announced as exactly `size` bytes. Without a
+    /// length a connection closed early can't be told from the end of the
+    /// body.
+    pub fn has_length(&self, size: usize) -> bool {
+        self.content_length == Some(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls_split_into_host_port_and_path() {
+        assert_eq!(
+            parse_url("http://192.168.1.10:8000/manifest.json"),
+            Some(("192.168.1.10", 8000, "/manifest.json"))
+        );
+        assert_eq!(
+            parse_url("http://updates.lan/fw/manifest.json"),
+            Some(("updates.lan", 80, "/fw/manifest.json"))
+        );
+        assert_eq!(
+            parse_url("http://updates.lan"),
+            Some(("updates.lan", 80, "/"))
+        );
+        assert_eq!(
+            parse_url("http://updates.lan:8080"),
+            Some(("updates.lan", 8080, "/"))
+        );
+    }
+
+    #[test]
+    fn only_plain_http_urls_are_accepted() {
+        for url in [
+            "https://updates.lan/manifest.json",
+            "ftp://updates.lan/manifest.json",
+            "updates.lan/manifest.json",
+            "http://updates.lan:port/",
+            "http://updates.lan:70000/",
+            "http://updates.lan:/",
+            "http:///manifest.json",
+            "http://:8000/manifest.json",
+            "",
+        ] {
+            assert_eq!(parse_url(url), None, "{url}");
+        }
+    }
+
+    #[test]
+    fn head_ends_at_the_blank_line() {
+        let response = b"HTTP/1.0 200 OK\r\nContent-Length: 3\r\n\r\nabc";
+        assert_eq!(ResponseHead::end(response), Some(response.len() - 3));
+        assert_eq!(ResponseHead::end(b"HTTP/1.0 200 OK\r\nContent-Le"), None);
+        assert_eq!(ResponseHead::end(b"HTTP/1.0 200 OK\r\n\r"), None);
+    }
+
+    #[test]
+    fn head_gives_status_and_length() {
+        let head =
+            ResponseHead::parse(b"HTTP/1.0 200 OK\r\nServer: x\r\nContent-Length: 1234\r\n\r\n");
+        assert_eq!(
+            head,
+            Some(ResponseHead {
+                status: 200,
+                content_length: Some(1234),
+            })
+        );
+        // Header names are case insensitive, values may be padded
+        let head = ResponseHead::parse(b"HTTP/1.1 200 OK\r\ncontent-length:  42 \r\n\r\n");
+        assert_eq!(head.and_then(|head| head.content_length), Some(42));
+        let head = ResponseHead::parse(b"HTTP/1.0 404 Not Found\r\n\r\n");
+        assert_eq!(
+            head,
+            Some(ResponseHead {
+                status: 404,
+                content_length: None,
+            })
+        );
+    }
+
+    #[test]
+    fn malformed_heads_are_rejected() {
+        for head in [
+            &b""[..],
+            b"\r\n\r\n",
+            b"SSH-2.0-OpenSSH\r\n\r\n",
+            b"HTTP/1.0\r\n\r\n",
+            b"HTTP/1.0 OK 200\r\n\r\n",
+            b"HTTP/1.0 200 OK\r\nContent-Length: -1\r\n\r\n",
+            b"HTTP/1.0 200 OK\r\nContent-Length: 12ab\r\n\r\n",
+            b"HTTP/1.0 200 OK\r\nContent-Length: \xff\r\n\r\n",
+        ] {
+            assert_eq!(ResponseHead::parse(head), None, "{head:?}");
+        }
+    }
+
+    #[test]
+    fn image_length_must_match_the_manifest() {
+        let head = |content_length| ResponseHead {
+            status: 200,
+            content_length,
+        };
+        assert!(head(Some(1234)).has_length(1234));
+        assert!(!head(Some(1233)).has_length(1234));
+        assert!(!head(Some(1235)).has_length(1234));
+        // A missing length could hide a truncated download
+        assert!(!head(None).has_length(1234));
+        assert!(!head(None).has_length(0));
+    }
+}
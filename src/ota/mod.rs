@@ -0,0 +1,443 @@
+//! Over-the-air firmware updates
+//!
+//! The update is pulled, never pushed: when a check is due the device fetches
+//! a JSON manifest over plain HTTP and reports its version to HA. Once an
+//! install is requested, the image is streamed into the inactive OTA slot
+//! while being hashed. Only a complete image with the expected SHA-256 is
+//! activated, and only if the manifest carries a valid Ed25519 signature from
+//! the key whose public half is compiled in (`OTA_PUBLIC_KEY`). The manifest
+//! and signature are produced by `tools/ota-sign`.
+//!
+//! A freshly installed image boots in the `New` state and has to prove itself
+//! by publishing to MQTT. Until it does, it stays `PendingVerify`; after
+//! [`OTA_MAX_VERIFY_WAKES`] wakes without a publish it is marked invalid and
+//! the previous slot boots again.
+
+mod http;
+mod verify;
+
+use alloc::{format, string::String, vec, vec::Vec};
+use embassy_net::{
+    Stack,
+    dns::{DnsQueryType, Error as DnsError},
+    tcp::{self, ConnectError, TcpSocket},
+};
+use embassy_time::Duration;
+use embedded_storage::{ReadStorage, Storage};
+use esp_bootloader_esp_idf::{
+    ota::OtaImageState,
+    ota_updater::OtaUpdater,
+    partitions::{self, PARTITION_TABLE_MAX_LEN},
+};
+use esp_hal::{peripherals::FLASH, rtc_cntl::SocResetReason, system::software_reset};
+use esp_storage::FlashStorage;
+use http::ResponseHead;
+use log::{error, info, warn};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    OTA_UNVERIFIED_WAKES,
+    config::{OTA_MAX_VERIFY_WAKES, OTA_SOCKET_TIMEOUT_SECONDS},
+};
+
+/// Firmware version of the running image, compared against the manifest
+pub const FIRMWARE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Manifest URL baked in at build time; OTA is disabled when it is unset or empty
+pub fn manifest_url() -> Option<&'static str> {
+    option_env!("OTA_MANIFEST_URL").filter(|url| !url.is_empty())
+}
+
+/// Whether `version` is a later release than the running firmware, by semver
+/// precedence. Only later releases are installed: the signature proves a
+/// manifest genuine, not current, so an older signed manifest and image
+/// could otherwise be replayed to downgrade the device.
+pub fn is_newer(version: &str) -> bool {
+    verify::is_newer(version, FIRMWARE_VERSION)
+}
+
+/// Fetch the manifest at `manifest_url` and return the firmware version it
+/// offers.
+pub async fn latest_version(stack: Stack<'_>, manifest_url: &str) -> Result<String, Error> {
+    Ok(fetch_manifest(stack, manifest_url).await?.version)
+}
+
+/// Image data is written one flash sector at a time
+const CHUNK_SIZE: usize = FlashStorage::SECTOR_SIZE as usize;
+/// Upper bound for the HTTP status line and headers
+const MAX_HEADER_SIZE: usize = 1024;
+/// Upper bound for the manifest body
+const MAX_MANIFEST_SIZE: usize = 1024;
+
+/// Update manifest, served next to the image:
+/// `{"version": "0.2.0", "url": "http://…/firmware.bin", "size": 1234, "sha256": "…", "signature": "…"}`
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    version: String,
+    url: String,
+    size: usize,
+    sha256: String,
+    /// Ed25519 signature over [`verify::signed_message`], hex encoded
+    signature: String,
+}
+
+/// Access to the OTA data and app partitions of the boot flash.
+pub struct Ota {
+    flash: FlashStorage<'static>,
+    /// The running image was installed by an update and is not confirmed yet.
+    pending_verify: bool,
+}
+
+impl Ota {
+    pub fn new(flash: FLASH<'static>) -> Self {
+        Self {
+            flash: FlashStorage::new(flash),
+            pending_verify: false,
+        }
+    }
+
+    /// Called once per boot, before anything can fail. Moves a freshly
+    /// installed image to `PendingVerify` and rolls back to the previous slot
+    /// (resetting the device) once it has used up its verification wakes.
+    /// Each boot counts, so an image that never gets as far as sleeping is
+    /// rolled back too.
+    pub fn check_running_image(&mut self, reset_reason: Option<SocResetReason>) {
+        // Persistent RTC memory holds garbage after power-on
+        if reset_reason == Some(SocResetReason::ChipPowerOn) {
+            OTA_UNVERIFIED_WAKES.set(0);
+        }
+        let state = match self.with_updater(|updater| updater.current_ota_state()) {
+            Ok(state) => state,
+            // Flashed over USB: no OTA data yet, nothing to verify
+            Err(Error::Partition(partitions::Error::InvalidState)) => return,
+            Err(error) => {
+                warn!("OTA unavailable: {error}");
+                return;
+            }
+        };
+
+        match state {
+            OtaImageState::New => {
+                info!("Booting a new firmware image, waiting for it to publish");
+                OTA_UNVERIFIED_WAKES.set(1);
+                if let Err(error) = self.set_state(OtaImageState::PendingVerify) {
+                    error!("Failed to mark image pending: {error}");
+                }
+                self.pending_verify = true;
+            }
+            OtaImageState::PendingVerify => {
+                let wakes = OTA_UNVERIFIED_WAKES.get();
+                if wakes >= OTA_MAX_VERIFY_WAKES {
+                    error!("Firmware image not verified after {wakes} wakes, rolling back");
+                    self.roll_back();
+                }
+                OTA_UNVERIFIED_WAKES.set(wakes.saturating_add(1));
+                self.pending_verify = true;
+            }
+            _ => {}
+        }
+    }
+
+    /// The running image proved itself by publishing to MQTT: keep it.
+    pub fn confirm(&mut self) {
+        if !self.pending_verify {
+            return;
+        }
+        match self.set_state(OtaImageState::Valid) {
+            Ok(()) => {
+                info!("Firmware {FIRMWARE_VERSION} verified");
+                self.pending_verify = false;
+            }
+            Err(error) => error!("Failed to mark image valid: {error}"),
+        }
+    }
+
+    /// Whether the running image still has to be confirmed. No update is
+    /// installed on top of an unconfirmed image.
+    pub fn is_pending_verify(&self) -> bool {
+        self.pending_verify
+    }
+
+    /// Fetch the manifest at `manifest_url` and install the image it names if
+    /// it is newer than the running firmware. Returns `true` when a new image
+    /// was activated; the caller resets into it.
+    pub async fn update(&mut self, stack: Stack<'_>, manifest_url: &str) -> Result<bool, Error> {
+        let manifest = fetch_manifest(stack, manifest_url).await?;
+        // The version is covered by the signature checked below
+        if !is_newer(&manifest.version) {
+            info!(
+                "Firmware {FIRMWARE_VERSION} is up to date, manifest offers {}",
+                manifest.version
+            );
+            return Ok(false);
+        }
+        info!(
+            "Installing firmware {} over {FIRMWARE_VERSION}",
+            manifest.version
+        );
+        // Reject an unsigned or forged manifest before anything is downloaded
+        let digest = verify::parse_hex::<32>(&manifest.sha256).ok_or(Error::Manifest)?;
+        // build.rs refuses to build with a manifest URL but no valid key
+        let public_key = option_env!("OTA_PUBLIC_KEY").ok_or(Error::PublicKey)?;
+        verify::verify_signature(public_key, &manifest.signature, &digest, &manifest.version)?;
+        self.install(stack, &manifest, &digest).await?;
+        Ok(true)
+    }
+
+    /// Stream the image into the inactive slot and select it for the next
+    /// boot if its SHA-256 is the signed `digest`.
+    async fn install(
+        &mut self,
+        stack: Stack<'_>,
+        manifest: &Manifest,
+        digest: &[u8; 32],
+    ) -> Result<(), Error> {
+        let mut table = [0u8; PARTITION_TABLE_MAX_LEN];
+        let mut updater = OtaUpdater::new(&mut self.flash, &mut table)?;
+        let (mut region, slot) = updater.next_partition()?;
+        if manifest.size > region.capacity() {
+            return Err(Error::TooLarge(manifest.size));
+        }
+
+        let mut rx_buffer = vec![0u8; CHUNK_SIZE];
+        let mut tx_buffer = vec![0u8; 512];
+        let mut response =
+            HttpResponse::get(stack, &manifest.url, &mut rx_buffer, &mut tx_buffer).await?;
+        if !response.head.has_length(manifest.size) {
+            return Err(Error::SizeMismatch);
+        }
+
+        info!("Writing {} bytes to {:?}", manifest.size, slot);
+        let mut hasher = Sha256::new();
+        let mut chunk = vec![0u8; CHUNK_SIZE];
+        let mut written = 0;
+        while written < manifest.size {
+            let len = CHUNK_SIZE.min(manifest.size - written);
+            response.read_exact(&mut chunk[..len]).await?;
+            hasher.update(&chunk[..len]);
+            region
+                .write(written as u32, &chunk[..len])
+                .map_err(|_| Error::Flash)?;
+            written += len;
+        }
+
+        if hasher.finalize()[..] != digest[..] {
+            return Err(Error::Checksum);
+        }
+
+        updater.activate_next_partition()?;
+        updater.set_current_ota_state(OtaImageState::New)?;
+        info!("Firmware {} activated", manifest.version);
+        Ok(())
+    }
+
+    /// Give up on the running image and reset into the previous slot.
+    fn roll_back(&mut self) -> ! {
+        let result = self.with_updater(|updater| {
+            updater.set_current_ota_state(OtaImageState::Invalid)?;
+            updater.activate_next_partition()
+        });
+        if let Err(error) = result {
+            error!("Rollback failed: {error}");
+        }
+        software_reset()
+    }
+
+    fn set_state(&mut self, state: OtaImageState) -> Result<(), Error> {
+        self.with_updater(|updater| updater.set_current_ota_state(state))
+    }
+
+    fn with_updater<T>(
+        &mut self,
+        f: impl FnOnce(&mut OtaUpdater<'_, FlashStorage<'static>>) -> Result<T, partitions::Error>,
+    ) -> Result<T, Error> {
+        let mut table = [0u8; PARTITION_TABLE_MAX_LEN];
+        let mut updater = OtaUpdater::new(&mut self.flash, &mut table)?;
+        Ok(f(&mut updater)?)
+    }
+}
+
+async fn fetch_manifest(stack: Stack<'_>, url: &str) -> Result<Manifest, Error> {
+    let mut rx_buffer = vec![0u8; MAX_MANIFEST_SIZE];
+    let mut tx_buffer = vec![0u8; 512];
+    let mut response = HttpResponse::get(stack, url, &mut rx_buffer, &mut tx_buffer).await?;
+
+    let length = response.head.content_length.ok_or(Error::Http)?;
+    if length > MAX_MANIFEST_SIZE {
+        return Err(Error::Manifest);
+    }
+    let mut body = vec![0u8; length];
+    response.read_exact(&mut body).await?;
+    serde_json::from_slice(&body).map_err(|_| Error::Manifest)
+}
+
+/// Minimal HTTP/1.0 client: a LAN update server needs no TLS, redirects or
+/// chunked encoding (an HTTP/1.0 response is never chunked).
+struct HttpResponse<'a> {
+    socket: TcpSocket<'a>,
+    head: ResponseHead,
+    /// Everything read while looking for the end of the headers
+    buffer: Vec<u8>,
+    /// Start of the not yet consumed body bytes in `buffer`
+    body_start: usize,
+}
+
+impl<'a> HttpResponse<'a> {
+    /// Send a GET for `url` (`http://host[:port]/path`) and read the response
+    /// headers. Anything but `200 OK` is an error.
+    async fn get(
+        stack: Stack<'a>,
+        url: &str,
+        rx_buffer: &'a mut [u8],
+        tx_buffer: &'a mut [u8],
+    ) -> Result<Self, Error> {
+        let (host, port, path) = http::parse_url(url).ok_or(Error::Url)?;
+        let address = stack
+            .dns_query(host, DnsQueryType::A)
+            .await?
+            .first()
+            .copied()
+            .ok_or(Error::Url)?;
+
+        let mut socket = TcpSocket::new(stack, rx_buffer, tx_buffer);
+        socket.set_timeout(Some(Duration::from_secs(OTA_SOCKET_TIMEOUT_SECONDS)));
+        socket.connect((address, port)).await?;
+
+        let request = format!("GET {path} HTTP/1.0\r\nHost: {host}\r\n\r\n");
+        let mut sent = 0;
+        while sent < request.len() {
+            match socket.write(&request.as_bytes()[sent..]).await? {
+                0 => return Err(Error::Http),
+                n => sent += n,
+            }
+        }
+        socket.flush().await?;
+
+        let mut buffer = vec![0u8; MAX_HEADER_SIZE];
+        let mut len = 0;
+        let body_start = loop {
+            if len == buffer.len() {
+                return Err(Error::Http);
+            }
+            match socket.read(&mut buffer[len..]).await? {
+                0 => return Err(Error::Http),
+                n => len += n,
+            }
+            if let Some(end) = ResponseHead::end(&buffer[..len]) {
+                break end;
+            }
+        };
+        buffer.truncate(len);
+
+        let head = ResponseHead::parse(&buffer[..body_start]).ok_or(Error::Http)?;
+        if head.status != 200 {
+            return Err(Error::Status(head.status));
+        }
+
+        Ok(Self {
+            socket,
+            head,
+            buffer,
+            body_start,
+        })
+    }
+
+    /// Fill `buf` completely; a connection closed early is an error.
+    async fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            let buffered = &self.buffer[self.body_start..];
+            let n = if buffered.is_empty() {
+                self.socket.read(&mut buf[filled..]).await?
+            } else {
+                let n = buffered.len().min(buf.len() - filled);
+                buf[filled..filled + n].copy_from_slice(&buffered[..n]);
+                self.body_start += n;
+                n
+            };
+            if n == 0 {
+                return Err(Error::Truncated);
+            }
+            filled += n;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Partition(partitions::Error),
+    Flash,
+    Url,
+    Dns(DnsError),
+    Connection(ConnectError),
+    Socket(tcp::Error),
+    Http,
+    Status(u16),
+    Manifest,
+    TooLarge(usize),
+    SizeMismatch,
+    Truncated,
+    Checksum,
+    PublicKey,
+    Signature,
+    Timeout,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::Partition(e) => write!(f, "Partition error: {e:?}"),
+            Error::Flash => write!(f, "Flash write failed"),
+            Error::Url => write!(f, "Invalid update URL"),
+            Error::Dns(e) => write!(f, "DNS error: {e:?}"),
+            Error::Connection(e) => write!(f, "Connection error: {e:?}"),
+            Error::Socket(e) => write!(f, "Socket error: {e:?}"),
+            Error::Http => write!(f, "Malformed HTTP response"),
+            Error::Status(code) => write!(f, "HTTP status {code}"),
+            Error::Manifest => write!(f, "Invalid update manifest"),
+            Error::TooLarge(size) => write!(f, "Image of {size} bytes exceeds the OTA slot"),
+            Error::SizeMismatch => write!(f, "Image size differs from the manifest"),
+            Error::Truncated => write!(f, "Image download truncated"),
+            Error::Checksum => write!(f, "Image SHA-256 mismatch"),
+            Error::PublicKey => write!(f, "No valid OTA public key compiled in"),
+            Error::Signature => write!(f, "Manifest signature invalid"),
+            Error::Timeout => write!(f, "Update timed out"),
+        }
+    }
+}
+
+impl From<verify::SignatureError> for Error {
+    fn from(error: verify::SignatureError) -> Self {
+        match error {
+            verify::SignatureError::PublicKey => Self::PublicKey,
+            verify::SignatureError::Encoding => Self::Manifest,
+            verify::SignatureError::Mismatch => Self::Signature,
+        }
+    }
+}
+
+impl From<partitions::Error> for Error {
+    fn from(error: partitions::Error) -> Self {
+        Self::Partition(error)
+    }
+}
+
+impl From<DnsError> for Error {
+    fn from(error: DnsError) -> Self {
+        Self::Dns(error)
+    }
+}
+
+impl From<ConnectError> for Error {
+    fn from(error: ConnectError) -> Self {
+        Self::Connection(error)
+    }
+}
+
+impl From<tcp::Error> for Error {
+    fn from(error: tcp::Error) -> Self {
+        Self::Socket(error)
+    }
+}
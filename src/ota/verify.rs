@@ -0,0 +1,270 @@
+//! Checks on an update manifest before anything is downloaded
+//!
+//! Only a later version than the running firmware is installed, and only
+//! with a valid Ed25519 signature over the image digest and that version.
+//! No hardware involved, so the host tests cover it.
+
+use alloc::vec::Vec;
+use core::{cmp::Ordering, str};
+
+use ed25519_dalek::{Signature, VerifyingKey};
+
+/// Why a manifest signature was not accepted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignatureError {
+    /// The public key isn't 64 hex digits of a valid Ed25519 key
+    PublicKey,
+    /// The signature isn't 128 hex digits
+    Encoding,
+    /// Not signed by the key, or the digest or version was changed
+    Mismatch,
+}
+
+/// Whether `version` is a later release than `running`, by semver
+/// precedence. A malformed version is never newer.
+pub fn is_newer(version: &str, running: &str) -> bool {
+    compare_versions(version, running) == Some(Ordering::Greater)
+}
+
+/// What the signing key signs: the image digest followed by the version, so
+/// neither can be swapped without the key. Must match `tools/ota-sign`.
+pub fn signed_message(digest: &[u8; 32], version: &str) -> Vec<u8> {
+    [digest.as_slice(), version.as_bytes()].concat()
+}
+
+/// Check the hex-encoded `signature` over `digest` and `version` against the
+/// hex-encoded `public_key`.
+pub fn verify_signature(
+    public_key: &str,
+    signature: &str,
+    digest: &[u8; 32],
+    version: &str,
+) -> Result<(), SignatureError> {
+    let public_key = parse_hex::<32>(public_key)
+        .and_then(|key| VerifyingKey::from_bytes(&key).ok())
+        .ok_or(SignatureError::PublicKey)?;
+    let signature = parse_hex::<64>(signature).ok_or(SignatureError::Encoding)?;
+    public_key
+        .verify_strict(
+            &signed_message(digest, version),
+            &Signature::from_bytes(&signature),
+        )
+        .map_err(|_| SignatureError::Mismatch)
+}
+
+/// Semver precedence of `a` over `b`; `None` if either isn't
+/// `major.minor.patch[-pre][+build]`. Build metadata doesn't count.
+fn compare_versions(a: &str, b: &str) -> Option<Ordering> {
+    let (a_core, a_pre) = split_version(a)?;
+    let (b_core, b_pre) = split_version(b)?;
+    Some(a_core.cmp(&b_core).then_with(|| match (a_pre, b_pre) {
+        (None, None) => Ordering::Equal,
+        // A release follows its pre-releases
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a), Some(b)) => compare_pre_releases(a, b),
+    }))
+}
+
+fn split_version(version: &str) -> Option<([u64; 3], Option<&str>)> {
+    let version = version
+        .split_once('+')
+        .map_or(version, |(version, _)| version);
+    let (core, pre) = match version.split_once('-') {
+        Some((core, pre)) => (core, Some(pre)),
+        None => (version, None),
+    };
+    if pre.is_some_and(|pre| pre.split('.').any(str::is_empty)) {
+        return None;
+    }
+    let mut numbers = core.split('.').map(parse_number);
+    let core = [numbers.next()??, numbers.next()??, numbers.next()??];
+    numbers.next().is_none().then_some((core, pre))
+}
+
+/// Dot-separated identifiers in turn: numeric ones compare as numbers and
+/// sort before alphanumeric ones; with equal identifiers the longer list wins.
+fn compare_pre_releases(a: &str, b: &str) -> Ordering {
+    let mut a = a.split('.');
+    let mut b = b.split('.');
+    loop {
+        let order = match (a.next(), b.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a), Some(b)) => match (parse_number(a), parse_number(b)) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => a.cmp(b),
+            },
+        };
+        if order != Ordering::Equal {
+            return order;
+        }
+    }
+}
+
+/// Digits only: `str::parse` would also take a leading `+`
+fn parse_number(number: &str) -> Option<u64> {
+    number
+        .bytes()
+        .all(|byte| byte.is_ascii_digit())
+        .then(|| number.parse().ok())?
+}
+
+/// Exactly `2 * N` hex digits, either case
+pub fn parse_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    if hex.len() != 2 * N || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut bytes = [0u8; N];
+    for (byte, pair) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{format, string::String};
+
+    use ed25519_dalek::{Signer, SigningKey};
+
+    use super::*;
+
+    const DIGEST: [u8; 32] = [0xab; 32];
+
+    fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    /// Public key and signature over `digest` and `version`, hex encoded as
+    /// `tools/ota-sign` writes them
+    fn sign(seed: u8, digest: &[u8; 32], version: &str) -> (String, String) {
+        let key = SigningKey::from_bytes(&[seed; 32]);
+        let signature = key.sign(&signed_message(digest, version));
+        (
+            to_hex(key.verifying_key().as_bytes()),
+            to_hex(&signature.to_bytes()),
+        )
+    }
+
+    #[test]
+    fn only_later_versions_are_newer() {
+        assert!(is_newer("0.2.0", "0.1.9"));
+        assert!(is_newer("0.10.0", "0.9.0"));
+        assert!(is_newer("1.0.0", "0.99.99"));
+        assert!(!is_newer("0.1.0", "0.1.0"));
+        assert!(!is_newer("0.1.0", "0.2.0"));
+        assert!(!is_newer("0.1.9", "0.2.0"));
+        // Build metadata doesn't count
+        assert!(!is_newer("0.1.0+abc", "0.1.0+def"));
+        assert!(is_newer("0.1.1+abc", "0.1.0"));
+    }
+
+    #[test]
+    fn pre_releases_precede_their_release() {
+        assert!(is_newer("1.2.0", "1.2.0-rc.1"));
+        assert!(!is_newer("1.2.0-rc.1", "1.2.0"));
+        assert!(is_newer("1.2.0-rc.1", "1.1.9"));
+        assert!(is_newer("1.2.0-rc.2", "1.2.0-rc.1"));
+        assert!(is_newer("1.2.0-rc.10", "1.2.0-rc.9"));
+        assert!(is_newer("1.2.0-rc", "1.2.0-beta"));
+        // Numeric identifiers sort before alphanumeric ones
+        assert!(is_newer("1.2.0-alpha", "1.2.0-1"));
+        // With equal identifiers the longer list wins
+        assert!(is_newer("1.2.0-rc.1.1", "1.2.0-rc.1"));
+        assert_eq!(
+            compare_versions("1.2.0-rc.1", "1.2.0-rc.1+build"),
+            Some(Ordering::Equal)
+        );
+    }
+
+    #[test]
+    fn malformed_versions_are_never_newer() {
+        for version in [
+            "",
+            "1",
+            "1.2",
+            "1.2.3.4",
+            "1.2.x",
+            "v1.2.3",
+            "+1.2.3",
+            "1.-2.3",
+            "1..3",
+            "1.2.3-",
+            "1.2.3-rc..1",
+            " 1.2.3",
+            "99999999999999999999.0.0",
+        ] {
+            assert_eq!(split_version(version), None, "{version}");
+            assert!(!is_newer(version, "0.1.0"), "{version}");
+            assert!(!is_newer("9.9.9", version), "{version}");
+        }
+    }
+
+    #[test]
+    fn signature_round_trip() {
+        let (public_key, signature) = sign(7, &DIGEST, "0.2.0");
+        assert_eq!(
+            verify_signature(&public_key, &signature, &DIGEST, "0.2.0"),
+            Ok(())
+        );
+        assert_eq!(
+            verify_signature(
+                &public_key.to_uppercase(),
+                &signature.to_uppercase(),
+                &DIGEST,
+                "0.2.0"
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn tampered_manifests_are_rejected() {
+        let (public_key, signature) = sign(7, &DIGEST, "0.2.0");
+        let mut digest = DIGEST;
+        digest[31] ^= 1;
+        assert_eq!(
+            verify_signature(&public_key, &signature, &digest, "0.2.0"),
+            Err(SignatureError::Mismatch)
+        );
+        assert_eq!(
+            verify_signature(&public_key, &signature, &DIGEST, "0.2.1"),
+            Err(SignatureError::Mismatch)
+        );
+        // Signed with another key
+        let (_, other) = sign(8, &DIGEST, "0.2.0");
+        assert_eq!(
+            verify_signature(&public_key, &other, &DIGEST, "0.2.0"),
+            Err(SignatureError::Mismatch)
+        );
+        assert_eq!(
+            verify_signature(&public_key, &signature[1..], &DIGEST, "0.2.0"),
+            Err(SignatureError::Encoding)
+        );
+        assert_eq!(
+            verify_signature("", &signature, &DIGEST, "0.2.0"),
+            Err(SignatureError::PublicKey)
+        );
+    }
+
+    #[test]
+    fn hex_must_be_exact() {
+        let lower = "00ff10ab".repeat(8);
+        let expected: [u8; 32] = [[0x00, 0xff, 0x10, 0xab]; 8].concat().try_into().unwrap();
+        assert_eq!(parse_hex::<32>(&lower), Some(expected));
+        assert_eq!(parse_hex::<32>(&lower.to_uppercase()), Some(expected));
+        assert_eq!(parse_hex::<32>(&lower[1..]), None);
+        assert_eq!(parse_hex::<32>(&format!("{lower}0")), None);
+        assert_eq!(parse_hex::<32>(&lower.replacen('a', "g", 1)), None);
+        // `from_str_radix` alone would take a sign
+        assert_eq!(parse_hex::<2>("+f0f"), None);
+        assert_eq!(parse_hex::<2>("-f0f"), None);
+        assert_eq!(parse_hex::<2>(" f0f"), None);
+        // Two bytes of UTF-8 are no hex digits
+        assert_eq!(parse_hex::<2>("éab"), None);
+    }
+}
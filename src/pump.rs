@@ -2,14 +2,21 @@ use embassy_time::{Duration, Timer};
 use esp_hal::gpio::Output;
 use log::info;
 
+use crate::{PUMP_RUN_COUNT, PUMP_RUNTIME_SECONDS};
+
 const PUMP_RUN_DURATION: Duration = Duration::from_secs(10);
 
 /// Run the pump for the fixed watering duration. Awaited inline by the wake
-/// cycle so deep sleep can never cut a run short.
-pub async fn run_pump(relay_pin: &mut Output<'_>) {
+/// cycle so deep sleep can never cut a run short. Returns the run duration
+/// after adding it to the run statistics in RTC memory.
+pub async fn run_pump(relay_pin: &mut Output<'_>) -> Duration {
     info!("Turning on pump");
     relay_pin.set_high();
     Timer::after(PUMP_RUN_DURATION).await;
     relay_pin.set_low();
     info!("Pump off after {} s", PUMP_RUN_DURATION.as_secs());
+
+    PUMP_RUN_COUNT.set(PUMP_RUN_COUNT.get() + 1);
+    PUMP_RUNTIME_SECONDS.set(PUMP_RUNTIME_SECONDS.get() + PUMP_RUN_DURATION.as_secs() as u32);
+    PUMP_RUN_DURATION
 }
@@ -0,0 +1,142 @@
+//! Reset-reason history
+//!
+//! Every boot that is not a wake from deep sleep is recorded with its reset
+//! reason and boot count, so a brownout or watchdog reset that happened while
+//! nobody was watching still shows up in HA later. A run of consecutive
+//! brownout/watchdog resets is a reset loop: the next cycle runs in safe mode
+//! instead of powering the radio again.
+
+use esp_hal::{Persistable, rtc_cntl::SocResetReason};
+
+use crate::{
+    RESET_HISTORY,
+    config::{RESET_HISTORY_LEN, RESET_LOOP_THRESHOLD},
+};
+
+/// Recent resets, newest first, as kept in RTC memory.
+///
+/// Only integers, so whatever a reset halfway through an update leaves behind
+/// is still a valid value; lengths are clamped on every read.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct ResetHistory {
+    len: u32,
+    /// Consecutive brownout/watchdog resets, ended by any other boot
+    fault_streak: u32,
+    /// Set when an entry was added that hasn't been published yet
+    unpublished: u32,
+    /// Raw `SocResetReason` codes, 0 for an unknown reason
+    reasons: [u32; RESET_HISTORY_LEN],
+    boot_counts: [u32; RESET_HISTORY_LEN],
+}
+
+// SAFETY: `ResetHistory` contains only integers and integer arrays, which are
+// valid for any bit pattern.
+unsafe impl Persistable for ResetHistory {}
+
+/// One recorded reset.
+pub struct ResetEntry {
+    pub reason: Option<SocResetReason>,
+    pub boot_count: u32,
+}
+
+impl ResetHistory {
+    pub const EMPTY: Self = Self {
+        len: 0,
+        fault_streak: 0,
+        unpublished: 0,
+        reasons: [0; RESET_HISTORY_LEN],
+        boot_counts: [0; RESET_HISTORY_LEN],
+    };
+
+    /// Wakes from deep sleep aren't kept, they only end a fault streak.
+    fn record(&mut self, reason: Option<SocResetReason>, boot_count: u32) {
+        self.fault_streak = if reason.is_some_and(is_fault) {
+            self.fault_streak.saturating_add(1)
+        } else {
+            0
+        };
+        if reason == Some(SocResetReason::CoreDeepSleep) {
+            return;
+        }
+
+        self.reasons.copy_within(..RESET_HISTORY_LEN - 1, 1);
+        self.boot_counts.copy_within(..RESET_HISTORY_LEN - 1, 1);
+        self.reasons[0] = reason.map_or(0, |reason| reason as u32);
+        self.boot_counts[0] = boot_count;
+        self.len = (self.len() + 1) as u32;
+        self.unpublished = 1;
+    }
+
+    /// Recorded resets, newest first
+    pub fn entries(&self) -> impl Iterator<Item = ResetEntry> + '_ {
+        self.reasons
+            .iter()
+            .zip(&self.boot_counts)
+            .take(self.len())
+            .map(|(&reason, &boot_count)| ResetEntry {
+                reason: SocResetReason::from_repr(reason as usize),
+                boot_count,
+            })
+    }
+
+    /// Consecutive brownout/watchdog resets up to and including this boot
+    pub fn fault_streak(&self) -> u32 {
+        self.fault_streak
+    }
+
+    /// `RESET_LOOP_THRESHOLD` brownout/watchdog resets in a row: the device
+    /// keeps dying under load and should stay off the radio for a while.
+    pub fn in_reset_loop(&self) -> bool {
+        self.fault_streak >= RESET_LOOP_THRESHOLD
+    }
+
+    fn len(&self) -> usize {
+        (self.len as usize).min(RESET_HISTORY_LEN)
+    }
+}
+
+/// Record the reset that started this boot and return the updated history.
+pub fn record(reason: Option<SocResetReason>, boot_count: u32) -> ResetHistory {
+    // Persistent RTC memory holds garbage after power-on
+    if reason == Some(SocResetReason::ChipPowerOn) {
+        RESET_HISTORY.set(ResetHistory::EMPTY);
+    }
+    let mut history = RESET_HISTORY.get();
+    history.record(reason, boot_count);
+    RESET_HISTORY.set(history);
+    history
+}
+
+/// The history, if it has changed since it was last published.
+pub fn unpublished() -> Option<ResetHistory> {
+    Some(RESET_HISTORY.get()).filter(|history| history.unpublished != 0)
+}
+
+/// Call once the broker has acknowledged the history.
+pub fn mark_published() {
+    let mut history = RESET_HISTORY.get();
+    history.unpublished = 0;
+    RESET_HISTORY.set(history);
+}
+
+/// Resets that mean the previous cycle died instead of going to sleep: the
+/// supply collapsed or the firmware stopped feeding a watchdog. Power-on,
+/// software resets (OTA, the HA reboot button) and USB resets from flashing
+/// are deliberate.
+fn is_fault(reason: SocResetReason) -> bool {
+    matches!(
+        reason,
+        SocResetReason::SysBrownOut
+            | SocResetReason::CorePwrGlitch
+            | SocResetReason::SysClkGlitch
+            | SocResetReason::CoreMwdt0
+            | SocResetReason::CoreMwdt1
+            | SocResetReason::CoreRtcWdt
+            | SocResetReason::CpuMwdt0
+            | SocResetReason::CpuMwdt1
+            | SocResetReason::CpuRtcWdt
+            | SocResetReason::SysRtcWdt
+            | SocResetReason::SysSuperWdt
+    )
+}
@@ -130,3 +130,8 @@ impl<T> RtcCell<T> {
 // transferred between tasks, which is sufficient for our use case since
 // we never expose direct references to T across task boundaries.
 unsafe impl<T> Sync for RtcCell<T> where T: Send {}
+
+// SAFETY: `RtcCell<T>` holds nothing but a `T`, so it is valid for every bit
+// pattern `T` is valid for. This allows `RtcCell` statics in
+// `#[ram(unstable(rtc_fast, persistent))]`.
+unsafe impl<T> esp_hal::Persistable for RtcCell<T> where T: esp_hal::Persistable {}
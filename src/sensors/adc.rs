@@ -1,91 +1,141 @@
-use embassy_time::{Duration, Timer};
+use alloc::boxed::Box;
+use core::{cell::RefCell, future::ready};
+
+use embassy_time::Duration;
 use esp_hal::{
     Blocking,
-    analog::adc::{Adc, AdcCalLine, AdcCalScheme, AdcChannel, AdcPin, RegisterAccess},
+    analog::adc::{Adc, AdcCalLine, AdcCalScheme, AdcChannel, AdcPin},
     gpio::Output,
     peripherals::{ADC1, GPIO4},
 };
-use log::{error, info};
-
-use crate::config::{SENSOR_WARMUP_DELAY_MS, USB_CHARGING_VOLTAGE_MV};
-
-/// Power pin on → warmup → ADC sample → power pin off.
-///
-/// Unified reader for any powered ADC sensor (soil moisture, water level).
-/// Each call site is monomorphised over its concrete pin/ADC types.
-pub(super) async fn read_powered_adc_sensor<'a, PIN, ADCI, ADCC>(
-    adc: &mut Adc<'a, ADCI, Blocking>,
-    pin: &mut AdcPin<PIN, ADCI, ADCC>,
-    power_pin: &mut Output<'a>,
-) -> Option<u16>
-where
-    PIN: AdcChannel,
-    ADCI: RegisterAccess + 'a,
-    ADCC: AdcCalScheme<ADCI>,
-{
-    power_pin.set_high();
-    let result = sample_adc_with_warmup(adc, pin, SENSOR_WARMUP_DELAY_MS).await;
-    power_pin.set_low();
-    result
+use log::info;
+
+use crate::{
+    config::{BATTERY_DIVIDER_RATIO, SENSOR_WARMUP_DELAY_MS, USB_CHARGING_VOLTAGE_MV},
+    domain::Quantity,
+};
+
+use super::driver::{ReadFuture, Reading, SensorDriver, SensorError};
+
+/// ADC1, shared by all analog drivers
+pub(super) type SharedAdc = &'static RefCell<Adc<'static, ADC1<'static>, Blocking>>;
+
+/// Analog probe powered through a GPIO only while it is sampled (soil
+/// moisture, water level), which saves power and slows electrode corrosion.
+pub(super) struct PoweredAdcSensor<PIN, ADCC> {
+    name: &'static str,
+    quantity: &'static [Quantity; 1],
+    adc: SharedAdc,
+    pin: AdcPin<PIN, ADC1<'static>, ADCC>,
+    power_pin: Output<'static>,
 }
 
-/// Read battery voltage, applying the 2× voltage divider and filtering out USB-charging readings.
-pub(super) async fn read_battery_voltage<'a>(
-    adc: &mut Adc<'a, ADC1<'a>, Blocking>,
-    pin: &mut AdcPin<GPIO4<'a>, ADC1<'a>, AdcCalLine<ADC1<'a>>>,
-) -> Option<u16> {
-    let value = sample_adc_with_warmup(adc, pin, SENSOR_WARMUP_DELAY_MS).await? * 2;
-
-    if value < USB_CHARGING_VOLTAGE_MV {
-        Some(value)
-    } else {
-        info!(
-            "Battery voltage too high - looks we are charging on USB: {}mV",
-            value
-        );
-        None
+impl<PIN, ADCC> PoweredAdcSensor<PIN, ADCC> {
+    pub(super) fn new(
+        name: &'static str,
+        quantity: &'static [Quantity; 1],
+        adc: SharedAdc,
+        pin: AdcPin<PIN, ADC1<'static>, ADCC>,
+        power_pin: Output<'static>,
+    ) -> Self {
+        Self {
+            name,
+            quantity,
+            adc,
+            pin,
+            power_pin,
+        }
     }
 }
 
-/// Sample an ADC pin after a configurable warmup delay.
-pub(super) async fn sample_adc_with_warmup<'a, PIN, ADCI, ADCC>(
-    adc: &mut Adc<'a, ADCI, Blocking>,
-    pin: &mut AdcPin<PIN, ADCI, ADCC>,
-    warmup_ms: u64,
-) -> Option<u16>
+impl<PIN, ADCC> SensorDriver for PoweredAdcSensor<PIN, ADCC>
 where
     PIN: AdcChannel,
-    ADCI: RegisterAccess + 'a,
-    ADCC: AdcCalScheme<ADCI>,
+    ADCC: AdcCalScheme<ADC1<'static>>,
 {
-    Timer::after(Duration::from_millis(warmup_ms)).await;
-    match nb::block!(adc.read_oneshot(pin)) {
-        Ok(value) => Some(value),
-        Err(e) => {
-            error!("Error reading sensor: {:?}", &e);
-            None
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn quantities(&self) -> &'static [Quantity] {
+        self.quantity
+    }
+
+    fn warmup(&self) -> Duration {
+        Duration::from_millis(SENSOR_WARMUP_DELAY_MS)
+    }
+
+    fn set_power(&mut self, on: bool) {
+        if on {
+            self.power_pin.set_high();
+        } else {
+            self.power_pin.set_low();
         }
     }
+
+    fn read(&mut self) -> ReadFuture<'_> {
+        let quantity = self.quantity[0];
+        let result = sample(self.adc, &mut self.pin)
+            .map(|value| Reading::default().with(quantity, value.into()));
+        Box::pin(ready(result))
+    }
 }
 
-/// Calculate the trimmed mean of a sample slice: remove the min and max, then average the rest.
-///
-/// Returns `None` if fewer than 3 samples are present.
-pub(super) fn calculate_average<T>(samples: &mut [T]) -> Option<T>
-where
-    T: Copy + Ord + Into<i32>,
-    i32: TryInto<T>,
-{
-    if samples.len() <= 2 {
-        return None;
+/// Battery voltage behind the board's `BATTERY_DIVIDER_RATIO` divider. Readings above
+/// `USB_CHARGING_VOLTAGE_MV` mean the board runs on USB and are dropped.
+pub(super) struct BatteryDivider {
+    adc: SharedAdc,
+    pin: AdcPin<GPIO4<'static>, ADC1<'static>, AdcCalLine<ADC1<'static>>>,
+}
+
+impl BatteryDivider {
+    pub(super) fn new(
+        adc: SharedAdc,
+        pin: AdcPin<GPIO4<'static>, ADC1<'static>, AdcCalLine<ADC1<'static>>>,
+    ) -> Self {
+        Self { adc, pin }
+    }
+}
+
+impl SensorDriver for BatteryDivider {
+    fn name(&self) -> &'static str {
+        "Battery"
     }
 
-    // Sort and remove outliers (first = lowest, last = highest)
-    samples.sort_unstable();
-    let samples = &samples[1..samples.len() - 1];
+    fn quantities(&self) -> &'static [Quantity] {
+        &[Quantity::BatteryVoltage]
+    }
 
-    let sum: i32 = samples.iter().map(|&x| x.into()).sum();
-    sum.checked_div(samples.len() as i32)
-        .and_then(|avg| avg.try_into().ok())
-        .or(None)
+    fn warmup(&self) -> Duration {
+        Duration::from_millis(SENSOR_WARMUP_DELAY_MS)
+    }
+
+    fn read(&mut self) -> ReadFuture<'_> {
+        let result = sample(self.adc, &mut self.pin).map(|value| {
+            let value = value * BATTERY_DIVIDER_RATIO;
+            if value < USB_CHARGING_VOLTAGE_MV {
+                Reading::default().with(Quantity::BatteryVoltage, value.into())
+            } else {
+                info!(
+                    "Battery voltage too high - looks we are charging on USB: {}mV",
+                    value
+                );
+                Reading::default()
+            }
+        });
+        Box::pin(ready(result))
+    }
+}
+
+/// One-shot read of an ADC pin. The conversion is blocking, so the drivers
+/// return futures that are ready right away.
+fn sample<PIN, ADCC>(
+    adc: SharedAdc,
+    pin: &mut AdcPin<PIN, ADC1<'static>, ADCC>,
+) -> Result<u16, SensorError>
+where
+    PIN: AdcChannel,
+    ADCC: AdcCalScheme<ADC1<'static>>,
+{
+    nb::block!(adc.borrow_mut().read_oneshot(pin)).map_err(|_| SensorError::Adc)
 }
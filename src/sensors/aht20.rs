@@ -0,0 +1,84 @@
+use alloc::boxed::Box;
+
+use embassy_time::{Duration, Timer};
+
+use crate::domain::Quantity;
+
+use super::{
+    driver::{ReadFuture, Reading, SensorDriver, SensorError},
+    i2c::{self, SharedI2c, crc8},
+};
+
+const ADDRESS: u8 = 0x38;
+const STATUS: [u8; 1] = [0x71];
+const INITIALIZE: [u8; 3] = [0xBE, 0x08, 0x00];
+const TRIGGER: [u8; 3] = [0xAC, 0x33, 0x00];
+const STATUS_BUSY: u8 = 0x80;
+const STATUS_CALIBRATED: u8 = 0x08;
+/// 80 ms per the datasheet
+const MEASURE_TIME: Duration = Duration::from_millis(80);
+
+/// Aosong AHT20 temperature and humidity sensor
+pub(super) struct Aht20 {
+    bus: SharedI2c,
+}
+
+impl Aht20 {
+    /// Check the chip answers, and load its calibration if it hasn't yet.
+    pub(super) async fn probe(bus: SharedI2c) -> Result<Self, SensorError> {
+        let mut status = [0];
+        i2c::write_read(bus, ADDRESS, &STATUS, &mut status)?;
+        if status[0] & STATUS_CALIBRATED == 0 {
+            i2c::write(bus, ADDRESS, &INITIALIZE)?;
+            Timer::after(Duration::from_millis(10)).await;
+        }
+        Ok(Self { bus })
+    }
+
+    async fn measure(&mut self) -> Result<Reading, SensorError> {
+        i2c::write(self.bus, ADDRESS, &TRIGGER)?;
+        Timer::after(MEASURE_TIME).await;
+        let mut data = [0; 7];
+        i2c::read(self.bus, ADDRESS, &mut data)?;
+        if data[0] & STATUS_BUSY != 0 {
+            return Err(SensorError::Timeout);
+        }
+        if crc8(&data[0..6]) != data[6] {
+            return Err(SensorError::Checksum);
+        }
+        // Two 20-bit values: humidity, then temperature
+        let humidity =
+            (u32::from(data[1]) << 12) | (u32::from(data[2]) << 4) | (u32::from(data[3]) >> 4);
+        let temperature =
+            (u32::from(data[3] & 0x0F) << 16) | (u32::from(data[4]) << 8) | u32::from(data[5]);
+        Ok(Reading::default()
+            // -50 °C + 200 °C × raw / 2^20, in tenths
+            .with(
+                Quantity::AirTemperature,
+                ((temperature * 2000 + (1 << 19)) >> 20) as i32 - 500,
+            )
+            // 100 % × raw / 2^20, in tenths
+            .with(
+                Quantity::AirHumidity,
+                ((humidity * 1000 + (1 << 19)) >> 20) as i32,
+            ))
+    }
+}
+
+impl SensorDriver for Aht20 {
+    fn name(&self) -> &'static str {
+        "AHT20"
+    }
+
+    fn quantities(&self) -> &'static [Quantity] {
+        &[Quantity::AirTemperature, Quantity::AirHumidity]
+    }
+
+    fn warmup(&self) -> Duration {
+        Duration::from_millis(0)
+    }
+
+    fn read(&mut self) -> ReadFuture<'_> {
+        Box::pin(self.measure())
+    }
+}
@@ -0,0 +1,64 @@
+use alloc::boxed::Box;
+
+use embassy_time::{Duration, Timer};
+
+use crate::domain::Quantity;
+
+use super::{
+    driver::{ReadFuture, Reading, SensorDriver, SensorError},
+    i2c::{self, SharedI2c},
+};
+
+/// ADDR to GND, then ADDR to VCC
+const ADDRESSES: [u8; 2] = [0x23, 0x5C];
+const POWER_ON: [u8; 1] = [0x01];
+/// One-time high-resolution mode (1 lx); the chip powers down afterwards
+const MEASURE: [u8; 1] = [0x20];
+/// 180 ms maximum at the default measurement time
+const MEASURE_TIME: Duration = Duration::from_millis(180);
+
+/// Rohm BH1750 ambient light sensor in one-time mode
+pub(super) struct Bh1750 {
+    bus: SharedI2c,
+    address: u8,
+}
+
+impl Bh1750 {
+    /// Find the chip at either address.
+    pub(super) async fn probe(bus: SharedI2c) -> Result<Self, SensorError> {
+        for address in ADDRESSES {
+            if i2c::write(bus, address, &POWER_ON).is_ok() {
+                return Ok(Self { bus, address });
+            }
+        }
+        Err(SensorError::NotFound)
+    }
+
+    async fn measure(&mut self) -> Result<Reading, SensorError> {
+        i2c::write(self.bus, self.address, &MEASURE)?;
+        Timer::after(MEASURE_TIME).await;
+        let mut data = [0; 2];
+        i2c::read(self.bus, self.address, &mut data)?;
+        let raw = i32::from(u16::from_be_bytes(data));
+        // raw / 1.2, rounded
+        Ok(Reading::default().with(Quantity::Illuminance, (raw * 5 + 3) / 6))
+    }
+}
+
+impl SensorDriver for Bh1750 {
+    fn name(&self) -> &'static str {
+        "BH1750"
+    }
+
+    fn quantities(&self) -> &'static [Quantity] {
+        &[Quantity::Illuminance]
+    }
+
+    fn warmup(&self) -> Duration {
+        Duration::from_millis(0)
+    }
+
+    fn read(&mut self) -> ReadFuture<'_> {
+        Box::pin(self.measure())
+    }
+}
@@ -0,0 +1,182 @@
+use alloc::boxed::Box;
+
+use embassy_time::{Duration, Timer};
+
+use crate::domain::Quantity;
+
+use super::{
+    driver::{ReadFuture, Reading, SensorDriver, SensorError},
+    i2c::{self, SharedI2c},
+};
+
+/// SDO to GND, then SDO to VDD
+const ADDRESSES: [u8; 2] = [0x76, 0x77];
+const CHIP_ID: u8 = 0x60;
+const REG_CHIP_ID: u8 = 0xD0;
+const REG_CALIBRATION_TP: u8 = 0x88;
+const REG_CALIBRATION_H: u8 = 0xE1;
+const REG_CTRL_HUM: u8 = 0xF2;
+const REG_CTRL_MEAS: u8 = 0xF4;
+const REG_DATA: u8 = 0xF7;
+/// Humidity oversampling ×1
+const CTRL_HUM: u8 = 0b001;
+/// Temperature oversampling ×1 (bits 7..5), pressure ×1 (4..2), forced mode
+const CTRL_MEAS: u8 = 0b0010_0101;
+/// 9.3 ms at ×1 oversampling of all three
+const MEASURE_TIME: Duration = Duration::from_millis(10);
+
+/// Bosch BME280 temperature, humidity and pressure sensor in forced mode
+pub(super) struct Bme280 {
+    bus: SharedI2c,
+    address: u8,
+    calibration: Calibration,
+}
+
+/// Trimming parameters from the chip's NVM
+struct Calibration {
+    t1: i32,
+    t2: i32,
+    t3: i32,
+    p: [i64; 9],
+    h1: i32,
+    h2: i32,
+    h3: i32,
+    h4: i32,
+    h5: i32,
+    h6: i32,
+}
+
+impl Bme280 {
+    /// Find the chip at either address and read its calibration.
+    pub(super) async fn probe(bus: SharedI2c) -> Result<Self, SensorError> {
+        for address in ADDRESSES {
+            let mut id = [0];
+            if i2c::write_read(bus, address, &[REG_CHIP_ID], &mut id).is_ok() && id[0] == CHIP_ID {
+                let calibration = Calibration::read(bus, address)?;
+                return Ok(Self {
+                    bus,
+                    address,
+                    calibration,
+                });
+            }
+        }
+        Err(SensorError::NotFound)
+    }
+
+    async fn measure(&mut self) -> Result<Reading, SensorError> {
+        i2c::write(self.bus, self.address, &[REG_CTRL_HUM, CTRL_HUM])?;
+        i2c::write(self.bus, self.address, &[REG_CTRL_MEAS, CTRL_MEAS])?;
+        Timer::after(MEASURE_TIME).await;
+        let mut data = [0; 8];
+        i2c::write_read(self.bus, self.address, &[REG_DATA], &mut data)?;
+
+        let adc_p =
+            (i32::from(data[0]) << 12) | (i32::from(data[1]) << 4) | (i32::from(data[2]) >> 4);
+        let adc_t =
+            (i32::from(data[3]) << 12) | (i32::from(data[4]) << 4) | (i32::from(data[5]) >> 4);
+        let adc_h = (i32::from(data[6]) << 8) | i32::from(data[7]);
+
+        let calibration = &self.calibration;
+        let t_fine = calibration.t_fine(adc_t);
+        // 0.01 °C to tenths
+        let temperature = (((t_fine * 5 + 128) >> 8) + 5).div_euclid(10);
+        // Pa in Q24.8 to hPa
+        let pressure = (calibration.pressure(adc_p, t_fine) + 12_800) / 25_600;
+        // %RH in Q22.10 to tenths
+        let humidity = (calibration.humidity(adc_h, t_fine) * 10 + 512) >> 10;
+        Ok(Reading::default()
+            .with(Quantity::AirTemperature, temperature)
+            .with(Quantity::AirHumidity, humidity)
+            .with(Quantity::AirPressure, pressure as i32))
+    }
+}
+
+impl SensorDriver for Bme280 {
+    fn name(&self) -> &'static str {
+        "BME280"
+    }
+
+    fn quantities(&self) -> &'static [Quantity] {
+        &[
+            Quantity::AirTemperature,
+            Quantity::AirHumidity,
+            Quantity::AirPressure,
+        ]
+    }
+
+    fn warmup(&self) -> Duration {
+        Duration::from_millis(0)
+    }
+
+    fn read(&mut self) -> ReadFuture<'_> {
+        Box::pin(self.measure())
+    }
+}
+
+/// Compensation formulas of the BME280 datasheet, section 4.2.3
+impl Calibration {
+    fn read(bus: SharedI2c, address: u8) -> Result<Self, SensorError> {
+        let mut tp = [0; 26];
+        i2c::write_read(bus, address, &[REG_CALIBRATION_TP], &mut tp)?;
+        let mut h = [0; 7];
+        i2c::write_read(bus, address, &[REG_CALIBRATION_H], &mut h)?;
+
+        let unsigned = |i: usize| i32::from(u16::from_le_bytes([tp[i], tp[i + 1]]));
+        let signed = |i: usize| i32::from(i16::from_le_bytes([tp[i], tp[i + 1]]));
+        let mut p = [0; 9];
+        p[0] = i64::from(unsigned(6));
+        for (n, value) in p.iter_mut().enumerate().skip(1) {
+            *value = i64::from(signed(6 + 2 * n));
+        }
+        Ok(Self {
+            t1: unsigned(0),
+            t2: signed(2),
+            t3: signed(4),
+            p,
+            h1: i32::from(tp[25]),
+            h2: i32::from(i16::from_le_bytes([h[0], h[1]])),
+            h3: i32::from(h[2]),
+            h4: (i32::from(h[3] as i8) << 4) | i32::from(h[4] & 0x0F),
+            h5: (i32::from(h[5] as i8) << 4) | i32::from(h[4] >> 4),
+            h6: i32::from(h[6] as i8),
+        })
+    }
+
+    fn t_fine(&self, adc_t: i32) -> i32 {
+        let var1 = (((adc_t >> 3) - (self.t1 << 1)) * self.t2) >> 11;
+        let delta = (adc_t >> 4) - self.t1;
+        let var2 = (((delta * delta) >> 12) * self.t3) >> 14;
+        var1 + var2
+    }
+
+    /// Pressure in Pa as Q24.8
+    fn pressure(&self, adc_p: i32, t_fine: i32) -> i64 {
+        let [p1, p2, p3, p4, p5, p6, p7, p8, p9] = self.p;
+        let mut var1 = i64::from(t_fine) - 128_000;
+        let mut var2 = var1 * var1 * p6;
+        var2 += (var1 * p5) << 17;
+        var2 += p4 << 35;
+        var1 = ((var1 * var1 * p3) >> 8) + ((var1 * p2) << 12);
+        var1 = (((1_i64 << 47) + var1) * p1) >> 33;
+        if var1 == 0 {
+            return 0;
+        }
+        let mut pressure = 1_048_576 - i64::from(adc_p);
+        pressure = (((pressure << 31) - var2) * 3125) / var1;
+        var1 = (p9 * (pressure >> 13) * (pressure >> 13)) >> 25;
+        var2 = (p8 * pressure) >> 19;
+        ((pressure + var1 + var2) >> 8) + (p7 << 4)
+    }
+
+    /// Relative humidity in % as Q22.10
+    fn humidity(&self, adc_h: i32, t_fine: i32) -> i32 {
+        let v = t_fine - 76_800;
+        let v = ((((adc_h << 14) - (self.h4 << 20) - (self.h5 * v)) + 16_384) >> 15)
+            * (((((((v * self.h6) >> 10) * (((v * self.h3) >> 11) + 32_768)) >> 10) + 2_097_152)
+                * self.h2
+                + 8192)
+                >> 14);
+        let v = v - (((((v >> 15) * (v >> 15)) >> 7) * self.h1) >> 4);
+        v.clamp(0, 419_430_400) >> 12
+    }
+}
@@ -1,210 +1,348 @@
-use dht_sensor::dht11::Reading;
-use embassy_time::{Delay, Duration, Timer};
+use alloc::boxed::Box;
+
+use embassy_futures::join::join;
+use embassy_time::Timer;
 use heapless::Vec;
 use log::{error, info, warn};
 
 use crate::{
-    config::{DHT11_MAX_ATTEMPTS, DHT11_WARMUP_DELAY_MS, SENSOR_SAMPLE_COUNT},
-    domain::{MoistureLevel, Sensor, SensorData, overflow_detected},
+    MOISTURE_CALIBRATION, MOISTURE_LEVEL, OVERFLOW_DETECTED,
+    climate::Climate,
+    config::SENSOR_SAMPLE_COUNT,
+    domain::{
+        Fault, MoistureLevel, Quantity, Sensor, SensorData, Tenths, classify_overflow, diagnose,
+        overflow_detected,
+    },
+    filter::{self, Filter},
+    light,
 };
 
-use super::adc::{calculate_average, read_battery_voltage, read_powered_adc_sensor};
-use super::hardware::SensorHardware;
-
-/// Read the DHT11, retrying on the frequent timing-induced checksum failures.
-///
-/// The DHT11 read is a blocking, bit-banged transfer whose correctness depends
-/// on microsecond edge timing. It is read *before* the WiFi radio starts so its
-/// timing is not corrupted by radio interrupts; retries here absorb the
-/// remaining transient glitches. Each attempt is preceded by the warmup/settle
-/// delay (the sensor cannot be sampled faster than ~1 Hz).
-pub(super) async fn read_dht11_with_retries(
-    dht11_pin: &mut esp_hal::gpio::Flex<'static>,
-) -> Option<Reading> {
-    for attempt in 1..=DHT11_MAX_ATTEMPTS {
-        Timer::after(Duration::from_millis(DHT11_WARMUP_DELAY_MS)).await;
-        match dht_sensor::dht11::blocking::read(&mut Delay, dht11_pin) {
+use super::driver::{Reading, Sampling, SensorDriver};
+
+/// Which readout of the wake this is. Only the first moves the state kept
+/// across wakes; maintenance refreshes are shown against it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum ReadoutKind {
+    Wake,
+    Refresh,
+}
+
+/// Samples of every quantity taken during one readout
+#[derive(Default)]
+pub(super) struct Samples {
+    values: [Vec<i32, SENSOR_SAMPLE_COUNT>; Quantity::COUNT],
+    /// A read of the quantity failed at least once
+    failed: [bool; Quantity::COUNT],
+}
+
+impl Samples {
+    /// Record one read of a driver measuring `quantities`. A failed read, or
+    /// a partial one (a DS18B20 probe that didn't answer), marks the missing
+    /// quantities as failed; an empty reading (the battery on USB power) is
+    /// no failure.
+    fn record(&mut self, quantities: &[Quantity], reading: Option<&Reading>) {
+        let Some(reading) = reading else {
+            for quantity in quantities {
+                self.failed[quantity.index()] = true;
+            }
+            return;
+        };
+        if reading.values().next().is_some() {
+            for &quantity in quantities {
+                if reading.get(quantity).is_none() {
+                    self.failed[quantity.index()] = true;
+                }
+            }
+        }
+        for (quantity, value) in reading.values() {
+            if self.values[quantity.index()].push(value).is_err() {
+                error!("Failed to push {} sample", quantity.name());
+            }
+        }
+    }
+
+    /// Fill every sample slot from one read, which keeps the averaging
+    /// intact for sensors read only once per wake.
+    fn fill(&mut self, quantities: &[Quantity], reading: Option<&Reading>) {
+        for _ in 0..SENSOR_SAMPLE_COUNT {
+            self.record(quantities, reading);
+        }
+    }
+}
+
+/// Power the sensor, let it settle and read it, retrying as often as the
+/// driver allows.
+async fn read_with_retries(driver: &mut dyn SensorDriver) -> Option<Reading> {
+    let attempts = driver.attempts();
+    for attempt in 1..=attempts {
+        driver.set_power(true);
+        Timer::after(driver.warmup()).await;
+        let result = driver.read().await;
+        driver.set_power(false);
+        match result {
             Ok(reading) => return Some(reading),
             Err(error) => warn!(
-                "DHT11 read attempt {}/{} failed: {:?}",
-                attempt, DHT11_MAX_ATTEMPTS, error
+                "{} read attempt {}/{} failed: {}",
+                driver.name(),
+                attempt,
+                attempts,
+                error
             ),
         }
     }
-    error!("DHT11 read failed after {} attempts", DHT11_MAX_ATTEMPTS);
+    error!("{} read failed after {} attempts", driver.name(), attempts);
     None
 }
 
-/// Collect the ADC sensors (moisture, water level, battery) and assemble the
-/// averaged SensorData, folding in the already-taken DHT11 reading.
-pub(super) async fn collect_adc_sensor_data(
-    hardware: &mut SensorHardware<'static>,
-    dht11_reading: Option<Reading>,
-) -> SensorData {
-    let mut air_humidity_samples: Vec<u8, SENSOR_SAMPLE_COUNT> = Vec::new();
-    let mut air_temperature_samples: Vec<i8, SENSOR_SAMPLE_COUNT> = Vec::new();
-    let mut soil_moisture_samples: Vec<u16, SENSOR_SAMPLE_COUNT> = Vec::new();
-    let mut battery_voltage_samples: Vec<u16, SENSOR_SAMPLE_COUNT> = Vec::new();
-    let mut water_level_samples: Vec<u16, SENSOR_SAMPLE_COUNT> = Vec::new();
-
-    // DHT11 was read once (pre-radio) for this cycle. Filling all sample slots
-    // from that one reading keeps the averaging logic intact.
-    if let Some(measurement) = dht11_reading {
-        for _ in 0..SENSOR_SAMPLE_COUNT {
-            let _ = air_temperature_samples.push(measurement.temperature);
-            let _ = air_humidity_samples.push(measurement.relative_humidity);
+/// Read the drivers that must run before the radio starts.
+pub(super) async fn read_before_radio(drivers: &mut [Box<dyn SensorDriver>]) -> Samples {
+    let mut samples = Samples::default();
+    for driver in drivers.iter_mut() {
+        if driver.sampling() == Sampling::BeforeRadio {
+            let reading = read_with_retries(driver.as_mut()).await;
+            samples.fill(driver.quantities(), reading.as_ref());
         }
     }
+    samples
+}
 
-    for i in 0..SENSOR_SAMPLE_COUNT {
-        info!(
-            "Reading ADC sensor data {}/{}",
-            (i + 1),
-            SENSOR_SAMPLE_COUNT
-        );
-
-        // Read soil moisture (powered ADC sensor)
-        if let Some(moisture) = read_powered_adc_sensor(
-            &mut hardware.adc1,
-            &mut hardware.moisture_pin,
-            &mut hardware.moisture_power_pin,
-        )
-        .await
-            && soil_moisture_samples.push(moisture).is_err()
-        {
-            error!("Failed to push SoilMoisture to sensor_data");
-        }
-
-        // Read water level (powered ADC sensor)
-        if let Some(water_level) = read_powered_adc_sensor(
-            &mut hardware.adc1,
-            &mut hardware.waterlevel_pin,
-            &mut hardware.water_level_power_pin,
-        )
-        .await
-            && water_level_samples.push(water_level).is_err()
-        {
-            error!("Failed to push WaterLevel to sensor_data");
-        }
-
-        // Read battery voltage
-        if let Some(battery_voltage) =
-            read_battery_voltage(&mut hardware.adc1, &mut hardware.battery_pin).await
-            && battery_voltage_samples.push(battery_voltage).is_err()
-        {
-            error!("Failed to push BatteryVoltage to sensor_data");
+/// Start the measurements of the drivers read once alongside the radio.
+pub(super) fn start_once(drivers: &mut [Box<dyn SensorDriver>]) {
+    for driver in drivers.iter_mut() {
+        if driver.sampling() == Sampling::Once {
+            driver.start();
         }
     }
+}
 
-    build_sensor_data(
-        air_humidity_samples,
-        air_temperature_samples,
-        soil_moisture_samples,
-        battery_voltage_samples,
-        water_level_samples,
-    )
+/// One read of the first driver that measures `quantity`
+pub(super) async fn read_quantity(
+    drivers: &mut [Box<dyn SensorDriver>],
+    quantity: Quantity,
+) -> Option<i32> {
+    let driver = drivers
+        .iter_mut()
+        .find(|driver| driver.quantities().contains(&quantity))?;
+    read_with_retries(driver.as_mut()).await?.get(quantity)
 }
 
-/// Average the raw sample vecs and assemble the final SensorData.
-fn build_sensor_data(
-    mut air_humidity_samples: Vec<u8, SENSOR_SAMPLE_COUNT>,
-    mut air_temperature_samples: Vec<i8, SENSOR_SAMPLE_COUNT>,
-    mut soil_moisture_samples: Vec<u16, SENSOR_SAMPLE_COUNT>,
-    mut battery_voltage_samples: Vec<u16, SENSOR_SAMPLE_COUNT>,
-    mut water_level_samples: Vec<u16, SENSOR_SAMPLE_COUNT>,
+/// Sample the repeated drivers while the once-read drivers finish their
+/// measurement, and assemble the averaged SensorData, folding in the samples
+/// taken before the radio started.
+pub(super) async fn collect_sensor_data(
+    drivers: &mut [Box<dyn SensorDriver>],
+    mut samples: Samples,
+    kind: ReadoutKind,
 ) -> SensorData {
-    let mut sensor_data = SensorData::default();
+    let (once, mut repeated): (alloc::vec::Vec<_>, alloc::vec::Vec<_>) = drivers
+        .iter_mut()
+        .filter(|driver| driver.sampling() != Sampling::BeforeRadio)
+        .map(|driver| &mut **driver)
+        .partition(|driver| driver.sampling() == Sampling::Once);
 
-    // Process air humidity
-    if let Some(avg_air_humidity) = calculate_average(&mut air_humidity_samples) {
-        info!("Air humidity: {}%", avg_air_humidity);
-        if sensor_data
-            .data
-            .push(Sensor::AirHumidity(avg_air_humidity))
-            .is_err()
-        {
-            error!("Failed to push AirHumidity to sensor_data");
-        }
-    } else {
-        error!(
-            "Unable to generate average value of air humidity - we had {} samples",
-            air_humidity_samples.len()
-        );
+    let read_once = async {
+        let mut readings = alloc::vec::Vec::new();
+        for driver in once {
+            let reading = read_with_retries(&mut *driver).await;
+            readings.push((driver.quantities(), reading));
+        }
+        readings
+    };
+    let read_repeated = async {
+        for i in 0..SENSOR_SAMPLE_COUNT {
+            info!("Reading sensor data {}/{}", (i + 1), SENSOR_SAMPLE_COUNT);
+            for driver in repeated.iter_mut() {
+                let reading = read_with_retries(&mut **driver).await;
+                samples.record(driver.quantities(), reading.as_ref());
+            }
+        }
+    };
+    let (readings, ()) = join(read_once, read_repeated).await;
+    for (quantities, reading) in &readings {
+        samples.fill(quantities, reading.as_ref());
     }
 
-    // Process air temperature
-    if let Some(avg_air_temperature) = calculate_average(&mut air_temperature_samples) {
-        info!("Air temperature: {}°C", avg_air_temperature);
-        if sensor_data
-            .data
-            .push(Sensor::AirTemperature(avg_air_temperature))
-            .is_err()
-        {
-            error!("Failed to push AirTemperature to sensor_data");
-        }
-    } else {
-        error!(
-            "Unable to generate average value of air temperature, we had {} samples",
-            air_temperature_samples.len()
-        );
+    build_sensor_data(samples, kind)
+}
+
+/// Filter the samples of each quantity and assemble the final SensorData.
+fn build_sensor_data(mut samples: Samples, kind: ReadoutKind) -> SensorData {
+    let mut sensor_data = SensorData::default();
+
+    for quantity in Quantity::iter() {
+        let failed = samples.failed[quantity.index()];
+        let samples = &mut samples.values[quantity.index()];
+        // Nothing to report, such as the battery on USB power
+        if samples.is_empty() && !failed {
+            continue;
+        }
+        let value = Filter::of(quantity).apply(samples);
+        let fault = diagnose(quantity, samples, value);
+        push_health(&mut sensor_data, quantity, fault);
+        let Some(value) = value else {
+            error!(
+                "Unable to generate a value of {} - we had {} samples",
+                quantity.name(),
+                samples.len()
+            );
+            continue;
+        };
+        // A faulted value would linger in the average for several wakes, and
+        // a moisture calibration needs the extremes of single wakes
+        let calibrating =
+            quantity == Quantity::SoilMoisture && MOISTURE_CALIBRATION.get().is_some();
+        let value = if fault.is_some() || calibrating {
+            value
+        } else if kind == ReadoutKind::Wake {
+            filter::smooth(quantity, value)
+        } else {
+            filter::preview(quantity, value)
+        };
+        push_derived_sensors(&mut sensor_data, quantity, value, fault, kind);
     }
+    push_climate_sensors(&mut sensor_data);
 
-    // Process overflow sensor
-    if let Some(avg_water_level) = calculate_average(&mut water_level_samples) {
-        let detected = overflow_detected(avg_water_level);
-        info!(
-            "Overflow raw ADC: {}mV → {}",
-            avg_water_level,
-            if detected {
-                "Water in overflow"
-            } else {
-                "No water in overflow"
+    sensor_data
+}
+
+/// Add the published values derived from the filtered value of one quantity.
+/// A faulted value is classified without hysteresis and doesn't update the
+/// stored moisture level or overflow state, nor the daily light integral.
+fn push_derived_sensors(
+    sensor_data: &mut SensorData,
+    quantity: Quantity,
+    average: i32,
+    fault: Option<Fault>,
+    kind: ReadoutKind,
+) {
+    match quantity {
+        Quantity::AirHumidity => {
+            let humidity = Tenths(average as i16);
+            info!("Air humidity: {}%", humidity);
+            push_sensor(sensor_data, Sensor::AirHumidity(humidity));
+        }
+        Quantity::AirTemperature => {
+            let temperature = Tenths(average as i16);
+            info!("Air temperature: {}°C", temperature);
+            push_sensor(sensor_data, Sensor::AirTemperature(temperature));
+        }
+        Quantity::AirPressure => {
+            info!("Air pressure: {}hPa", average);
+            push_sensor(sensor_data, Sensor::AirPressure(average as u16));
+        }
+        Quantity::WaterLevel => {
+            let detected = match fault {
+                Some(_) => overflow_detected(average as u16),
+                None => {
+                    let detected = classify_overflow(average as u16, OVERFLOW_DETECTED.get());
+                    if kind == ReadoutKind::Wake {
+                        OVERFLOW_DETECTED.set(detected);
+                    }
+                    detected
+                }
+            };
+            info!(
+                "Overflow raw ADC: {}mV → {}",
+                average,
+                if detected {
+                    "Water in overflow"
+                } else {
+                    "No water in overflow"
+                }
+            );
+            push_sensor(sensor_data, Sensor::OverflowDetected(detected));
+        }
+        Quantity::SoilMoisture => {
+            let raw = average as u16;
+            let moisture_level = match fault {
+                Some(_) => MoistureLevel::from(raw),
+                None => {
+                    let level = MoistureLevel::classify(raw, MOISTURE_LEVEL.get());
+                    if kind == ReadoutKind::Wake {
+                        MOISTURE_LEVEL.set(Some(level));
+                    }
+                    level
+                }
+            };
+            info!("Raw Moisture: {} ({})", raw, moisture_level);
+            // Calibration needs the unclamped value, so capture it before the
+            // reading is folded into SoilMoistureRaw.
+            if let Some(calibration) = MOISTURE_CALIBRATION.get() {
+                let calibration = calibration.record(raw);
+                info!(
+                    "Moisture calibration: {}..{} after {} readings",
+                    calibration.min, calibration.max, calibration.samples
+                );
+                MOISTURE_CALIBRATION.set(Some(calibration));
             }
-        );
-        if sensor_data
-            .data
-            .push(Sensor::OverflowDetected(detected))
-            .is_err()
-        {
-            error!("Failed to push OverflowDetected to sensor_data");
-        }
-    } else {
-        error!("Unable to generate average value of overflow sensor");
+            push_sensor(sensor_data, Sensor::SoilMoistureRaw(raw.into()));
+            push_sensor(sensor_data, Sensor::SoilMoisture(moisture_level));
+        }
+        Quantity::BatteryVoltage => {
+            info!("Battery voltage: {}mV", average);
+            push_sensor(sensor_data, Sensor::BatteryVoltage(average as u16));
+        }
+        Quantity::Illuminance => {
+            let lux = average.max(0) as u32;
+            info!("Illuminance: {}lx", lux);
+            push_sensor(sensor_data, Sensor::Illuminance(lux));
+            let integral = match (fault, kind) {
+                (Some(_), _) => light::today(),
+                (None, ReadoutKind::Wake) => light::record(lux),
+                (None, ReadoutKind::Refresh) => light::preview(lux),
+            };
+            info!("Daily light integral: {}mol/m²", integral);
+            push_sensor(sensor_data, Sensor::DailyLightIntegral(integral));
+        }
+        Quantity::Co2 => {
+            info!("CO2: {}ppm", average);
+            push_sensor(sensor_data, Sensor::Co2(average.max(0) as u16));
+        }
+        Quantity::SoilTemperature(probe) => {
+            let temperature = Tenths(average as i16);
+            info!("Soil temperature {}: {}°C", probe, temperature);
+            push_sensor(sensor_data, Sensor::SoilTemperature(probe, temperature));
+        }
     }
+}
 
-    // Process soil moisture
-    if let Some(avg_soil_moisture) = calculate_average(&mut soil_moisture_samples) {
-        let moisture_level = MoistureLevel::from(avg_soil_moisture);
-        info!("Raw Moisture: {} ({})", avg_soil_moisture, moisture_level);
-        if sensor_data
-            .data
-            .push(Sensor::SoilMoistureRaw(avg_soil_moisture.into()))
-            .is_err()
-        {
-            error!("Failed to push SoilMoistureRaw to sensor_data");
-        }
-        if sensor_data
-            .data
-            .push(Sensor::SoilMoisture(moisture_level))
-            .is_err()
-        {
-            error!("Failed to push SoilMoisture to sensor_data");
-        }
-    } else {
-        error!("Unable to generate average value of soil moisture");
+/// Add the metrics derived from the air temperature and humidity together.
+fn push_climate_sensors(sensor_data: &mut SensorData) {
+    let (mut temperature, mut humidity) = (None, None);
+    for sensor in &sensor_data.data {
+        match sensor {
+            Sensor::AirTemperature(value) => temperature = Some(*value),
+            Sensor::AirHumidity(value) => humidity = Some(*value),
+            _ => {}
+        }
+    }
+    let (Some(temperature), Some(humidity)) = (temperature, humidity) else {
+        return;
+    };
+    let climate = Climate::new(temperature, humidity);
+    if let Some(dew_point) = climate.dew_point() {
+        info!("Dew point: {}°C", dew_point);
+        push_sensor(sensor_data, Sensor::DewPoint(dew_point));
     }
+    let absolute_humidity = climate.absolute_humidity();
+    info!("Absolute humidity: {}g/m³", absolute_humidity);
+    push_sensor(sensor_data, Sensor::AbsoluteHumidity(absolute_humidity));
+    let deficit = climate.vapour_pressure_deficit();
+    info!("Vapour pressure deficit: {}kPa", deficit);
+    push_sensor(sensor_data, Sensor::VapourPressureDeficit(deficit));
+}
 
-    // Process battery voltage
-    if let Some(avg_battery_voltage) = calculate_average(&mut battery_voltage_samples) {
-        info!("Battery voltage: {}mV", avg_battery_voltage);
-        if sensor_data
-            .data
-            .push(Sensor::BatteryVoltage(avg_battery_voltage))
-            .is_err()
-        {
-            error!("Failed to push BatteryVoltage to sensor_data");
-        }
+fn push_health(sensor_data: &mut SensorData, quantity: Quantity, fault: Option<Fault>) {
+    if let Some(fault) = fault {
+        warn!("{} fault: {}", quantity.label(), fault);
+    }
+    if sensor_data.health.push((quantity, fault)).is_err() {
+        error!("Failed to push {} health to sensor_data", quantity.name());
     }
+}
 
-    sensor_data
+fn push_sensor(sensor_data: &mut SensorData, sensor: Sensor) {
+    if let Err(sensor) = sensor_data.data.push(sensor) {
+        error!("Failed to push {} to sensor_data", sensor.info().name);
+    }
 }
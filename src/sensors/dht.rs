@@ -0,0 +1,126 @@
+use alloc::boxed::Box;
+use core::future::ready;
+
+use dht_sensor::DhtError;
+use embassy_time::{Delay, Duration};
+use esp_hal::{
+    gpio::{DriveMode, Flex, Level, Output, OutputConfig, Pull},
+    peripherals::GPIO1,
+};
+
+#[cfg(not(feature = "dht22"))]
+use crate::config::DHT11_WARMUP_DELAY_MS;
+#[cfg(feature = "dht22")]
+use crate::config::DHT22_WARMUP_DELAY_MS;
+use crate::{config::DHT_MAX_ATTEMPTS, domain::Quantity};
+
+use super::driver::{ReadFuture, Reading, Sampling, SensorDriver, SensorError};
+
+/// DHT11 air temperature and humidity sensor, or the DHT22/AM2302 with the
+/// `dht22` feature.
+///
+/// The read is a blocking, bit-banged transfer whose correctness depends on
+/// microsecond edge timing, so it is read *before* the WiFi radio starts;
+/// retries absorb the remaining checksum glitches. The DHT11 cannot be
+/// sampled faster than ~1 Hz and the DHT22 ~0.5 Hz, hence the warmup before
+/// every attempt.
+pub(super) struct Dht {
+    pin: Flex<'static>,
+}
+
+impl Dht {
+    pub(super) fn new(pin: GPIO1<'static>) -> Self {
+        // Open-drain, no pull, input enabled
+        let mut pin = Output::new(
+            pin,
+            Level::High,
+            OutputConfig::default()
+                .with_drive_mode(DriveMode::OpenDrain)
+                .with_pull(Pull::None),
+        )
+        .into_flex();
+        pin.set_input_enable(true);
+        Self { pin }
+    }
+
+    /// Whole degrees and percent
+    #[cfg(not(feature = "dht22"))]
+    fn measure(&mut self) -> Result<Reading, SensorError> {
+        let reading = dht_sensor::dht11::blocking::read(&mut Delay, &mut self.pin)?;
+        Ok(Reading::default()
+            .with(
+                Quantity::AirTemperature,
+                i32::from(reading.temperature) * 10,
+            )
+            .with(
+                Quantity::AirHumidity,
+                i32::from(reading.relative_humidity) * 10,
+            ))
+    }
+
+    /// The DHT22 sends tenths, which the crate hands out divided by ten
+    #[cfg(feature = "dht22")]
+    fn measure(&mut self) -> Result<Reading, SensorError> {
+        let reading = dht_sensor::dht22::blocking::read(&mut Delay, &mut self.pin)?;
+        Ok(Reading::default()
+            .with(Quantity::AirTemperature, tenths(reading.temperature))
+            .with(Quantity::AirHumidity, tenths(reading.relative_humidity)))
+    }
+}
+
+impl SensorDriver for Dht {
+    fn name(&self) -> &'static str {
+        if cfg!(feature = "dht22") {
+            "DHT22"
+        } else {
+            "DHT11"
+        }
+    }
+
+    fn quantities(&self) -> &'static [Quantity] {
+        &[Quantity::AirTemperature, Quantity::AirHumidity]
+    }
+
+    fn sampling(&self) -> Sampling {
+        Sampling::BeforeRadio
+    }
+
+    #[cfg(not(feature = "dht22"))]
+    fn warmup(&self) -> Duration {
+        Duration::from_millis(DHT11_WARMUP_DELAY_MS)
+    }
+
+    #[cfg(feature = "dht22")]
+    fn warmup(&self) -> Duration {
+        Duration::from_millis(DHT22_WARMUP_DELAY_MS)
+    }
+
+    fn attempts(&self) -> usize {
+        DHT_MAX_ATTEMPTS
+    }
+
+    fn read(&mut self) -> ReadFuture<'_> {
+        Box::pin(ready(self.measure()))
+    }
+}
+
+/// Round to the nearest tenth, half away from zero
+#[cfg(feature = "dht22")]
+fn tenths(value: f32) -> i32 {
+    let scaled = value * 10.0;
+    (if scaled < 0.0 {
+        scaled - 0.5
+    } else {
+        scaled + 0.5
+    }) as i32
+}
+
+impl<E> From<DhtError<E>> for SensorError {
+    fn from(error: DhtError<E>) -> Self {
+        match error {
+            DhtError::PinError(_) => Self::Bus,
+            DhtError::ChecksumMismatch => Self::Checksum,
+            DhtError::Timeout => Self::Timeout,
+        }
+    }
+}
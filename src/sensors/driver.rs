@@ -0,0 +1,115 @@
+//! Sensor driver interface
+//!
+//! Every sensor is a [`SensorDriver`]: it names the quantities it measures,
+//! how long it needs powered before a read and how it is sampled. The readout
+//! powers, reads, retries and averages all drivers the same way, so a new
+//! sensor needs a driver and an entry in the driver list, not changes to the
+//! readout.
+
+use alloc::boxed::Box;
+use core::{future::Future, pin::Pin};
+
+use embassy_time::Duration;
+use heapless::Vec;
+use log::error;
+
+use crate::domain::Quantity;
+
+/// Most quantities one driver reports per read
+const MAX_READING_VALUES: usize = 4;
+
+/// Future returned by [`SensorDriver::read`]. Boxed so drivers can be kept
+/// as trait objects.
+pub type ReadFuture<'a> = Pin<Box<dyn Future<Output = Result<Reading, SensorError>> + 'a>>;
+
+/// All fitted drivers, in read order
+pub type Drivers = alloc::vec::Vec<Box<dyn SensorDriver>>;
+
+/// Values of one read, one per quantity. A driver that has nothing to report
+/// (the battery on USB power) returns an empty reading.
+#[derive(Debug, Default)]
+pub struct Reading(Vec<(Quantity, i32), MAX_READING_VALUES>);
+
+impl Reading {
+    pub fn with(mut self, quantity: Quantity, value: i32) -> Self {
+        if self.0.push((quantity, value)).is_err() {
+            error!("Failed to push {} to reading", quantity.name());
+        }
+        self
+    }
+
+    pub fn get(&self, quantity: Quantity) -> Option<i32> {
+        self.values()
+            .find(|(q, _)| *q == quantity)
+            .map(|(_, value)| value)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = (Quantity, i32)> + '_ {
+        self.0.iter().copied()
+    }
+}
+
+/// When a driver is read during the wake cycle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sampling {
+    /// Once before the radio starts, the value fills every sample slot.
+    /// For bit-banged sensors whose timing radio interrupts corrupt.
+    BeforeRadio,
+    /// `SENSOR_SAMPLE_COUNT` times, alongside the WiFi connection
+    Repeated,
+    /// Once alongside the WiFi connection, next to the repeated reads; the
+    /// value fills every sample slot. For sensors that take seconds to
+    /// measure, started before the radio with [`SensorDriver::start`].
+    Once,
+}
+
+pub trait SensorDriver {
+    /// Name for the logs
+    fn name(&self) -> &'static str;
+
+    /// What the driver measures; decides the HA entities announced
+    fn quantities(&self) -> &'static [Quantity];
+
+    fn sampling(&self) -> Sampling {
+        Sampling::Repeated
+    }
+
+    /// Settle time after power on, before each read attempt
+    fn warmup(&self) -> Duration;
+
+    /// Read attempts before the sensor counts as failed for this sample
+    fn attempts(&self) -> usize {
+        1
+    }
+
+    /// Switch the sensor supply. Sensors without a power pin ignore it.
+    fn set_power(&mut self, _on: bool) {}
+
+    /// Start a measurement for the next read to collect, so it runs while
+    /// the rest of the wake goes on. Called before the radio for
+    /// [`Sampling::Once`] drivers.
+    fn start(&mut self) {}
+
+    fn read(&mut self) -> ReadFuture<'_>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SensorError {
+    NotFound,
+    Timeout,
+    Checksum,
+    Bus,
+    Adc,
+}
+
+impl core::fmt::Display for SensorError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SensorError::NotFound => write!(f, "No sensor at the address"),
+            SensorError::Timeout => write!(f, "Sensor did not respond"),
+            SensorError::Checksum => write!(f, "Checksum mismatch"),
+            SensorError::Bus => write!(f, "Bus error"),
+            SensorError::Adc => write!(f, "ADC read failed"),
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use alloc::boxed::Box;
+
+use embassy_time::{Duration, Timer};
+use heapless::Vec;
+use log::{info, warn};
+
+use crate::{
+    config::{DS18B20_MAX_ATTEMPTS, SOIL_PROBE_ROMS},
+    domain::{Probe, Quantity},
+};
+
+use super::{
+    driver::{ReadFuture, Reading, Sampling, SensorDriver, SensorError},
+    onewire::{OneWire, Rom, crc8},
+};
+
+const FAMILY_CODE: u8 = 0x28;
+const CONVERT_T: u8 = 0x44;
+const READ_SCRATCHPAD: u8 = 0xBE;
+/// 750 ms at the default 12-bit resolution
+const CONVERSION_TIME: Duration = Duration::from_millis(750);
+/// Temperature register after power-up (85 °C), before any conversion ran
+const POWER_ON_RESET: i16 = 0x0550;
+
+/// One soil temperature quantity per probe slot
+static QUANTITIES: [Quantity; Probe::COUNT] = [
+    Quantity::SoilTemperature(Probe(0)),
+    Quantity::SoilTemperature(Probe(1)),
+    Quantity::SoilTemperature(Probe(2)),
+    Quantity::SoilTemperature(Probe(3)),
+];
+
+/// Maxim DS18B20 soil temperature probes sharing one 1-Wire bus. All probes
+/// convert at once, then each is read by its ROM code into its own slot.
+pub(super) struct Ds18b20 {
+    bus: OneWire,
+    roms: Vec<Rom, { Probe::COUNT }>,
+}
+
+impl Ds18b20 {
+    /// Use the probes in `SOIL_PROBE_ROMS`, or every DS18B20 on the bus in
+    /// ROM order when none is configured.
+    pub(super) fn probe(mut bus: OneWire) -> Result<Self, SensorError> {
+        let candidates: alloc::vec::Vec<Rom> = if SOIL_PROBE_ROMS.is_empty() {
+            bus.search()?
+                .into_iter()
+                .filter(|rom| rom.family() == FAMILY_CODE)
+                .collect()
+        } else {
+            SOIL_PROBE_ROMS.iter().copied().map(Rom).collect()
+        };
+        let mut roms = Vec::new();
+        for rom in candidates {
+            if roms.push(rom).is_err() {
+                warn!(
+                    "More than {} soil temperature probes, ignoring {}",
+                    Probe::COUNT,
+                    rom
+                );
+            }
+        }
+        if roms.is_empty() {
+            return Err(SensorError::NotFound);
+        }
+        for (slot, rom) in roms.iter().enumerate() {
+            info!("Soil temperature probe {}: {}", slot + 1, rom);
+        }
+        Ok(Self { bus, roms })
+    }
+
+    async fn measure(&mut self) -> Result<Reading, SensorError> {
+        self.bus.select(None)?;
+        self.bus.write_byte(CONVERT_T);
+        Timer::after(CONVERSION_TIME).await;
+
+        let mut reading = Reading::default();
+        let mut failure = None;
+        for (slot, rom) in self.roms.clone().into_iter().enumerate() {
+            let probe = Probe(slot as u8);
+            match self.temperature(rom) {
+                Ok(temperature) => {
+                    reading = reading.with(Quantity::SoilTemperature(probe), temperature)
+                }
+                Err(error) => {
+                    warn!("Soil temperature probe {} ({}): {}", probe, rom, error);
+                    failure = Some(error);
+                }
+            }
+        }
+        // Retry only when no probe answered; the others keep their reading
+        match failure {
+            Some(error) if reading.values().next().is_none() => Err(error),
+            _ => Ok(reading),
+        }
+    }
+
+    /// Temperature of one probe in tenths of a degree
+    fn temperature(&mut self, rom: Rom) -> Result<i32, SensorError> {
+        self.bus.select(Some(rom))?;
+        self.bus.write_byte(READ_SCRATCHPAD);
+        let mut scratchpad = [0; 9];
+        self.bus.read_bytes(&mut scratchpad);
+        // A bus held low reads all zeros, which passes the CRC
+        if scratchpad == [0; 9] {
+            return Err(SensorError::Bus);
+        }
+        if crc8(&scratchpad) != 0 {
+            return Err(SensorError::Checksum);
+        }
+        let raw = i16::from_le_bytes([scratchpad[0], scratchpad[1]]);
+        if raw == POWER_ON_RESET {
+            return Err(SensorError::Timeout);
+        }
+        // 1/16 °C to tenths, rounded
+        Ok((i32::from(raw) * 10 + 8).div_euclid(16))
+    }
+}
+
+impl SensorDriver for Ds18b20 {
+    fn name(&self) -> &'static str {
+        "DS18B20"
+    }
+
+    fn quantities(&self) -> &'static [Quantity] {
+        &QUANTITIES[..self.roms.len()]
+    }
+
+    fn sampling(&self) -> Sampling {
+        Sampling::BeforeRadio
+    }
+
+    fn warmup(&self) -> Duration {
+        Duration::from_millis(0)
+    }
+
+    fn attempts(&self) -> usize {
+        DS18B20_MAX_ATTEMPTS
+    }
+
+    fn read(&mut self) -> ReadFuture<'_> {
+        Box::pin(self.measure())
+    }
+}
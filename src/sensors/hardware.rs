@@ -1,34 +1,58 @@
+use alloc::boxed::Box;
+use core::cell::RefCell;
+
+#[cfg(feature = "ds18b20")]
+use esp_hal::peripherals::GPIO10;
+#[cfg(feature = "i2c")]
+use esp_hal::peripherals::{GPIO17, GPIO18, I2C0};
 use esp_hal::{
     Blocking,
-    analog::adc::{Adc, AdcCalCurve, AdcCalLine, AdcConfig, AdcPin, Attenuation},
-    gpio::{DriveMode, Level, Output, OutputConfig, Pull},
+    analog::adc::{Adc, AdcCalCurve, AdcConfig, Attenuation},
+    gpio::{Level, Output, OutputConfig},
     peripherals::{ADC1, GPIO1, GPIO2, GPIO3, GPIO4, GPIO16, GPIO21},
 };
+#[cfg(feature = "ds18b20")]
+use log::warn;
+use static_cell::StaticCell;
 
-/// ADC and GPIO handles — private to the sensors module.
-pub(super) struct SensorHardware<'a> {
-    pub(super) adc1: Adc<'a, ADC1<'a>, Blocking>,
-    pub(super) moisture_pin: AdcPin<GPIO2<'a>, ADC1<'a>, AdcCalCurve<ADC1<'a>>>,
-    pub(super) waterlevel_pin: AdcPin<GPIO3<'a>, ADC1<'a>, ()>,
-    pub(super) battery_pin: AdcPin<GPIO4<'a>, ADC1<'a>, AdcCalLine<ADC1<'a>>>,
-    pub(super) moisture_power_pin: Output<'a>,
-    pub(super) water_level_power_pin: Output<'a>,
-    pub(super) dht11_pin: esp_hal::gpio::Flex<'a>,
-}
+#[cfg(feature = "scd4x")]
+use crate::config::SCD4X_CLIMATE;
+use crate::domain::Quantity;
+
+#[cfg(feature = "i2c")]
+use super::i2c;
+use super::{
+    adc::{BatteryDivider, PoweredAdcSensor, SharedAdc},
+    dht::Dht,
+    driver::{Drivers, SensorDriver},
+};
+#[cfg(feature = "ds18b20")]
+use super::{ds18b20::Ds18b20, onewire::OneWire};
+
+static ADC: StaticCell<RefCell<Adc<'static, ADC1<'static>, Blocking>>> = StaticCell::new();
 
 /// Peripheral bundle passed from main.rs into the sensor task.
 pub struct SensorPeripherals {
-    pub dht11_digital_pin: GPIO1<'static>,
+    pub dht_digital_pin: GPIO1<'static>,
     pub battery_pin: GPIO4<'static>,
     pub moisture_power_pin: GPIO16<'static>,
     pub moisture_analog_pin: GPIO2<'static>,
     pub water_level_analog_pin: GPIO3<'static>,
     pub water_level_power_pin: GPIO21<'static>,
     pub adc1: ADC1<'static>,
+    #[cfg(feature = "i2c")]
+    pub i2c0: I2C0<'static>,
+    #[cfg(feature = "i2c")]
+    pub i2c_sda_pin: GPIO17<'static>,
+    #[cfg(feature = "i2c")]
+    pub i2c_scl_pin: GPIO18<'static>,
+    #[cfg(feature = "ds18b20")]
+    pub onewire_pin: GPIO10<'static>,
 }
 
-/// Initialize all sensor hardware from the peripheral bundle.
-pub(super) async fn initialize_hardware(p: SensorPeripherals) -> SensorHardware<'static> {
+/// Set up the sensors on the board and their drivers, in read order.
+#[cfg_attr(feature = "mock-sensors", allow(dead_code))]
+pub(super) async fn drivers(p: SensorPeripherals) -> Drivers {
     let mut adc2_config = AdcConfig::new();
     let moisture_pin = adc2_config
         .enable_pin_with_cal::<_, AdcCalCurve<ADC1>>(p.moisture_analog_pin, Attenuation::_11dB);
@@ -36,30 +60,61 @@ pub(super) async fn initialize_hardware(p: SensorPeripherals) -> SensorHardware<
 
     let mut adc1_config = AdcConfig::new();
     let battery_pin = adc1_config.enable_pin_with_cal(p.battery_pin, Attenuation::_11dB);
-    let adc1 = Adc::new(p.adc1, adc1_config);
+    let adc: SharedAdc = ADC.init(RefCell::new(Adc::new(p.adc1, adc1_config)));
 
     let moisture_power_pin = Output::new(p.moisture_power_pin, Level::Low, OutputConfig::default());
     let water_level_power_pin =
         Output::new(p.water_level_power_pin, Level::Low, OutputConfig::default());
 
-    // Setup DHT11 pin once — open-drain, no pull, input enabled
-    let mut dht11_pin = Output::new(
-        p.dht11_digital_pin,
-        Level::High,
-        OutputConfig::default()
-            .with_drive_mode(DriveMode::OpenDrain)
-            .with_pull(Pull::None),
-    )
-    .into_flex();
-    dht11_pin.set_input_enable(true);
-
-    SensorHardware {
-        adc1,
+    let mut drivers = Drivers::new();
+    #[cfg(feature = "i2c")]
+    let bus = i2c::bus(p.i2c0, p.i2c_sda_pin, p.i2c_scl_pin);
+    #[cfg(feature = "i2c")]
+    let climate = match bus {
+        Some(bus) => i2c::climate_sensor(bus).await,
+        None => None,
+    };
+    #[cfg(not(feature = "i2c"))]
+    let climate = None;
+    #[cfg(feature = "scd4x")]
+    let mut co2: Option<Box<dyn SensorDriver>> = match bus {
+        Some(bus) => i2c::co2_sensor(bus, climate.is_none() && SCD4X_CLIMATE).await,
+        None => None,
+    };
+    #[cfg(not(feature = "scd4x"))]
+    let mut co2: Option<Box<dyn SensorDriver>> = None;
+    // An SCD4x measuring the air takes the place of the DHT
+    let climate = climate
+        .or_else(|| co2.take_if(|driver| driver.quantities().contains(&Quantity::AirTemperature)));
+    drivers.push(climate.unwrap_or_else(|| Box::new(Dht::new(p.dht_digital_pin))));
+    if let Some(driver) = co2 {
+        drivers.push(driver);
+    }
+    #[cfg(feature = "ds18b20")]
+    match Ds18b20::probe(OneWire::new(p.onewire_pin)) {
+        Ok(driver) => drivers.push(Box::new(driver)),
+        Err(error) => warn!("No soil temperature probe found: {}", error),
+    }
+    #[cfg(feature = "i2c")]
+    if let Some(bus) = bus
+        && let Some(driver) = i2c::light_sensor(bus).await
+    {
+        drivers.push(driver);
+    }
+    drivers.push(Box::new(PoweredAdcSensor::new(
+        "Moisture probe",
+        &[Quantity::SoilMoisture],
+        adc,
         moisture_pin,
-        waterlevel_pin,
-        battery_pin,
         moisture_power_pin,
+    )));
+    drivers.push(Box::new(PoweredAdcSensor::new(
+        "Overflow probe",
+        &[Quantity::WaterLevel],
+        adc,
+        waterlevel_pin,
         water_level_power_pin,
-        dht11_pin,
-    }
+    )));
+    drivers.push(Box::new(BatteryDivider::new(adc, battery_pin)));
+    drivers
 }
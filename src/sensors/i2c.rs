@@ -0,0 +1,178 @@
+//! I2C bus for the climate and light sensors
+//!
+//! The bus runs on GPIO17 (SDA) and GPIO18 (SCL) at 100 kHz. The climate
+//! chips enabled by cargo feature (`bme280`, `sht3x`, `aht20`) are probed in
+//! that order at startup; the first one that answers measures the air, and
+//! the DHT11/DHT22 stays the fallback when none does. The light sensors
+//! (`bh1750`, `veml7700`) and the SCD4x CO2 sensor (`scd4x`) are probed the
+//! same way.
+
+use alloc::boxed::Box;
+use core::cell::RefCell;
+
+use esp_hal::{
+    Blocking,
+    i2c::master::{AcknowledgeCheckFailedReason, Config, Error, I2c},
+    peripherals::{GPIO17, GPIO18, I2C0},
+};
+use log::{error, info, warn};
+use static_cell::StaticCell;
+
+#[cfg(feature = "aht20")]
+use super::aht20::Aht20;
+#[cfg(feature = "bh1750")]
+use super::bh1750::Bh1750;
+#[cfg(feature = "bme280")]
+use super::bme280::Bme280;
+use super::driver::{SensorDriver, SensorError};
+#[cfg(feature = "scd4x")]
+use super::scd4x::Scd4x;
+#[cfg(feature = "sht3x")]
+use super::sht3x::Sht3x;
+#[cfg(feature = "veml7700")]
+use super::veml7700::Veml7700;
+
+/// I2C0, shared by all I2C drivers
+pub(super) type SharedI2c = &'static RefCell<I2c<'static, Blocking>>;
+
+static I2C: StaticCell<RefCell<I2c<'static, Blocking>>> = StaticCell::new();
+
+/// Set up the bus, or `None` if the peripheral rejects the configuration.
+pub(super) fn bus(
+    i2c0: I2C0<'static>,
+    sda: GPIO17<'static>,
+    scl: GPIO18<'static>,
+) -> Option<SharedI2c> {
+    match I2c::new(i2c0, Config::default()) {
+        Ok(i2c) => Some(I2C.init(RefCell::new(i2c.with_sda(sda).with_scl(scl)))),
+        Err(error) => {
+            error!("I2C setup failed: {:?}", error);
+            None
+        }
+    }
+}
+
+/// The first enabled climate sensor that answers on the bus
+pub(super) async fn climate_sensor(bus: SharedI2c) -> Option<Box<dyn SensorDriver>> {
+    #[cfg(feature = "bme280")]
+    match Bme280::probe(bus).await {
+        Ok(driver) => return Some(found("Climate", Box::new(driver))),
+        Err(error) => warn!("BME280 not found: {}", error),
+    }
+    #[cfg(feature = "sht3x")]
+    match Sht3x::probe(bus).await {
+        Ok(driver) => return Some(found("Climate", Box::new(driver))),
+        Err(error) => warn!("SHT3x not found: {}", error),
+    }
+    #[cfg(feature = "aht20")]
+    match Aht20::probe(bus).await {
+        Ok(driver) => return Some(found("Climate", Box::new(driver))),
+        Err(error) => warn!("AHT20 not found: {}", error),
+    }
+    // Without any chip enabled there is nothing to probe
+    let _ = bus;
+    None
+}
+
+/// The first enabled light sensor that answers on the bus
+pub(super) async fn light_sensor(bus: SharedI2c) -> Option<Box<dyn SensorDriver>> {
+    #[cfg(feature = "bh1750")]
+    match Bh1750::probe(bus).await {
+        Ok(driver) => return Some(found("Light", Box::new(driver))),
+        Err(error) => warn!("BH1750 not found: {}", error),
+    }
+    #[cfg(feature = "veml7700")]
+    match Veml7700::probe(bus).await {
+        Ok(driver) => return Some(found("Light", Box::new(driver))),
+        Err(error) => warn!("VEML7700 not found: {}", error),
+    }
+    let _ = bus;
+    None
+}
+
+/// The SCD4x CO2 sensor, also measuring the air when `climate` is set
+#[cfg(feature = "scd4x")]
+pub(super) async fn co2_sensor(bus: SharedI2c, climate: bool) -> Option<Box<dyn SensorDriver>> {
+    match Scd4x::probe(bus, climate).await {
+        Ok(driver) => Some(found("CO2", Box::new(driver))),
+        Err(error) => {
+            warn!("SCD4x not found: {}", error);
+            None
+        }
+    }
+}
+
+#[cfg(any(
+    feature = "bme280",
+    feature = "sht3x",
+    feature = "aht20",
+    feature = "bh1750",
+    feature = "veml7700",
+    feature = "scd4x"
+))]
+fn found(kind: &str, driver: Box<dyn SensorDriver>) -> Box<dyn SensorDriver> {
+    info!("{} sensor: {}", kind, driver.name());
+    driver
+}
+
+pub(super) fn write(bus: SharedI2c, address: u8, bytes: &[u8]) -> Result<(), SensorError> {
+    bus.borrow_mut()
+        .write(address, bytes)
+        .map_err(SensorError::from)
+}
+
+#[cfg(any(
+    feature = "sht3x",
+    feature = "aht20",
+    feature = "bh1750",
+    feature = "scd4x"
+))]
+pub(super) fn read(bus: SharedI2c, address: u8, buffer: &mut [u8]) -> Result<(), SensorError> {
+    bus.borrow_mut()
+        .read(address, buffer)
+        .map_err(SensorError::from)
+}
+
+pub(super) fn write_read(
+    bus: SharedI2c,
+    address: u8,
+    bytes: &[u8],
+    buffer: &mut [u8],
+) -> Result<(), SensorError> {
+    bus.borrow_mut()
+        .write_read(address, bytes, buffer)
+        .map_err(SensorError::from)
+}
+
+/// CRC-8 used by the Sensirion and Aosong chips (polynomial 0x31, init 0xFF)
+#[cfg(any(feature = "sht3x", feature = "aht20", feature = "scd4x"))]
+pub(super) fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0xFF, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x31
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// A 16-bit Sensirion word followed by its CRC
+#[cfg(any(feature = "sht3x", feature = "scd4x"))]
+pub(super) fn checked_word(bytes: &[u8]) -> Result<u16, SensorError> {
+    if crc8(&bytes[0..2]) != bytes[2] {
+        return Err(SensorError::Checksum);
+    }
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+impl From<Error> for SensorError {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::AcknowledgeCheckFailed(AcknowledgeCheckFailedReason::Address) => Self::NotFound,
+            Error::Timeout => Self::Timeout,
+            _ => Self::Bus,
+        }
+    }
+}
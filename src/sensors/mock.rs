@@ -0,0 +1,557 @@
+//! Mock sensor drivers
+//!
+//! Built with the `mock-sensors` feature in place of the board's drivers, so
+//! the wake cycle runs on a bare ESP32-S3 and the readout can be tested
+//! without sensors. Each mock replays a script of results, wrapping around
+//! at the end.
+
+use alloc::boxed::Box;
+use core::future::ready;
+
+use embassy_time::Duration;
+use log::info;
+
+use crate::{
+    config::{DHT_MAX_ATTEMPTS, SENSOR_WARMUP_DELAY_MS},
+    domain::Quantity,
+};
+
+#[cfg(feature = "ds18b20")]
+use crate::domain::Probe;
+
+use super::driver::{Drivers, ReadFuture, Reading, Sampling, SensorDriver, SensorError};
+
+/// One scripted read
+pub(super) enum Step {
+    /// One value per quantity of the mock, in the same order
+    Values(&'static [i32]),
+    Fail(SensorError),
+}
+
+pub(super) struct MockSensor {
+    name: &'static str,
+    quantities: &'static [Quantity],
+    script: &'static [Step],
+    next: usize,
+    sampling: Sampling,
+    attempts: usize,
+}
+
+impl MockSensor {
+    pub(super) fn new(
+        name: &'static str,
+        quantities: &'static [Quantity],
+        script: &'static [Step],
+    ) -> Self {
+        Self {
+            name,
+            quantities,
+            script,
+            next: 0,
+            sampling: Sampling::Repeated,
+            attempts: 1,
+        }
+    }
+
+    pub(super) fn before_radio(self) -> Self {
+        Self {
+            sampling: Sampling::BeforeRadio,
+            ..self
+        }
+    }
+
+    pub(super) fn once(self) -> Self {
+        Self {
+            sampling: Sampling::Once,
+            ..self
+        }
+    }
+
+    pub(super) fn with_attempts(self, attempts: usize) -> Self {
+        Self { attempts, ..self }
+    }
+}
+
+impl SensorDriver for MockSensor {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn quantities(&self) -> &'static [Quantity] {
+        self.quantities
+    }
+
+    fn sampling(&self) -> Sampling {
+        self.sampling
+    }
+
+    fn warmup(&self) -> Duration {
+        Duration::from_millis(SENSOR_WARMUP_DELAY_MS)
+    }
+
+    fn attempts(&self) -> usize {
+        self.attempts
+    }
+
+    fn read(&mut self) -> ReadFuture<'_> {
+        let step = &self.script[self.next % self.script.len()];
+        self.next += 1;
+        let result = match step {
+            Step::Values(values) => Ok(self
+                .quantities
+                .iter()
+                .zip(values.iter())
+                .fold(Reading::default(), |reading, (&quantity, &value)| {
+                    reading.with(quantity, value)
+                })),
+            Step::Fail(error) => Err(*error),
+        };
+        Box::pin(ready(result))
+    }
+}
+
+/// Stand-ins for the board's sensors: a DHT11 that needs a retry, moisture
+/// readings with an outlier, a dry overflow probe and a battery at ~3.9 V.
+/// With `ds18b20` also two soil temperature probes, with `bh1750` or
+/// `veml7700` a light sensor at ~12 klx, with `scd4x` a CO2 sensor at
+/// 812 ppm.
+#[allow(
+    clippy::vec_init_then_push,
+    reason = "which mocks are pushed depends on the sensor features"
+)]
+pub(super) fn drivers() -> Drivers {
+    info!("Using mock sensors");
+    let mut drivers = Drivers::new();
+    drivers.push(Box::new(
+        MockSensor::new(
+            "Mock DHT11",
+            &[Quantity::AirTemperature, Quantity::AirHumidity],
+            &[Step::Fail(SensorError::Checksum), Step::Values(&[215, 483])],
+        )
+        .before_radio()
+        .with_attempts(DHT_MAX_ATTEMPTS),
+    ));
+    #[cfg(feature = "ds18b20")]
+    drivers.push(Box::new(
+        MockSensor::new(
+            "Mock DS18B20",
+            &[
+                Quantity::SoilTemperature(Probe(0)),
+                Quantity::SoilTemperature(Probe(1)),
+            ],
+            &[Step::Values(&[183, 176])],
+        )
+        .before_radio(),
+    ));
+    drivers.push(Box::new(MockSensor::new(
+        "Mock moisture probe",
+        &[Quantity::SoilMoisture],
+        &[
+            Step::Values(&[1480]),
+            Step::Values(&[1510]),
+            Step::Values(&[2900]),
+            Step::Values(&[1495]),
+            Step::Values(&[1500]),
+        ],
+    )));
+    drivers.push(Box::new(MockSensor::new(
+        "Mock overflow probe",
+        &[Quantity::WaterLevel],
+        &[Step::Values(&[2210]), Step::Values(&[2205])],
+    )));
+    #[cfg(any(feature = "bh1750", feature = "veml7700"))]
+    drivers.push(Box::new(MockSensor::new(
+        "Mock light sensor",
+        &[Quantity::Illuminance],
+        &[
+            Step::Values(&[11980]),
+            Step::Values(&[12010]),
+            Step::Values(&[12000]),
+            Step::Values(&[12030]),
+            Step::Values(&[11990]),
+        ],
+    )));
+    #[cfg(feature = "scd4x")]
+    drivers.push(Box::new(
+        MockSensor::new("Mock SCD41", &[Quantity::Co2], &[Step::Values(&[812])]).once(),
+    ));
+    drivers.push(Box::new(MockSensor::new(
+        "Mock battery",
+        &[Quantity::BatteryVoltage],
+        &[Step::Values(&[3950]), Step::Values(&[3940])],
+    )));
+    drivers
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{
+        string::{String, ToString},
+        vec,
+    };
+
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::{
+        LIGHT_INTEGRAL, MOISTURE_CALIBRATION, MOISTURE_LEVEL, OVERFLOW_DETECTED,
+        SENSOR_FILTER_STATE,
+        domain::{Fault, MoistureCalibration, MoistureLevel, Probe, Sensor, SensorData, Tenths},
+        light::LightIntegral,
+        sensors::builder::{ReadoutKind, collect_sensor_data, read_before_radio},
+    };
+
+    /// Clear the state kept across wakes, as a power-on does
+    fn power_on() {
+        SENSOR_FILTER_STATE.set([None; Quantity::COUNT]);
+        MOISTURE_LEVEL.set(None);
+        OVERFLOW_DETECTED.set(false);
+        MOISTURE_CALIBRATION.set(None);
+        LIGHT_INTEGRAL.set(LightIntegral::EMPTY);
+    }
+
+    fn read(drivers: &mut Drivers, kind: ReadoutKind) -> SensorData {
+        block_on(async {
+            let samples = read_before_radio(drivers).await;
+            collect_sensor_data(drivers, samples, kind).await
+        })
+    }
+
+    fn probe(quantities: &'static [Quantity], script: &'static [Step]) -> Drivers {
+        vec![Box::new(MockSensor::new("Mock probe", quantities, script))]
+    }
+
+    fn raw_moisture(sensor_data: &SensorData) -> Option<String> {
+        sensor_data.data.iter().find_map(|sensor| match sensor {
+            Sensor::SoilMoistureRaw(raw) => Some(raw.to_string()),
+            _ => None,
+        })
+    }
+
+    fn moisture_level(sensor_data: &SensorData) -> Option<MoistureLevel> {
+        sensor_data.data.iter().find_map(|sensor| match sensor {
+            Sensor::SoilMoisture(level) => Some(*level),
+            _ => None,
+        })
+    }
+
+    fn overflow(sensor_data: &SensorData) -> Option<bool> {
+        sensor_data.data.iter().find_map(|sensor| match sensor {
+            Sensor::OverflowDetected(detected) => Some(*detected),
+            _ => None,
+        })
+    }
+
+    // Trimmed means of 1500, 1000 and 2100 mV
+    const MOIST: &[Step] = &[
+        Step::Values(&[1495]),
+        Step::Values(&[1500]),
+        Step::Values(&[1505]),
+        Step::Values(&[1500]),
+        Step::Values(&[1500]),
+    ];
+    const WET: &[Step] = &[
+        Step::Values(&[995]),
+        Step::Values(&[1000]),
+        Step::Values(&[1005]),
+        Step::Values(&[1000]),
+        Step::Values(&[1000]),
+    ];
+    const DRY: &[Step] = &[
+        Step::Values(&[2095]),
+        Step::Values(&[2100]),
+        Step::Values(&[2105]),
+        Step::Values(&[2100]),
+        Step::Values(&[2100]),
+    ];
+
+    #[test]
+    fn board_mocks_read_like_the_board() {
+        power_on();
+        let sensor_data = read(&mut drivers(), ReadoutKind::Wake);
+
+        let value =
+            |wanted: fn(&Sensor) -> Option<String>| sensor_data.data.iter().find_map(wanted);
+        // The DHT11 mock fails its first attempt; the retry fills every slot
+        let temperature = value(|s| matches!(s, Sensor::AirTemperature(_)).then(|| s.value()));
+        let humidity = value(|s| matches!(s, Sensor::AirHumidity(_)).then(|| s.value()));
+        let battery = value(|s| matches!(s, Sensor::BatteryVoltage(_)).then(|| s.value()));
+        assert_eq!(temperature.as_deref(), Some("21.5"));
+        assert_eq!(humidity.as_deref(), Some("48.3"));
+        // The 2900 mV outlier is trimmed; the first wake starts the EMA
+        assert_eq!(raw_moisture(&sensor_data).as_deref(), Some("1502"));
+        assert_eq!(moisture_level(&sensor_data), Some(MoistureLevel::Moist));
+        assert_eq!(overflow(&sensor_data), Some(false));
+        assert_eq!(battery.as_deref(), Some("3947"));
+        assert!(sensor_data.health.iter().all(|(_, fault)| fault.is_none()));
+        assert!(
+            sensor_data
+                .data
+                .iter()
+                .any(|s| matches!(s, Sensor::DewPoint(_)))
+        );
+    }
+
+    #[test]
+    fn failing_sensor_is_a_failed_read() {
+        power_on();
+        let mut drivers: Drivers = vec![Box::new(
+            MockSensor::new(
+                "Mock barometer",
+                &[Quantity::AirPressure],
+                &[Step::Fail(SensorError::Bus)],
+            )
+            .with_attempts(2),
+        )];
+        let sensor_data = read(&mut drivers, ReadoutKind::Wake);
+
+        assert_eq!(
+            sensor_data.fault(Quantity::AirPressure),
+            Some(Fault::FailedRead)
+        );
+        assert!(sensor_data.data.is_empty());
+    }
+
+    #[test]
+    fn dht_failing_every_attempt_drops_the_climate() {
+        power_on();
+        let mut drivers: Drivers = vec![
+            Box::new(
+                MockSensor::new(
+                    "Mock DHT11",
+                    &[Quantity::AirTemperature, Quantity::AirHumidity],
+                    &[Step::Fail(SensorError::Checksum)],
+                )
+                .before_radio()
+                .with_attempts(DHT_MAX_ATTEMPTS),
+            ),
+            Box::new(MockSensor::new(
+                "Mock battery",
+                &[Quantity::BatteryVoltage],
+                &[Step::Values(&[3950]), Step::Values(&[3940])],
+            )),
+        ];
+        let sensor_data = read(&mut drivers, ReadoutKind::Wake);
+
+        assert_eq!(
+            sensor_data.fault(Quantity::AirTemperature),
+            Some(Fault::FailedRead)
+        );
+        assert_eq!(
+            sensor_data.fault(Quantity::AirHumidity),
+            Some(Fault::FailedRead)
+        );
+        assert!(matches!(
+            sensor_data.data[..],
+            [Sensor::BatteryVoltage(3947)]
+        ));
+    }
+
+    #[test]
+    fn empty_reading_is_left_out() {
+        power_on();
+        // The battery on USB power
+        let mut drivers = probe(&[Quantity::BatteryVoltage], &[Step::Values(&[])]);
+        let sensor_data = read(&mut drivers, ReadoutKind::Wake);
+
+        assert!(sensor_data.data.is_empty());
+        assert!(sensor_data.health.is_empty());
+    }
+
+    #[test]
+    fn too_few_good_reads_are_a_failed_read() {
+        power_on();
+        const FLAKY: &[Step] = &[
+            Step::Values(&[1500]),
+            Step::Fail(SensorError::Adc),
+            Step::Fail(SensorError::Adc),
+            Step::Values(&[1510]),
+            Step::Fail(SensorError::Adc),
+        ];
+        let sensor_data = read(
+            &mut probe(&[Quantity::SoilMoisture], FLAKY),
+            ReadoutKind::Wake,
+        );
+
+        assert_eq!(
+            sensor_data.fault(Quantity::SoilMoisture),
+            Some(Fault::FailedRead)
+        );
+        assert!(sensor_data.data.is_empty());
+    }
+
+    #[test]
+    fn partial_reading_fails_the_missing_quantity() {
+        power_on();
+        const PROBES: &[Quantity] = &[
+            Quantity::SoilTemperature(Probe(0)),
+            Quantity::SoilTemperature(Probe(1)),
+        ];
+        let mut drivers: Drivers = vec![Box::new(
+            MockSensor::new("Mock DS18B20", PROBES, &[Step::Values(&[183])]).before_radio(),
+        )];
+        let sensor_data = read(&mut drivers, ReadoutKind::Wake);
+
+        assert!(matches!(
+            sensor_data.data[..],
+            [Sensor::SoilTemperature(Probe(0), Tenths(183))]
+        ));
+        assert_eq!(sensor_data.fault(PROBES[0]), None);
+        assert_eq!(sensor_data.fault(PROBES[1]), Some(Fault::FailedRead));
+    }
+
+    #[test]
+    fn once_reading_fills_every_slot() {
+        power_on();
+        let mut drivers: Drivers = vec![
+            Box::new(
+                MockSensor::new("Mock SCD41", &[Quantity::Co2], &[Step::Values(&[812])]).once(),
+            ),
+            Box::new(MockSensor::new(
+                "Mock battery",
+                &[Quantity::BatteryVoltage],
+                &[Step::Values(&[3950]), Step::Values(&[3940])],
+            )),
+        ];
+        let sensor_data = read(&mut drivers, ReadoutKind::Wake);
+
+        assert!(
+            sensor_data
+                .data
+                .iter()
+                .any(|s| matches!(s, Sensor::Co2(812)))
+        );
+        assert_eq!(sensor_data.fault(Quantity::Co2), None);
+    }
+
+    #[test]
+    fn identical_analog_samples_have_no_variance() {
+        power_on();
+        let mut drivers = probe(&[Quantity::BatteryVoltage], &[Step::Values(&[3900])]);
+        let sensor_data = read(&mut drivers, ReadoutKind::Wake);
+
+        assert_eq!(
+            sensor_data.fault(Quantity::BatteryVoltage),
+            Some(Fault::NoVariance)
+        );
+        assert!(matches!(
+            sensor_data.data[..],
+            [Sensor::BatteryVoltage(3900)]
+        ));
+    }
+
+    #[test]
+    fn before_radio_reading_fills_every_slot() {
+        power_on();
+        let mut drivers: Drivers = vec![Box::new(
+            MockSensor::new(
+                "Mock DHT11",
+                &[Quantity::AirTemperature],
+                &[Step::Values(&[-35])],
+            )
+            .before_radio(),
+        )];
+        let sensor_data = read(&mut drivers, ReadoutKind::Wake);
+
+        assert!(matches!(
+            sensor_data.data[..],
+            [Sensor::AirTemperature(Tenths(-35))]
+        ));
+        assert_eq!(sensor_data.fault(Quantity::AirTemperature), None);
+    }
+
+    #[test]
+    fn refreshes_leave_the_moisture_average() {
+        power_on();
+        let wake = |script| {
+            read(
+                &mut probe(&[Quantity::SoilMoisture], script),
+                ReadoutKind::Wake,
+            )
+        };
+        let refresh = |script| {
+            read(
+                &mut probe(&[Quantity::SoilMoisture], script),
+                ReadoutKind::Refresh,
+            )
+        };
+
+        assert_eq!(raw_moisture(&wake(MOIST)).as_deref(), Some("1500"));
+        // 40 % of the way from the stored 1500 mV, every time
+        assert_eq!(raw_moisture(&refresh(WET)).as_deref(), Some("1300"));
+        assert_eq!(raw_moisture(&refresh(WET)).as_deref(), Some("1300"));
+        assert_eq!(raw_moisture(&wake(WET)).as_deref(), Some("1300"));
+        assert_eq!(raw_moisture(&wake(WET)).as_deref(), Some("1180"));
+    }
+
+    #[test]
+    fn calibration_captures_unsmoothed_readings() {
+        power_on();
+        let wake = |script| {
+            read(
+                &mut probe(&[Quantity::SoilMoisture], script),
+                ReadoutKind::Wake,
+            )
+        };
+        wake(MOIST);
+        MOISTURE_CALIBRATION.set(Some(MoistureCalibration::EMPTY));
+
+        assert_eq!(raw_moisture(&wake(WET)).as_deref(), Some("1000"));
+        let calibration = MOISTURE_CALIBRATION.get().unwrap();
+        assert_eq!(
+            (calibration.min, calibration.max, calibration.samples),
+            (1000, 1000, 1)
+        );
+    }
+
+    #[test]
+    fn refreshes_leave_the_stored_levels() {
+        power_on();
+        let moisture = |script, kind| read(&mut probe(&[Quantity::SoilMoisture], script), kind);
+        let water = |script, kind| read(&mut probe(&[Quantity::WaterLevel], script), kind);
+
+        assert_eq!(
+            moisture_level(&moisture(DRY, ReadoutKind::Wake)),
+            Some(MoistureLevel::Dry)
+        );
+        // 1860 mV after smoothing: Moist, past the hysteresis of Dry
+        let refreshed = moisture(MOIST, ReadoutKind::Refresh);
+        assert_eq!(moisture_level(&refreshed), Some(MoistureLevel::Moist));
+        assert_eq!(MOISTURE_LEVEL.get(), Some(MoistureLevel::Dry));
+
+        const FLOODED: &[Step] = &[Step::Values(&[2895]), Step::Values(&[2905])];
+        const DRAINED: &[Step] = &[Step::Values(&[2395]), Step::Values(&[2405])];
+        assert_eq!(overflow(&water(FLOODED, ReadoutKind::Wake)), Some(true));
+        assert_eq!(overflow(&water(DRAINED, ReadoutKind::Refresh)), Some(false));
+        assert!(OVERFLOW_DETECTED.get());
+    }
+
+    #[test]
+    fn only_sound_wake_readings_add_light() {
+        power_on();
+        let light = |script, kind| read(&mut probe(&[Quantity::Illuminance], script), kind);
+        const LAMP: &[Step] = &[Step::Values(&[12000]), Step::Values(&[12010])];
+        // Above what any light sensor reports
+        const GLITCH: &[Step] = &[Step::Values(&[150_000]), Step::Values(&[150_010])];
+
+        light(LAMP, ReadoutKind::Wake);
+        let integral = LIGHT_INTEGRAL.get();
+        assert_ne!(integral, LightIntegral::EMPTY);
+
+        let refreshed = light(LAMP, ReadoutKind::Refresh);
+        assert!(
+            refreshed
+                .data
+                .iter()
+                .any(|s| matches!(s, Sensor::DailyLightIntegral(_)))
+        );
+        assert_eq!(LIGHT_INTEGRAL.get(), integral);
+
+        let glitched = light(GLITCH, ReadoutKind::Wake);
+        assert_eq!(
+            glitched.fault(Quantity::Illuminance),
+            Some(Fault::OutOfRange)
+        );
+        assert_eq!(LIGHT_INTEGRAL.get(), integral);
+    }
+}
@@ -1,48 +1,115 @@
 mod adc;
+#[cfg(feature = "aht20")]
+mod aht20;
+#[cfg(feature = "bh1750")]
+mod bh1750;
+#[cfg(feature = "bme280")]
+mod bme280;
 mod builder;
+mod dht;
+mod driver;
+#[cfg(feature = "ds18b20")]
+mod ds18b20;
 mod hardware;
+#[cfg(feature = "i2c")]
+mod i2c;
+#[cfg(feature = "mock-sensors")]
+mod mock;
+#[cfg(feature = "ds18b20")]
+mod onewire;
+#[cfg(feature = "scd4x")]
+mod scd4x;
+#[cfg(feature = "sht3x")]
+mod sht3x;
+#[cfg(feature = "veml7700")]
+mod veml7700;
 
 pub use hardware::SensorPeripherals;
 
+use core::cell::Cell;
+
+use critical_section::Mutex;
 use log::info;
 
-use crate::domain::SensorData;
-use adc::read_battery_voltage;
-use builder::{collect_adc_sensor_data, read_dht11_with_retries};
-use dht_sensor::dht11::Reading;
-use hardware::SensorHardware;
+use crate::domain::{Quantity, SensorData};
+use builder::{
+    ReadoutKind, Samples, collect_sensor_data, read_before_radio, read_quantity, start_once,
+};
+use driver::Drivers;
+
+/// Quantities measured by the fitted drivers, set by [`begin_read`]
+static FITTED: Mutex<Cell<[bool; Quantity::COUNT]>> =
+    Mutex::new(Cell::new([false; Quantity::COUNT]));
 
 /// Sensor reading in progress. Created with [`begin_read`] (which reads the
-/// timing-sensitive DHT11 and an early battery sample *before* the WiFi radio
-/// starts), then completed with [`finish_read`] (the ADC loop, which can safely
-/// overlap WiFi via `join`).
+/// timing-sensitive drivers and an early battery sample *before* the WiFi
+/// radio starts), then completed with [`finish_read`] (the repeated samples,
+/// which can safely overlap WiFi via `join`). Keeps the drivers for
+/// [`read_again`].
 pub struct SensorReadout {
-    hw: SensorHardware<'static>,
-    dht11: Option<Reading>,
+    drivers: Drivers,
+    before_radio: Samples,
     /// Early single-shot battery reading (mV) used for the low-battery guard.
-    /// `None` while charging on USB (see [`read_battery_voltage`]).
+    /// `None` while charging on USB.
     pub battery_mv: Option<u16>,
 }
 
-/// Pre-radio phase: initialize hardware, read the DHT11 (with retries) and take
-/// one battery sample. Keeping these off the radio avoids interrupt corruption
-/// of the bit-banged DHT11 read and gives the low-battery guard a value before
-/// WiFi is ever powered on.
+/// Pre-radio phase: set up the drivers, start the slow measurements (the
+/// SCD4x), read those that must run before the radio (the DHT11 and DS18B20
+/// probes, with retries) and take one battery sample. Keeping these off the
+/// radio avoids interrupt corruption of the bit-banged reads and gives the
+/// low-battery guard a value before WiFi is ever powered on.
 pub async fn begin_read(p: SensorPeripherals) -> SensorReadout {
     info!("Initializing sensor hardware");
-    let mut hw = hardware::initialize_hardware(p).await;
-    let dht11 = read_dht11_with_retries(&mut hw.dht11_pin).await;
-    let battery_mv = read_battery_voltage(&mut hw.adc1, &mut hw.battery_pin).await;
+    let mut drivers = drivers(p).await;
+    critical_section::with(|cs| {
+        let mut fitted = [false; Quantity::COUNT];
+        for quantity in drivers.iter().flat_map(|driver| driver.quantities()) {
+            fitted[quantity.index()] = true;
+        }
+        FITTED.borrow(cs).set(fitted);
+    });
+    start_once(&mut drivers);
+    let before_radio = read_before_radio(&mut drivers).await;
+    let battery_mv = read_quantity(&mut drivers, Quantity::BatteryVoltage)
+        .await
+        .map(|mv| mv as u16);
     SensorReadout {
-        hw,
-        dht11,
+        drivers,
+        before_radio,
         battery_mv,
     }
 }
 
-/// Post-guard phase: sample the ADC sensors and build the averaged SensorData,
-/// folding in the DHT11 reading taken in [`begin_read`]. Safe to run inside a
-/// `join` alongside the WiFi connection.
-pub async fn finish_read(mut readout: SensorReadout) -> SensorData {
-    collect_adc_sensor_data(&mut readout.hw, readout.dht11).await
+/// Post-guard phase: take the repeated samples and build the averaged
+/// SensorData, folding in the readings taken in [`begin_read`]. Safe to run
+/// inside a `join` alongside the WiFi connection.
+pub async fn finish_read(readout: &mut SensorReadout) -> SensorData {
+    let before_radio = core::mem::take(&mut readout.before_radio);
+    collect_sensor_data(&mut readout.drivers, before_radio, ReadoutKind::Wake).await
+}
+
+/// Take a complete new reading for maintenance mode. The radio is on by now,
+/// so the DHT11 read relies on its retries. The state kept across wakes
+/// (EMA, hysteresis) is read but not updated.
+pub async fn read_again(readout: &mut SensorReadout) -> SensorData {
+    start_once(&mut readout.drivers);
+    let before_radio = read_before_radio(&mut readout.drivers).await;
+    collect_sensor_data(&mut readout.drivers, before_radio, ReadoutKind::Refresh).await
+}
+
+/// Whether a fitted driver measures `quantity`; sensors derived from other
+/// quantities are not announced to HA.
+pub fn is_fitted(quantity: Quantity) -> bool {
+    critical_section::with(|cs| FITTED.borrow(cs).get()[quantity.index()])
+}
+
+#[cfg(not(feature = "mock-sensors"))]
+async fn drivers(p: SensorPeripherals) -> Drivers {
+    hardware::drivers(p).await
+}
+
+#[cfg(feature = "mock-sensors")]
+async fn drivers(_: SensorPeripherals) -> Drivers {
+    mock::drivers()
 }
@@ -0,0 +1,208 @@
+//! Bit-banged 1-Wire bus for the DS18B20 soil temperature probes
+//!
+//! The bus runs on GPIO10 with an external 4.7 kΩ pull-up to 3.3 V; the
+//! probes are powered from 3.3 V, not parasitically. Like the DHT11 it is
+//! bit-banged and read before the radio starts, and every time slot runs in
+//! a critical section so an interrupt can't stretch it.
+
+use core::fmt::{self, Display, Formatter};
+
+use esp_hal::{
+    delay::Delay,
+    gpio::{DriveMode, Flex, Level, Output, OutputConfig, Pull},
+    peripherals::GPIO10,
+};
+use heapless::Vec;
+use log::warn;
+
+use super::driver::SensorError;
+
+/// Most devices [`OneWire::search`] collects
+const MAX_DEVICES: usize = 8;
+const SEARCH_ROM: u8 = 0xF0;
+const MATCH_ROM: u8 = 0x55;
+const SKIP_ROM: u8 = 0xCC;
+
+/// 64-bit ROM code of a device: family code in the low byte, CRC in the high
+/// byte. Shown the way `config::SOIL_PROBE_ROMS` takes it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Rom(pub u64);
+
+impl Rom {
+    pub(super) fn family(&self) -> u8 {
+        self.0 as u8
+    }
+}
+
+impl Display for Rom {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:016X}", self.0)
+    }
+}
+
+pub(super) struct OneWire {
+    pin: Flex<'static>,
+    delay: Delay,
+}
+
+impl OneWire {
+    pub(super) fn new(pin: GPIO10<'static>) -> Self {
+        // Open-drain, released high by the external pull-up
+        let mut pin = Output::new(
+            pin,
+            Level::High,
+            OutputConfig::default()
+                .with_drive_mode(DriveMode::OpenDrain)
+                .with_pull(Pull::None),
+        )
+        .into_flex();
+        pin.set_input_enable(true);
+        Self {
+            pin,
+            delay: Delay::new(),
+        }
+    }
+
+    /// Reset pulse; true when a device answered with a presence pulse.
+    fn reset(&mut self) -> bool {
+        critical_section::with(|_| {
+            self.pin.set_low();
+            self.delay.delay_micros(480);
+            self.pin.set_high();
+            self.delay.delay_micros(70);
+            let present = self.pin.is_low();
+            self.delay.delay_micros(410);
+            present
+        })
+    }
+
+    fn write_bit(&mut self, bit: bool) {
+        critical_section::with(|_| {
+            self.pin.set_low();
+            if bit {
+                self.delay.delay_micros(6);
+                self.pin.set_high();
+                self.delay.delay_micros(64);
+            } else {
+                self.delay.delay_micros(60);
+                self.pin.set_high();
+                self.delay.delay_micros(10);
+            }
+        })
+    }
+
+    fn read_bit(&mut self) -> bool {
+        critical_section::with(|_| {
+            self.pin.set_low();
+            self.delay.delay_micros(6);
+            self.pin.set_high();
+            self.delay.delay_micros(9);
+            let bit = self.pin.is_high();
+            self.delay.delay_micros(55);
+            bit
+        })
+    }
+
+    /// Least significant bit first
+    pub(super) fn write_byte(&mut self, byte: u8) {
+        for i in 0..8 {
+            self.write_bit((byte >> i) & 1 != 0);
+        }
+    }
+
+    pub(super) fn read_bytes(&mut self, buffer: &mut [u8]) {
+        for byte in buffer.iter_mut() {
+            *byte = (0..8).fold(0, |byte, i| byte | (u8::from(self.read_bit()) << i));
+        }
+    }
+
+    /// Reset the bus and address one device, or every device with `None`.
+    pub(super) fn select(&mut self, rom: Option<Rom>) -> Result<(), SensorError> {
+        if !self.reset() {
+            return Err(SensorError::NotFound);
+        }
+        match rom {
+            Some(rom) => {
+                self.write_byte(MATCH_ROM);
+                rom.0
+                    .to_le_bytes()
+                    .into_iter()
+                    .for_each(|byte| self.write_byte(byte));
+            }
+            None => self.write_byte(SKIP_ROM),
+        }
+        Ok(())
+    }
+
+    /// ROM codes of the devices on the bus, in ascending bit order
+    /// (Maxim application note 187).
+    pub(super) fn search(&mut self) -> Result<Vec<Rom, MAX_DEVICES>, SensorError> {
+        let mut roms = Vec::new();
+        let mut rom = 0_u64;
+        // 1-based bit position where the last pass took the 0 branch
+        let mut last_discrepancy = 0;
+        loop {
+            if !self.reset() {
+                return Err(SensorError::NotFound);
+            }
+            self.write_byte(SEARCH_ROM);
+            let mut discrepancy = 0;
+            for position in 1..=64 {
+                let bit = self.read_bit();
+                let complement = self.read_bit();
+                let direction = match (bit, complement) {
+                    // Nobody answered: a device left the bus mid-search
+                    (true, true) => return Err(SensorError::Bus),
+                    // Devices differ here
+                    (false, false) => {
+                        let direction = if position < last_discrepancy {
+                            (rom >> (position - 1)) & 1 != 0
+                        } else {
+                            position == last_discrepancy
+                        };
+                        if !direction {
+                            discrepancy = position;
+                        }
+                        direction
+                    }
+                    (bit, _) => bit,
+                };
+                if direction {
+                    rom |= 1 << (position - 1);
+                } else {
+                    rom &= !(1 << (position - 1));
+                }
+                self.write_bit(direction);
+            }
+            if crc8(&rom.to_le_bytes()) != 0 {
+                return Err(SensorError::Checksum);
+            }
+            if roms.push(Rom(rom)).is_err() {
+                warn!(
+                    "More than {} 1-Wire devices, ignoring the rest",
+                    MAX_DEVICES
+                );
+                break;
+            }
+            last_discrepancy = discrepancy;
+            if last_discrepancy == 0 {
+                break;
+            }
+        }
+        Ok(roms)
+    }
+}
+
+/// Dallas/Maxim CRC-8 (polynomial 0x31 reflected, init 0). Zero over data
+/// followed by its CRC.
+pub(super) fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x01 != 0 {
+                (crc >> 1) ^ 0x8C
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
@@ -0,0 +1,157 @@
+use alloc::boxed::Box;
+
+use embassy_time::{Duration, Instant, Timer};
+use log::warn;
+
+use crate::{config::SCD4X_SINGLE_SHOT, domain::Quantity};
+
+use super::{
+    driver::{ReadFuture, Reading, Sampling, SensorDriver, SensorError},
+    i2c::{self, SharedI2c, checked_word},
+};
+
+const ADDRESS: u8 = 0x62;
+const GET_SERIAL_NUMBER: [u8; 2] = [0x36, 0x82];
+const MEASURE_SINGLE_SHOT: [u8; 2] = [0x21, 0x9D];
+const START_PERIODIC: [u8; 2] = [0x21, 0xB1];
+const STOP_PERIODIC: [u8; 2] = [0x3F, 0x86];
+const GET_DATA_READY: [u8; 2] = [0xE4, 0xB8];
+const READ_MEASUREMENT: [u8; 2] = [0xEC, 0x05];
+/// 5 s for a single shot, and until the first periodic result
+const MEASURE_TIME: Duration = Duration::from_millis(5000);
+/// Until the chip takes commands again after stopping periodic measurement
+const STOP_TIME: Duration = Duration::from_millis(500);
+/// Execution time of the read commands
+const COMMAND_TIME: Duration = Duration::from_millis(1);
+
+static CO2: [Quantity; 1] = [Quantity::Co2];
+static CO2_AND_CLIMATE: [Quantity; 3] = [
+    Quantity::Co2,
+    Quantity::AirTemperature,
+    Quantity::AirHumidity,
+];
+
+/// Sensirion SCD40/SCD41 CO2 sensor, measured once per wake. The measurement
+/// is started before the radio and collected after WiFi association, so its
+/// 5 s overlap the rest of the wake.
+pub(super) struct Scd4x {
+    bus: SharedI2c,
+    /// Also report air temperature and humidity
+    climate: bool,
+    /// When the pending measurement was started
+    started: Option<Instant>,
+}
+
+impl Scd4x {
+    /// Check the chip answers by reading its serial number. A periodic
+    /// measurement left running by a reset mid-read is stopped first.
+    pub(super) async fn probe(bus: SharedI2c, climate: bool) -> Result<Self, SensorError> {
+        let sensor = Self {
+            bus,
+            climate,
+            started: None,
+        };
+        if sensor.read_words::<3>(&GET_SERIAL_NUMBER).await.is_err() {
+            sensor.stop_periodic().await?;
+            sensor.read_words::<3>(&GET_SERIAL_NUMBER).await?;
+        }
+        Ok(sensor)
+    }
+
+    /// Send a command, then read `N` CRC-checked words back.
+    async fn read_words<const N: usize>(&self, command: &[u8; 2]) -> Result<[u16; N], SensorError> {
+        i2c::write(self.bus, ADDRESS, command)?;
+        Timer::after(COMMAND_TIME).await;
+        let mut data = [0; 9];
+        let data = &mut data[..N * 3];
+        i2c::read(self.bus, ADDRESS, data)?;
+        let mut words = [0; N];
+        for (word, bytes) in words.iter_mut().zip(data.chunks(3)) {
+            *word = checked_word(bytes)?;
+        }
+        Ok(words)
+    }
+
+    async fn stop_periodic(&self) -> Result<(), SensorError> {
+        i2c::write(self.bus, ADDRESS, &STOP_PERIODIC)?;
+        Timer::after(STOP_TIME).await;
+        Ok(())
+    }
+
+    /// Start a measurement and return when it started.
+    fn trigger(&self) -> Result<Instant, SensorError> {
+        let command = if SCD4X_SINGLE_SHOT {
+            &MEASURE_SINGLE_SHOT
+        } else {
+            &START_PERIODIC
+        };
+        i2c::write(self.bus, ADDRESS, command)?;
+        Ok(Instant::now())
+    }
+
+    /// Collect the started measurement, starting one if none is pending.
+    async fn measure(&mut self) -> Result<Reading, SensorError> {
+        let started = match self.started.take() {
+            Some(started) => started,
+            None => self.trigger()?,
+        };
+        Timer::at(started + MEASURE_TIME).await;
+        let result = self.read_measurement().await;
+        if !SCD4X_SINGLE_SHOT {
+            self.stop_periodic().await?;
+        }
+        result
+    }
+
+    async fn read_measurement(&self) -> Result<Reading, SensorError> {
+        let [status] = self.read_words::<1>(&GET_DATA_READY).await?;
+        // Data is ready when any of the low 11 bits is set
+        if status & 0x07FF == 0 {
+            return Err(SensorError::Timeout);
+        }
+        let [co2, temperature, humidity] = self.read_words::<3>(&READ_MEASUREMENT).await?;
+        let reading = Reading::default().with(Quantity::Co2, i32::from(co2));
+        if !self.climate {
+            return Ok(reading);
+        }
+        let temperature = i32::from(temperature);
+        let humidity = i32::from(humidity);
+        Ok(reading
+            // -45 °C + 175 °C × raw / (2^16 - 1), in tenths
+            .with(
+                Quantity::AirTemperature,
+                (1750 * temperature + 32767) / 65535 - 450,
+            )
+            // 100 % × raw / (2^16 - 1), in tenths
+            .with(Quantity::AirHumidity, (1000 * humidity + 32767) / 65535))
+    }
+}
+
+impl SensorDriver for Scd4x {
+    fn name(&self) -> &'static str {
+        "SCD4x"
+    }
+
+    fn quantities(&self) -> &'static [Quantity] {
+        if self.climate { &CO2_AND_CLIMATE } else { &CO2 }
+    }
+
+    fn sampling(&self) -> Sampling {
+        Sampling::Once
+    }
+
+    fn warmup(&self) -> Duration {
+        Duration::from_millis(0)
+    }
+
+    fn start(&mut self) {
+        match self.trigger() {
+            Ok(started) => self.started = Some(started),
+            Err(error) => warn!("SCD4x measurement start failed: {}", error),
+        }
+    }
+
+    fn read(&mut self) -> ReadFuture<'_> {
+        Box::pin(self.measure())
+    }
+}
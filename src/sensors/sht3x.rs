@@ -0,0 +1,67 @@
+use alloc::boxed::Box;
+
+use embassy_time::{Duration, Timer};
+
+use crate::domain::Quantity;
+
+use super::{
+    driver::{ReadFuture, Reading, SensorDriver, SensorError},
+    i2c::{self, SharedI2c, checked_word},
+};
+
+const ADDRESS: u8 = 0x44;
+const READ_STATUS: [u8; 2] = [0xF3, 0x2D];
+/// Single shot, high repeatability, no clock stretching
+const MEASURE: [u8; 2] = [0x24, 0x00];
+/// 15.5 ms at high repeatability
+const MEASURE_TIME: Duration = Duration::from_millis(16);
+
+/// Sensirion SHT30/31/35 temperature and humidity sensor in single-shot mode
+pub(super) struct Sht3x {
+    bus: SharedI2c,
+}
+
+impl Sht3x {
+    /// Check the chip answers by reading its status register.
+    pub(super) async fn probe(bus: SharedI2c) -> Result<Self, SensorError> {
+        let mut status = [0; 3];
+        i2c::write_read(bus, ADDRESS, &READ_STATUS, &mut status)?;
+        checked_word(&status)?;
+        Ok(Self { bus })
+    }
+
+    async fn measure(&mut self) -> Result<Reading, SensorError> {
+        i2c::write(self.bus, ADDRESS, &MEASURE)?;
+        Timer::after(MEASURE_TIME).await;
+        let mut data = [0; 6];
+        i2c::read(self.bus, ADDRESS, &mut data)?;
+        let temperature = i32::from(checked_word(&data[0..3])?);
+        let humidity = i32::from(checked_word(&data[3..6])?);
+        Ok(Reading::default()
+            // -45 °C + 175 °C × raw / (2^16 - 1), in tenths
+            .with(
+                Quantity::AirTemperature,
+                (1750 * temperature + 32767) / 65535 - 450,
+            )
+            // 100 % × raw / (2^16 - 1), in tenths
+            .with(Quantity::AirHumidity, (1000 * humidity + 32767) / 65535))
+    }
+}
+
+impl SensorDriver for Sht3x {
+    fn name(&self) -> &'static str {
+        "SHT3x"
+    }
+
+    fn quantities(&self) -> &'static [Quantity] {
+        &[Quantity::AirTemperature, Quantity::AirHumidity]
+    }
+
+    fn warmup(&self) -> Duration {
+        Duration::from_millis(0)
+    }
+
+    fn read(&mut self) -> ReadFuture<'_> {
+        Box::pin(self.measure())
+    }
+}
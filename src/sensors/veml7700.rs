@@ -0,0 +1,78 @@
+use alloc::boxed::Box;
+
+use embassy_time::{Duration, Timer};
+
+use crate::domain::Quantity;
+
+use super::{
+    driver::{ReadFuture, Reading, SensorDriver, SensorError},
+    i2c::{self, SharedI2c},
+};
+
+const ADDRESS: u8 = 0x10;
+const REG_CONFIG: u8 = 0x00;
+const REG_ALS: u8 = 0x04;
+const REG_ID: u8 = 0x07;
+const DEVICE_ID: u8 = 0x81;
+/// Gain ×1/8 (bits 12..11), 100 ms integration time (bits 9..6), powered on
+const CONFIG_ON: u16 = 0b0001_0000_0000_0000;
+/// Same with the shutdown bit set
+const CONFIG_SHUTDOWN: u16 = CONFIG_ON | 0b1;
+/// One integration time after power-on, plus the 2.5 ms wake-up
+const MEASURE_TIME: Duration = Duration::from_millis(110);
+/// 0.5376 lx per count at gain ×1/8 and 100 ms, in 1/10000 lx; saturates
+/// around 35 klx
+const LUX_PER_COUNT: i32 = 5376;
+
+/// Vishay VEML7700 ambient light sensor, shut down between reads
+pub(super) struct Veml7700 {
+    bus: SharedI2c,
+}
+
+impl Veml7700 {
+    /// Check the device ID, and shut the chip down until the first read.
+    pub(super) async fn probe(bus: SharedI2c) -> Result<Self, SensorError> {
+        let mut id = [0; 2];
+        i2c::write_read(bus, ADDRESS, &[REG_ID], &mut id)?;
+        if id[0] != DEVICE_ID {
+            return Err(SensorError::NotFound);
+        }
+        let sensor = Self { bus };
+        sensor.configure(CONFIG_SHUTDOWN)?;
+        Ok(sensor)
+    }
+
+    fn configure(&self, config: u16) -> Result<(), SensorError> {
+        let [low, high] = config.to_le_bytes();
+        i2c::write(self.bus, ADDRESS, &[REG_CONFIG, low, high])
+    }
+
+    async fn measure(&mut self) -> Result<Reading, SensorError> {
+        self.configure(CONFIG_ON)?;
+        Timer::after(MEASURE_TIME).await;
+        let mut data = [0; 2];
+        let result = i2c::write_read(self.bus, ADDRESS, &[REG_ALS], &mut data);
+        self.configure(CONFIG_SHUTDOWN)?;
+        result?;
+        let raw = i32::from(u16::from_le_bytes(data));
+        Ok(Reading::default().with(Quantity::Illuminance, (raw * LUX_PER_COUNT + 5000) / 10_000))
+    }
+}
+
+impl SensorDriver for Veml7700 {
+    fn name(&self) -> &'static str {
+        "VEML7700"
+    }
+
+    fn quantities(&self) -> &'static [Quantity] {
+        &[Quantity::Illuminance]
+    }
+
+    fn warmup(&self) -> Duration {
+        Duration::from_millis(0)
+    }
+
+    fn read(&mut self) -> ReadFuture<'_> {
+        Box::pin(self.measure())
+    }
+}
@@ -0,0 +1,293 @@
+//! Logger with remote shipping over UDP syslog
+//!
+//! Prints to the USB serial console like `esp_println`'s logger and, with
+//! `SYSLOG_HOST` set, also keeps the records of the wake cycle in a fixed
+//! buffer. Before WiFi goes down they are sent as RFC 5424 messages over UDP,
+//! so a device nobody has plugged in still leaves a log behind. The level for
+//! shipping is set at runtime from the HA **Remote log level** select.
+
+use core::{cell::RefCell, fmt::Write};
+
+use critical_section::Mutex;
+use embassy_net::{
+    IpAddress, Stack,
+    dns::{DnsQueryType, Error as DnsError},
+    udp::{BindError, PacketMetadata, SendError, UdpSocket},
+};
+use esp_println::println;
+use heapless::Deque;
+use log::{Level, LevelFilter, Log, Metadata, Record, info};
+
+use crate::{
+    BOOT_COUNT, SYSLOG_LEVEL,
+    config::{CONSOLE_LOG_LEVEL, DEVICE_ID, SYSLOG_BUFFER_LINES, SYSLOG_LINE_LEN, SYSLOG_PORT},
+    crash::Truncating,
+};
+
+/// Levels offered by the HA select, lowest first
+pub const LEVELS: [LevelFilter; 6] = [
+    LevelFilter::Off,
+    LevelFilter::Error,
+    LevelFilter::Warn,
+    LevelFilter::Info,
+    LevelFilter::Debug,
+    LevelFilter::Trace,
+];
+
+/// Records waiting to be shipped, oldest first
+static BUFFER: Mutex<RefCell<Buffer>> = Mutex::new(RefCell::new(Buffer::new()));
+
+static LOGGER: Logger = Logger;
+
+/// The syslog server, if log shipping is configured
+pub fn host() -> Option<&'static str> {
+    option_env!("SYSLOG_HOST").filter(|host| !host.is_empty())
+}
+
+/// Install the logger. Records below `CONSOLE_LOG_LEVEL` only reach the
+/// console, records below the shipping level only the buffer.
+pub fn init() {
+    if log::set_logger(&LOGGER).is_ok() {
+        apply_max_level();
+    }
+}
+
+/// Level for shipping, kept in RTC memory across deep sleep
+pub fn level() -> LevelFilter {
+    SYSLOG_LEVEL.get()
+}
+
+pub fn set_level(level: LevelFilter) {
+    SYSLOG_LEVEL.set(level);
+    apply_max_level();
+}
+
+/// Lowercase name, as used by the HA select
+pub fn level_name(level: LevelFilter) -> &'static str {
+    match level {
+        LevelFilter::Off => "off",
+        LevelFilter::Error => "error",
+        LevelFilter::Warn => "warn",
+        LevelFilter::Info => "info",
+        LevelFilter::Debug => "debug",
+        LevelFilter::Trace => "trace",
+    }
+}
+
+fn apply_max_level() {
+    let shipped = if host().is_some() {
+        level()
+    } else {
+        LevelFilter::Off
+    };
+    log::set_max_level(CONSOLE_LOG_LEVEL.max(shipped));
+}
+
+fn ships(level: Level) -> bool {
+    host().is_some() && level <= self::level()
+}
+
+/// Send the buffered records to `SYSLOG_HOST` and empty the buffer. Records
+/// logged while sending stay in the buffer; callers bound this with a
+/// timeout.
+pub async fn flush(stack: Stack<'_>) -> Result<(), Error> {
+    let Some(host) = host() else {
+        return Ok(());
+    };
+    let address = resolve(stack, host).await?;
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0u8; 16];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0u8; 4 * SYSLOG_LINE_LEN];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(0)?;
+
+    let (lines, dropped) = critical_section::with(|cs| {
+        let mut buffer = BUFFER.borrow_ref_mut(cs);
+        (buffer.lines.len(), core::mem::take(&mut buffer.dropped))
+    });
+    if dropped > 0 {
+        let mut line = Line::new();
+        line.format(
+            Level::Warn,
+            format_args!("{dropped} earlier records dropped, syslog buffer full"),
+        );
+        socket
+            .send_to(line.as_bytes(), (address, SYSLOG_PORT))
+            .await?;
+    }
+    for _ in 0..lines {
+        let Some(line) = critical_section::with(|cs| BUFFER.borrow_ref_mut(cs).lines.pop_front())
+        else {
+            break;
+        };
+        socket
+            .send_to(line.as_bytes(), (address, SYSLOG_PORT))
+            .await?;
+    }
+    socket.flush().await;
+    info!("Shipped {} log records to {}", lines, host);
+    Ok(())
+}
+
+async fn resolve(stack: Stack<'_>, host: &str) -> Result<IpAddress, Error> {
+    stack
+        .dns_query(host, DnsQueryType::A)
+        .await?
+        .first()
+        .copied()
+        .ok_or(Error::Dns(DnsError::Failed))
+}
+
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= CONSOLE_LOG_LEVEL || ships(metadata.level())
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() <= CONSOLE_LOG_LEVEL {
+            print(record);
+        }
+        if ships(record.level()) {
+            let mut line = Line::new();
+            line.format(
+                record.level(),
+                format_args!("{}: {}", record.target(), record.args()),
+            );
+            critical_section::with(|cs| BUFFER.borrow_ref_mut(cs).push(line));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Same format as `esp_println::logger`
+fn print(record: &Record) {
+    const RESET: &str = "\u{001B}[0m";
+    let color = match record.level() {
+        Level::Error => "\u{001B}[31m",
+        Level::Warn => "\u{001B}[33m",
+        Level::Info => "\u{001B}[32m",
+        Level::Debug => "\u{001B}[34m",
+        Level::Trace => "\u{001B}[35m",
+    };
+    println!("{}{} - {}{}", color, record.level(), record.args(), RESET);
+}
+
+struct Buffer {
+    lines: Deque<Line, SYSLOG_BUFFER_LINES>,
+    /// Records dropped since the last flush
+    dropped: usize,
+}
+
+impl Buffer {
+    const fn new() -> Self {
+        Self {
+            lines: Deque::new(),
+            dropped: 0,
+        }
+    }
+
+    /// A full buffer drops its oldest record: the end of a failing cycle
+    /// matters more than its start.
+    fn push(&mut self, line: Line) {
+        if self.lines.is_full() {
+            self.lines.pop_front();
+            self.dropped += 1;
+        }
+        let _ = self.lines.push_back(line);
+    }
+}
+
+/// One RFC 5424 message, formatted when it was logged
+struct Line {
+    bytes: [u8; SYSLOG_LINE_LEN],
+    len: usize,
+}
+
+impl Line {
+    const fn new() -> Self {
+        Self {
+            bytes: [0; SYSLOG_LINE_LEN],
+            len: 0,
+        }
+    }
+
+    /// `<PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID SD MSG` with facility
+    /// `user`. There is no wall clock, so the timestamp is left out and the
+    /// uptime goes into the `meta` element; the wake number is the PROCID.
+    fn format(&mut self, level: Level, message: core::fmt::Arguments) {
+        let severity = match level {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        };
+        let uptime_cs = esp_hal::time::Instant::now()
+            .duration_since_epoch()
+            .as_millis()
+            / 10;
+        let mut writer = Truncating::new(&mut self.bytes);
+        let _ = write!(
+            writer,
+            "<{}>1 - {} {} {} - [meta sysUpTime=\"{}\"] {}",
+            8 + severity,
+            DEVICE_ID,
+            env!("CARGO_PKG_NAME"),
+            BOOT_COUNT.get(),
+            uptime_cs,
+            message
+        );
+        self.len = writer.len;
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Dns(DnsError),
+    Bind(BindError),
+    Send(SendError),
+    Timeout,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::Dns(e) => write!(f, "DNS error: {e:?}"),
+            Error::Bind(e) => write!(f, "Bind error: {e:?}"),
+            Error::Send(e) => write!(f, "Send error: {e:?}"),
+            Error::Timeout => write!(f, "Log shipping timed out"),
+        }
+    }
+}
+
+impl From<DnsError> for Error {
+    fn from(error: DnsError) -> Self {
+        Self::Dns(error)
+    }
+}
+
+impl From<BindError> for Error {
+    fn from(error: BindError) -> Self {
+        Self::Bind(error)
+    }
+}
+
+impl From<SendError> for Error {
+    fn from(error: SendError) -> Self {
+        Self::Send(error)
+    }
+}
@@ -0,0 +1,111 @@
+//! Wake-cycle watchdog
+//!
+//! The RTC watchdog (RWDT) bounds every phase of the wake cycle. A hang in a
+//! driver (display init, the DHT11 bit-bang, a blocking ADC read) would
+//! otherwise keep the radio, and maybe the pump relay, on until the battery
+//! is empty. The wake cycle feeds it at phase boundaries; if a phase overruns,
+//! stage 0 interrupts, switches the pump off, records the hang as a crash
+//! report and puts the device to sleep. Should interrupts be blocked, stage 1
+//! resets the chip a few seconds later.
+
+use core::cell::{Cell, RefCell};
+
+use critical_section::Mutex;
+use embassy_time::Duration;
+use esp_hal::{
+    delay::Delay,
+    gpio::{Level, Output, OutputConfig},
+    handler,
+    interrupt::Priority,
+    peripherals::{GPIO13, GPIO14, LPWR},
+    rtc_cntl::{Rtc, RwdtStage, RwdtStageAction},
+    time,
+};
+use log::{error, info};
+
+use crate::{
+    config::{DEEP_SLEEP_DURATION_SECONDS, WATCHDOG_RESET_GRACE_SECONDS, WATCHDOG_TIMEOUT_SECONDS},
+    crash,
+    sleep::enter_deep,
+};
+
+/// Owns the RTC while the watchdog runs; `stop` hands it on to deep sleep.
+static RTC: Mutex<RefCell<Option<Rtc<'static>>>> = Mutex::new(RefCell::new(None));
+
+/// The phase the wake cycle entered with the last feed
+static PHASE: Mutex<Cell<&'static str>> = Mutex::new(Cell::new("boot"));
+
+/// Arm the watchdog with `WATCHDOG_TIMEOUT_SECONDS` for the `boot` phase.
+pub fn start(lpwr: LPWR<'static>) {
+    let mut rtc = Rtc::new(lpwr);
+    rtc.set_interrupt_handler(expired);
+
+    // `enable` restores the default stage actions, so it comes first.
+    rtc.rwdt.enable();
+    rtc.rwdt.listen();
+    rtc.rwdt
+        .set_timeout(RwdtStage::Stage0, timeout(WATCHDOG_TIMEOUT_SECONDS));
+    rtc.rwdt
+        .set_stage_action(RwdtStage::Stage1, RwdtStageAction::ResetSystem);
+    rtc.rwdt
+        .set_timeout(RwdtStage::Stage1, timeout(WATCHDOG_RESET_GRACE_SECONDS));
+    rtc.rwdt.feed();
+
+    critical_section::with(|cs| RTC.borrow_ref_mut(cs).replace(rtc));
+}
+
+/// Enter `phase` with a fresh `WATCHDOG_TIMEOUT_SECONDS` budget.
+pub fn feed(phase: &'static str) {
+    feed_for(phase, Duration::from_secs(WATCHDOG_TIMEOUT_SECONDS));
+}
+
+/// Enter `phase` with a budget other than the default, for phases that are
+/// bounded by a longer timeout of their own.
+pub fn feed_for(phase: &'static str, budget: Duration) {
+    critical_section::with(|cs| {
+        if let Some(rtc) = RTC.borrow_ref_mut(cs).as_mut() {
+            rtc.rwdt
+                .set_timeout(RwdtStage::Stage0, timeout(budget.as_secs()));
+            rtc.rwdt.feed();
+        }
+        PHASE.borrow(cs).set(phase);
+    });
+}
+
+/// Run `f` on the RTC, which the watchdog holds for the whole wake cycle.
+pub fn with_rtc<R>(f: impl FnOnce(&Rtc<'static>) -> R) -> Option<R> {
+    critical_section::with(|cs| RTC.borrow_ref(cs).as_ref().map(f))
+}
+
+/// Hand the RTC over for deep sleep; `enter_deep` disables the watchdog.
+pub fn stop() -> Rtc<'static> {
+    critical_section::with(|cs| RTC.borrow_ref_mut(cs).take()).expect("watchdog started at boot")
+}
+
+fn timeout(seconds: u64) -> time::Duration {
+    time::Duration::from_secs(seconds)
+}
+
+/// Stage 0 expired: the current phase hangs. Never returns to it.
+#[handler(priority = Priority::max())]
+fn expired() {
+    // SAFETY: the interrupted wake cycle never runs again, so nothing else
+    // uses these peripherals.
+    let _pump_pin = Output::new(
+        unsafe { GPIO13::steal() },
+        Level::Low,
+        OutputConfig::default(),
+    );
+    let phase = critical_section::with(|cs| PHASE.borrow(cs).get());
+    error!("Watchdog expired in phase {phase}");
+    crash::record_hang(phase);
+
+    info!("Enter deep sleep for {}s", DEEP_SLEEP_DURATION_SECONDS);
+    Delay::new().delay_millis(100);
+    let mut wake_up_btn_pin = unsafe { GPIO14::steal() };
+    enter_deep(
+        &mut wake_up_btn_pin,
+        &mut Rtc::new(unsafe { LPWR::steal() }),
+        Duration::from_secs(DEEP_SLEEP_DURATION_SECONDS),
+    )
+}
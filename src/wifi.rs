@@ -1,7 +1,9 @@
 use embassy_executor::Spawner;
 use embassy_futures::select::{Either, select};
 use embassy_net::{Config, DhcpConfig, Runner, Stack, StackResources};
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex, once_lock::OnceLock, signal::Signal,
+};
 use embassy_time::{Duration, Timer};
 use esp_hal::peripherals;
 use esp_radio::wifi::Config as WifiConfig;
@@ -9,7 +11,10 @@ use esp_radio::wifi::{ControllerConfig, Interface, WifiController, WifiError, st
 use log::{error, info};
 use static_cell::StaticCell;
 
-use crate::config::{WIFI_RECONNECT_BACKOFF_MAX_MS, WIFI_RECONNECT_BACKOFF_START_MS};
+use crate::{
+    config::{WIFI_RECONNECT_BACKOFF_MAX_MS, WIFI_RECONNECT_BACKOFF_START_MS},
+    energy::{self, Phase},
+};
 
 /// Static cell for network stack resources
 static STACK_RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
@@ -17,6 +22,15 @@ static STACK_RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
 /// Signal to request to stop WiFi
 pub static WIFI_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
 
+/// The network stack once it has an IP address
+static STACK: OnceLock<Stack<'static>> = OnceLock::new();
+
+/// The connected network stack, for work at the end of the wake cycle that
+/// needs the network however the cycle ended.
+pub fn stack() -> Option<Stack<'static>> {
+    STACK.try_get().copied()
+}
+
 pub async fn connect_to_wifi(
     wifi: peripherals::WIFI<'static>,
     seed: u64,
@@ -58,10 +72,12 @@ pub async fn connect_to_wifi(
         Timer::after(Duration::from_millis(500)).await;
     }
 
+    energy::enter(Phase::Dhcp);
     info!("Wait for IP address");
     loop {
         if let Some(config) = stack.config_v4() {
             info!("Connected to WiFi with IP address {}", config.address);
+            let _ = STACK.init(stack);
             break;
         }
         Timer::after(Duration::from_millis(500)).await;
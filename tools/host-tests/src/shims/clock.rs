@@ -0,0 +1,12 @@
+//! Host stand-in for `src/clock.rs`: the time since start-up and no wall
+//! clock, as on a device that hasn't synced over SNTP.
+
+use embassy_time::Instant;
+
+pub fn now_us() -> u64 {
+    Instant::now().as_micros()
+}
+
+pub fn local_day() -> Option<u64> {
+    None
+}
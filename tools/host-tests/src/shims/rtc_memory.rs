@@ -0,0 +1,49 @@
+//! Host stand-in for `src/rtc_memory.rs`: the same `RtcCell` interface
+//! without the ESP32's RTC memory.
+//!
+//! Each test thread sees its own copy of every cell, starting from the
+//! value it was declared with, so tests running in parallel don't share
+//! the state they keep across simulated wakes.
+
+use std::{any::Any, cell::RefCell, collections::HashMap};
+
+std::thread_local! {
+    /// The values set on this thread, keyed by the address of their cell
+    static VALUES: RefCell<HashMap<usize, Box<dyn Any>>> = RefCell::new(HashMap::new());
+}
+
+pub struct RtcCell<T> {
+    initial: T,
+}
+
+impl<T> RtcCell<T> {
+    pub const fn new(value: T) -> Self {
+        Self { initial: value }
+    }
+
+    fn key(&self) -> usize {
+        core::ptr::from_ref(self) as usize
+    }
+
+    pub fn get(&self) -> T
+    where
+        T: Copy + 'static,
+    {
+        VALUES.with_borrow(|values| {
+            values
+                .get(&self.key())
+                .and_then(|value| value.downcast_ref::<T>())
+                .copied()
+                .unwrap_or(self.initial)
+        })
+    }
+
+    pub fn set(&self, value: T)
+    where
+        T: 'static,
+    {
+        VALUES.with_borrow_mut(|values| {
+            values.insert(self.key(), Box::new(value));
+        });
+    }
+}
@@ -0,0 +1,137 @@
+//! Host-side signing tool for OTA firmware images
+//!
+//! ```sh
+//! ota-sign keygen ota-signing.key
+//! ota-sign sign ota-signing.key firmware.bin 0.2.0 http://192.168.1.10:8000/firmware.bin > manifest.json
+//! ```
+//!
+//! `keygen` writes a new Ed25519 secret key and prints the public key for
+//! `OTA_PUBLIC_KEY` in `.env`. `sign` prints the manifest the device fetches
+//! from `OTA_MANIFEST_URL`. The signature covers the image's SHA-256 digest
+//! followed by the version, exactly as `ota::verify::signed_message` on the
+//! device.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::Path,
+    process::ExitCode,
+};
+
+use ed25519_dalek::{Signer, SigningKey};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+const USAGE: &str = "usage:
+  ota-sign keygen <key-file>
+  ota-sign sign <key-file> <image.bin> <version> <image-url>";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["keygen", key_file] => keygen(Path::new(key_file)),
+        ["sign", key_file, image, version, url] => {
+            sign(Path::new(key_file), Path::new(image), version, url)
+        }
+        _ => Err(USAGE.to_string()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Generate a signing key. Never overwrites an existing key: devices in the
+/// field only accept images signed with the key they were built with.
+/// The file is created readable by the owner only.
+fn keygen(key_file: &Path) -> Result<(), String> {
+    let mut seed = [0u8; 32];
+    getrandom::fill(&mut seed).map_err(|e| format!("No randomness available: {e}"))?;
+    let key = SigningKey::from_bytes(&seed);
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(key_file).map_err(|e| match e.kind() {
+        ErrorKind::AlreadyExists => format!("{} already exists", key_file.display()),
+        _ => format!("Failed to create {}: {e}", key_file.display()),
+    })?;
+    writeln!(file, "{}", to_hex(&seed))
+        .map_err(|e| format!("Failed to write {}: {e}", key_file.display()))?;
+    println!("OTA_PUBLIC_KEY={}", to_hex(key.verifying_key().as_bytes()));
+    eprintln!(
+        "Secret key written to {}; keep it out of the repository.",
+        key_file.display()
+    );
+    Ok(())
+}
+
+fn sign(key_file: &Path, image: &Path, version: &str, url: &str) -> Result<(), String> {
+    let key = read_key(key_file)?;
+    let image = fs::read(image).map_err(|e| format!("Failed to read {}: {e}", image.display()))?;
+    if !url.starts_with("http://") {
+        return Err("The device only fetches http:// URLs".to_string());
+    }
+    if !is_version(version) {
+        return Err(format!(
+            "{version} is no major.minor.patch version; the device only installs newer versions"
+        ));
+    }
+
+    let digest: [u8; 32] = Sha256::digest(&image).into();
+    let signature = key.sign(&signed_message(&digest, version));
+
+    let manifest = json!({
+        "version": version,
+        "url": url,
+        "size": image.len(),
+        "sha256": to_hex(&digest),
+        "signature": to_hex(&signature.to_bytes()),
+    });
+    println!("{manifest:#}");
+    Ok(())
+}
+
+/// `major.minor.patch`, optionally with a pre-release and build metadata,
+/// as `ota::is_newer` in the firmware compares them.
+fn is_version(version: &str) -> bool {
+    let version = version
+        .split_once('+')
+        .map_or(version, |(version, _)| version);
+    let core = version.split_once('-').map_or(version, |(core, _)| core);
+    let numbers: Vec<&str> = core.split('.').collect();
+    numbers.len() == 3 && numbers.iter().all(|n| n.parse::<u64>().is_ok())
+}
+
+/// Must match `ota::verify::signed_message` in the firmware.
+fn signed_message(digest: &[u8; 32], version: &str) -> Vec<u8> {
+    [digest.as_slice(), version.as_bytes()].concat()
+}
+
+fn read_key(key_file: &Path) -> Result<SigningKey, String> {
+    let hex = fs::read_to_string(key_file)
+        .map_err(|e| format!("Failed to read {}: {e}", key_file.display()))?;
+    let seed = from_hex(hex.trim())
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .ok_or_else(|| format!("{} is not a hex-encoded 32-byte key", key_file.display()))?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}